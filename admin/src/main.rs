@@ -1,30 +1,361 @@
 //! The API interface is responsebile to response the request from users. It save the data to DB,
 //! and talk to other components such as engine and reconciller to fulfill the request.
 //!
-use actix_web::{post, delete, Result, web, App, middleware, HttpServer, HttpResponse, http::StatusCode};
+use actix_web::{get, post, patch, put, delete, Result, web, App, middleware, HttpServer, HttpRequest, HttpResponse, http::StatusCode};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::Deserialize;
 
-use train_lib::bo::initialize_db_pool;
+use train_lib::bo::{AccountOps, AuditOps, OutboxOps, QuotaOps, TeamOps, TokenOps, UserOps, audit::{self, AuditQuery, Resource}, quota::QuotaRequest, team::{TeamRequest, TeamUpdateRequest}, token::IssuedToken, user::{MemberRequest, MemberUpdateRequest, UserRequest}, ConnectionPool, initialize_db_pool};
+use train_lib::{crypto, error, executor, oidc, queue};
+use diesel::PgConnection;
+use train_lib::scheduler::Executable;
+use std::sync::Arc;
 
+#[derive(Debug, Default, Deserialize)]
+struct RotateQuery {
+    /// The seconds the current token stays valid after the rotation.
+    grace_sec: Option<i64>
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    cascade: bool
+}
+
+/// Create the team. The token of the team is returned only once.
+/// All the admin endpoints require the admin token `TRAIN_ADMIN_TOKEN`, or the token of a global
+/// admin, as the bearer token.
 #[post("/api/v1/team")]
-async fn team_create(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn team_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<TeamRequest>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let data = data.into_inner();
+        let (_, token) = AuditOps::run(&mut conn, &actor, "team.create", &Resource::Team(data.name.clone()), &request_id(&req), |conn| {
+            TeamOps::create(conn, data.name.clone(), data.desp)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(IssuedToken { name: data.name, token }))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Rename the team or update its description.
+#[patch("/api/v1/team/{team_id}")]
+async fn team_update(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>, data: web::Json<TeamUpdateRequest>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "team.update", &Resource::Team(team_id.to_string()), &request_id(&req), |conn| {
+            TeamOps::update(conn, team_id.into_inner(), data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
-#[post("/api/v1/team/{team_id}")]
-async fn team_update(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+/// Issue a new token to the team, which is returned only once. With `grace_sec`, the current
+/// token stays valid for the seconds, otherwise it is revoked at once.
+#[put("/api/v1/team/{team_id}/token")]
+async fn team_rotate(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>, query: web::Query<RotateQuery>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let name = team_id.into_inner();
+        let token = AuditOps::run(&mut conn, &actor, "team.rotate", &Resource::Team(name.clone()), &request_id(&req), |conn| {
+            TeamOps::rotate_token(conn, name.clone(), query.grace_sec)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(IssuedToken { name, token }))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Delete the team. It is refused while the team owns any artifacts, secrets or account pools,
+/// unless `?cascade=true` is given to delete them as well.
 #[delete("/api/v1/team/{team_id}")]
-async fn team_delete(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn team_delete(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>, query: web::Query<DeleteQuery>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "team.delete", &Resource::Team(team_id.to_string()), &request_id(&req), |conn| {
+            TeamOps::delete_by_name(conn, team_id.into_inner(), query.cascade)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Set the quota of the team, such as `{"maxArtifacts": 10, "maxTotal": 40, "maxRunning": 8,
+/// "maxBorrowed": 20}`. The absent limits are unlimited. The requests over the quota are refused
+/// with 403, telling the usage against the limit.
+#[put("/api/v1/team/{team_id}/quota")]
+async fn quota_set(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>, data: web::Json<QuotaRequest>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "team.quota", &Resource::Team(team_id.to_string()), &request_id(&req), |conn| {
+            QuotaOps::set(conn, &team_id, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Show the quota of the team along with the artifacts and the summed `total` it uses.
+#[get("/api/v1/team/{team_id}/quota")]
+async fn quota_show(auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let info = QuotaOps::show(&mut conn, &team_id)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(info))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Create the user. The user acts in the teams it is added to, with the token of each membership.
+#[post("/api/v1/user")]
+async fn user_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<UserRequest>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "user.create", &Resource::User(data.name.clone()), &request_id(&req), |conn| {
+            UserOps::create(conn, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Delete the user along with its memberships.
+#[delete("/api/v1/user/{user_id}")]
+async fn user_delete(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, user_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "user.delete", &Resource::User(user_id.to_string()), &request_id(&req), |conn| {
+            UserOps::delete(conn, &user_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+#[get("/api/v1/team/{team_id}/member")]
+async fn member_list(auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let members = UserOps::list_members(&mut conn, &team_id)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(members))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Add the user to the team with the role owner, member or viewer. The token of the user in the
+/// team is returned only once.
+#[post("/api/v1/team/{team_id}/member")]
+async fn member_add(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, team_id: web::Path<String>, data: web::Json<MemberRequest>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let resource = Resource::Member(team_id.to_string(), data.user.clone());
+        let issued = AuditOps::run(&mut conn, &actor, "member.add", &resource, &request_id(&req), |conn| {
+            UserOps::add_member(conn, &team_id, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(issued))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+#[patch("/api/v1/team/{team_id}/member/{user_id}")]
+async fn member_update(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, path: web::Path<(String, String)>, data: web::Json<MemberUpdateRequest>) -> Result<HttpResponse> {
+    let (team_id, user_id) = path.into_inner();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "member.update", &Resource::Member(team_id.clone(), user_id.clone()), &request_id(&req), |conn| {
+            UserOps::update_member(conn, &team_id, &user_id, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+#[delete("/api/v1/team/{team_id}/member/{user_id}")]
+async fn member_remove(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, path: web::Path<(String, String)>) -> Result<HttpResponse> {
+    let (team_id, user_id) = path.into_inner();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        AuditOps::run(&mut conn, &actor, "member.remove", &Resource::Member(team_id.clone(), user_id.clone()), &request_id(&req), |conn| {
+            UserOps::remove_member(conn, &team_id, &user_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// List the account units quarantined after the failed clean runs.
+#[get("/api/v1/acnt/quarantine")]
+async fn quarantine_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let units = AccountOps::list_quarantined(&mut conn)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(units))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Inspect the quarantined unit, including its data.
+#[get("/api/v1/acnt/quarantine/{unit_id}")]
+async fn quarantine_show(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, unit_id: web::Path<i32>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let unit_id = unit_id.into_inner();
+        let unit = AuditOps::run(&mut conn, &actor, "unit.read", &Resource::Unit(unit_id), &request_id(&req), |conn| {
+            AccountOps::inspect(conn, unit_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(unit))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Force-release the quarantined unit back to the stock. The artifacts pending on the account
+/// are resumed.
+#[put("/api/v1/acnt/quarantine/{unit_id}/release")]
+async fn quarantine_release(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, unit_id: web::Path<i32>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let unit_id = unit_id.into_inner();
+        AuditOps::run(&mut conn, &actor, "unit.release", &Resource::Unit(unit_id), &request_id(&req), |conn| {
+            AccountOps::release(conn, unit_id)
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Run the scrub pipeline of the account against the quarantined unit. The name of the pipeline
+/// run is returned.
+#[post("/api/v1/acnt/quarantine/{unit_id}/scrub")]
+async fn quarantine_scrub(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, executor: web::Data<dyn Executable>, unit_id: web::Path<i32>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let unit_id = unit_id.into_inner();
+        let run_name = AuditOps::run(&mut conn, &actor, "unit.scrub", &Resource::Unit(unit_id), &request_id(&req), |conn| {
+            AccountOps::scrub(conn, executor.get_ref(), unit_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).body(run_name))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// List the ids of the artifacts in the dead-letter list of the scheduler queue, which failed
+/// too many times in a row, the oldest first.
+#[get("/api/v1/queue/dead")]
+async fn dead_letter_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let mut redis_conn = queue::connection()?;
+        let dead = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned()).dead_letters(&mut redis_conn)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(dead))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Move the dead-lettered artifact back to the scheduler queue with fresh attempts.
+/// Return 404 if the artifact is not dead-lettered.
+#[post("/api/v1/queue/dead/{art_id}/replay")]
+async fn dead_letter_replay(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let art_id = art_id.into_inner();
+        let mut redis_conn = queue::connection()?;
+        let queue = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+        if !queue.dead_letters(&mut redis_conn)?.contains(&art_id) {
+            return Ok(HttpResponse::build(StatusCode::NOT_FOUND).body(format!("The artifact {} is not dead-lettered", art_id)));
+        }
+        AuditOps::run(&mut conn, &actor, "queue.replay", &Resource::DeadLetter(art_id.clone()), &request_id(&req), |conn| {
+            OutboxOps::notify(conn, parse_art_id(&art_id)?, "replay")
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Move all the dead-lettered artifacts back to the scheduler queue, the ids replayed are
+/// returned.
+#[post("/api/v1/queue/dead/replay")]
+async fn dead_letter_replay_all(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let mut redis_conn = queue::connection()?;
+        let replayed = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned()).dead_letters(&mut redis_conn)?;
+        AuditOps::run(&mut conn, &actor, "queue.replay", &Resource::DeadLetter("*".to_owned()), &request_id(&req), |conn| {
+            for art_id in &replayed {
+                OutboxOps::notify(conn, parse_art_id(art_id)?, "replay")?;
+            }
+            Ok(())
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).json(replayed))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Query the audit log, such as `?resource=secret/db-pass&since=2024-05-01T00:00:00`. The records
+/// are returned from the newest, up to `limit`.
+#[get("/api/v1/audit")]
+async fn audit_query(auth: BearerAuth, pool: web::Data<ConnectionPool>, query: web::Query<AuditQuery>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize_admin(&mut conn, auth.token())?;
+        let records = AuditOps::query(&mut conn, query.into_inner())?;
+        Ok(HttpResponse::build(StatusCode::OK).json(records))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// The dead letters are the ids of the artifacts.
+fn parse_art_id(art_id: &str) -> error::Result<i32> {
+    art_id.parse().map_err(|_| error::bad_request(&format!("Invalid artifact id: {}", art_id)))
+}
+
+/// Move the notifications committed to the outbox to the scheduler queue. They stay in the outbox
+/// for the scheduler to drain if the redis server is unavailable now.
+fn notify_scheduler(conn: &mut PgConnection) {
+    let queue = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    let drained = queue::connection().and_then(|mut redis_conn| OutboxOps::drain(conn, &mut redis_conn, &queue));
+    if let Err(e) = drained {
+        log::warn!("WARN: failed to notify scheduler, it will drain the outbox later. error: {}", e);
+    }
+}
+
+/// The request id recorded in the audit log, taken from the header `X-Request-Id`.
+fn request_id(req: &HttpRequest) -> String {
+    audit::request_id(req.headers().get("X-Request-Id").and_then(|v| v.to_str().ok()))
 }
 
 #[actix_web::main]
 pub async fn main() -> std::io::Result<()>{
     env_logger::init();    
     log::info!("Starting API service at 3200");
+    crypto::check_key().expect("Failed to load the encryption key");
     let pool = initialize_db_pool();
+    if let Some(verifier) = oidc::Verifier::from_env().expect("Failed to load the OIDC config") {
+        oidc::install(verifier);
+    }
+    let executor: Arc<dyn Executable> = Arc::from(executor::from_env().expect("Failed to open the executor"));
 
     HttpServer::new(move || {
        // let cors = actix_cors::Cors::default()
@@ -33,9 +364,28 @@ pub async fn main() -> std::io::Result<()>{
         App::new()
        //     .wrap(cors)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(executor.clone()))
             .wrap(middleware::Logger::default())
             .service(team_create)
             .service(team_update)
+            .service(team_rotate)
+            .service(team_delete)
+            .service(quota_set)
+            .service(quota_show)
+            .service(user_create)
+            .service(user_delete)
+            .service(member_list)
+            .service(member_add)
+            .service(member_update)
+            .service(member_remove)
+            .service(quarantine_list)
+            .service(quarantine_show)
+            .service(quarantine_release)
+            .service(quarantine_scrub)
+            .service(dead_letter_list)
+            .service(dead_letter_replay)
+            .service(dead_letter_replay_all)
+            .service(audit_query)
     })
     .bind(("0.0.0.0", 3201))?
     .run()
@@ -1,56 +1,64 @@
 //! The API interface is responsebile to response the request from users. It save the data to DB,
 //! and talk to other components such as engine and reconciller to fulfill the request.
 //!
-use actix_web::{get, post, patch, put, delete, Result, web, App, middleware, HttpServer, HttpResponse, http::StatusCode};
+use actix_web::{get, post, patch, put, delete, Result, web, App, middleware, HttpServer, HttpRequest, HttpResponse, http::StatusCode};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 
-use train_lib::bo::{ArtifactOps,artifact::ArtifactRequest, ConnectionPool, initialize_db_pool};
-use train_lib::scheduler::{Executable, DefaultExecutor};
+use train_lib::bo::{AccountOps, ArtifactOps, AuditOps, CredentialOps, OutboxOps, SecretOps, TokenOps, account::{AccountRequest, AccountUpdateRequest}, artifact::ArtifactRequest, audit::{self, Resource}, secret::{SecretRequest, SecretVersionRequest}, token::{Scope, TokenRequest}, ConnectionPool, initialize_db_pool};
+use diesel::PgConnection;
+use train_lib::{crypto, oidc, queue};
 
 /// Create the artifact.
 /// User need to have the bearer token in the header. if the token does not match the token, the
 /// request will be rejected.
-/// We may consider to issue tokens with lifespan to avoid the leakage of the team token. Or the
-/// team tokens have to be renewed in the given time.
+/// Besides the team token, the api tokens with the scope `art:write` are accepted. Each route
+/// requires the scope of its operation, see `Scope`.
 /// For the `ArtifactRequest`, please see the sample json file under `asset` folder.
-/// Return 200 if the `ArtifactRequest` is accepted. A notice message will be sent to `engine` to
-/// schedule the artifact.
+/// Return 200 if the `ArtifactRequest` is accepted. The artifact is queued to the scheduler.
 /// Return 400 if the artifact is malformed.
 ///
 #[post("/api/v1/art")]
-async fn art_create(auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<ArtifactRequest>) -> Result<HttpResponse> {
+async fn art_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<ArtifactRequest>) -> Result<HttpResponse> {
     // Validate the request
     let token = auth.token();
     if let Ok(mut conn) = pool.get() {
-        let art_id = ArtifactOps::create(&mut conn, token, data.into_inner())?;
-        // notify the engine that the new art is ready.
-        let client = awc::Client::new();
-        let res = client.post(format!("http://scheduler.train.svc.cluster.local/api/v1/sched/{art_id}")).send().await;
-        match res {
-            Ok(r) => {
-                if r.status() != awc::http::StatusCode::OK {
-                    log::warn!("WARN: failed to notify scheduler");
-                }
-            },
-            Err(e) => {
-                log::warn!("WARN: failed to notify scheduler. error: {}", e);
-            }
-        }
-
+        let actor = TokenOps::authorize(&mut conn, token, Scope::ArtWrite)?;
+        let resource = Resource::Artifact(data.name.clone());
+        AuditOps::run(&mut conn, &actor, "art.create", &resource, &request_id(&req), |conn| {
+            ArtifactOps::create(conn, token, data.into_inner())
+        })?;
+        notify_scheduler(&mut conn);
         Ok(HttpResponse::build(StatusCode::OK).into())
     } else {
         Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
     }
 }
 
+/// Move the notifications committed to the outbox to the scheduler queue. They stay in the outbox
+/// for the scheduler to drain if the redis server is unavailable now.
+fn notify_scheduler(conn: &mut PgConnection) {
+    let queue = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    let drained = queue::connection().and_then(|mut redis_conn| OutboxOps::drain(conn, &mut redis_conn, &queue));
+    if let Err(e) = drained {
+        log::warn!("WARN: failed to notify scheduler, it will drain the outbox later. error: {}", e);
+    }
+}
+
 /// Update the artifact
+/// The secrets can be pinned to a version with the `version` field of the secret reference, or
+/// follow the latest version if the field is absent.
 ///
 #[patch("/api/v1/art/{art_id}")]
-async fn art_update(auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<ArtifactRequest>) -> Result<HttpResponse> {
+async fn art_update(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<ArtifactRequest>) -> Result<HttpResponse> {
     // Validate the request
     let token = auth.token();
     if let Ok(mut conn) = pool.get() {
-        ArtifactOps::update(&mut conn, token, data.into_inner())?;
+        let actor = TokenOps::authorize(&mut conn, token, Scope::ArtWrite)?;
+        let resource = Resource::Artifact(data.name.clone());
+        AuditOps::run(&mut conn, &actor, "art.update", &resource, &request_id(&req), |conn| {
+            ArtifactOps::update(conn, token, data.into_inner())
+        })?;
+        notify_scheduler(&mut conn);
         Ok(HttpResponse::build(StatusCode::OK).into())
     } else {
         Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
@@ -59,93 +67,361 @@ async fn art_update(auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web
 
 
 #[get("/api/v1/art")]
-async fn art_list() -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(""))
+async fn art_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::ArtRead)?;
+        Ok(HttpResponse::build(StatusCode::OK).body(""))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Show the artifact and its status. If the artifact is `PendingAccount`, the `blockedBy` is the
+/// account pool it is waiting for.
 #[get("/api/v1/art/{art_id}")]
-async fn art_show(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_show(auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, token, Scope::ArtRead)?;
+        let info = ArtifactOps::show(&mut conn, token, art_id.into_inner())?;
+        Ok(HttpResponse::build(StatusCode::OK).json(info))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
+/// Not implemented yet: the instances of the artifact have to be cleaned before it is deleted.
+/// Nothing is audited since nothing is done.
 #[delete("/api/v1/art/{art_id}")]
-async fn art_delete(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_delete(auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::ArtWrite)?;
+        Ok(HttpResponse::build(StatusCode::NOT_IMPLEMENTED).body(format!("Deleting the artifact {} is not supported yet", art_id)))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Return the credentials generated for the instance of the artifact, such as the admin
+/// password. Each instance has its own credentials.
+#[get("/api/v1/art/{art_id}/inst/{inst_id}/cred")]
+async fn art_instance_cred(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, path: web::Path<(String, String)>) -> Result<HttpResponse> {
+    let token = auth.token();
+    let (art_id, inst_id) = path.into_inner();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::ArtBorrow)?;
+        let resource = Resource::Credential(art_id.clone(), inst_id.clone());
+        let creds = AuditOps::run(&mut conn, &actor, "cred.read", &resource, &request_id(&req), |conn| {
+            CredentialOps::show(conn, token, art_id, &inst_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(creds))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
 #[put("/api/v1/art/{art_id}/borrow")]
-async fn art_borrow(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_borrow(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, auth.token(), Scope::ArtBorrow)?;
+        let mut redis_conn = queue::connection()?;
+        AuditOps::run(&mut conn, &actor, "art.borrow", &Resource::Artifact(art_id.to_string()), &request_id(&req), |conn| {
+            ArtifactOps::borrow(conn, &mut redis_conn, auth.token(), art_id.to_string())
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
 #[put("/api/v1/art/{art_id}/return")]
-async fn art_return(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_return(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, auth.token(), Scope::ArtBorrow)?;
+        AuditOps::run(&mut conn, &actor, "art.return", &Resource::Artifact(art_id.to_string()), &request_id(&req), |conn| {
+            ArtifactOps::notify(conn, auth.token(), art_id.to_string(), "return")
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+/// Take the artifact out of `Maintenance` after its builds failed too many times in a row. The
+/// builds are scheduled again.
+#[put("/api/v1/art/{art_id}/ack")]
+async fn art_acknowledge(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, auth.token(), Scope::ArtWrite)?;
+        AuditOps::run(&mut conn, &actor, "art.ack", &Resource::Artifact(art_id.to_string()), &request_id(&req), |conn| {
+            ArtifactOps::acknowledge(conn, auth.token(), art_id.to_string())
+        })?;
+        notify_scheduler(&mut conn);
+        Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
+
+/// Not implemented yet, nothing is audited since nothing is done.
 #[put("/api/v1/art/{art_id}/pause")]
-async fn art_pause(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_pause(auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::ArtWrite)?;
+        Ok(HttpResponse::build(StatusCode::NOT_IMPLEMENTED).body(format!("Pausing the artifact {} is not supported yet", art_id)))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Not implemented yet, nothing is audited since nothing is done.
 #[put("/api/v1/art/{art_id}/resume")]
-async fn art_resume(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn art_resume(auth: BearerAuth, pool: web::Data<ConnectionPool>, art_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::ArtWrite)?;
+        Ok(HttpResponse::build(StatusCode::NOT_IMPLEMENTED).body(format!("Resuming the artifact {} is not supported yet", art_id)))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Create the secret owned by the team of the bearer token.
+/// The data is saved as the version 1 of the secret.
 #[post("/api/v1/sec")]
-async fn secret_create(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn secret_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<SecretRequest>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecAdmin)?;
+        let resource = Resource::Secret(data.name.clone());
+        AuditOps::run(&mut conn, &actor, "sec.create", &resource, &request_id(&req), |conn| {
+            SecretOps::create(conn, token, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Rotate the secret by writing a new version.
+/// The artifacts following the latest version of the secret are sent to the scheduler, which
+/// re-applies their Kubernetes Secret objects before the next run.
 #[patch("/api/v1/sec/{sec_id}")]
-async fn secret_update(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn secret_update(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, sec_id: web::Path<String>, data: web::Json<SecretVersionRequest>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecAdmin)?;
+        let affected = AuditOps::run(&mut conn, &actor, "sec.rotate", &Resource::Secret(sec_id.to_string()), &request_id(&req), |conn| {
+            SecretOps::rotate(conn, token, &sec_id, data.into_inner().data)
+        })?;
+        if !affected.is_empty() {
+            notify_scheduler(&mut conn);
+        }
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Show the secret and its versions. The values of the secret are not returned.
 #[get("/api/v1/sec/{sec_id}")]
-async fn secret_show(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn secret_show(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, sec_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecRead)?;
+        let info = AuditOps::run(&mut conn, &actor, "sec.read", &Resource::Secret(sec_id.to_string()), &request_id(&req), |conn| {
+            SecretOps::show(conn, token, &sec_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(info))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// List the instances built with the secret, and the version each of them was built with.
+#[get("/api/v1/sec/{sec_id}/usage")]
+async fn secret_usage(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, sec_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecRead)?;
+        let usage = AuditOps::run(&mut conn, &actor, "sec.usage", &Resource::Secret(sec_id.to_string()), &request_id(&req), |conn| {
+            SecretOps::usage(conn, token, &sec_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(usage))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
 #[get("/api/v1/sec")]
-async fn secret_list(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn secret_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::SecRead)?;
+        Ok(HttpResponse::build(StatusCode::OK).body(""))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Not implemented yet, nothing is audited since nothing is done.
 #[delete("/api/v1/sec/{sec_id}")]
-async fn secret_delete(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn secret_delete(auth: BearerAuth, pool: web::Data<ConnectionPool>, sec_id: web::Path<String>) -> Result<HttpResponse> {
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, auth.token(), Scope::SecAdmin)?;
+        Ok(HttpResponse::build(StatusCode::NOT_IMPLEMENTED).body(format!("Deleting the secret {} is not supported yet", sec_id)))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
 
+/// Create the account pool owned by the team of the bearer token.
+/// Each of the `units` is an account which is checked out by one instance at a time.
 #[post("/api/v1/acnt")]
-async fn account_create(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn account_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<AccountRequest>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecAdmin)?;
+        let resource = Resource::Account(data.name.clone());
+        AuditOps::run(&mut conn, &actor, "acnt.create", &resource, &request_id(&req), |conn| {
+            AccountOps::create(conn, token, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Update the data of the account pool, add units or remove the available units.
 #[patch("/api/v1/acnt/{acnt_id}")]
-async fn account_update(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn account_update(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, acnt_id: web::Path<String>, data: web::Json<AccountUpdateRequest>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecAdmin)?;
+        AuditOps::run(&mut conn, &actor, "acnt.update", &Resource::Account(acnt_id.to_string()), &request_id(&req), |conn| {
+            AccountOps::update(conn, token, &acnt_id, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Show the account pool and the status of its units. The data of the units are not returned.
 #[get("/api/v1/acnt/{acnt_id}")]
-async fn account_show(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn account_show(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, acnt_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecRead)?;
+        let info = AuditOps::run(&mut conn, &actor, "acnt.read", &Resource::Account(acnt_id.to_string()), &request_id(&req), |conn| {
+            AccountOps::show(conn, token, &acnt_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(info))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// List the check-outs of the units of the account pool.
+#[get("/api/v1/acnt/{acnt_id}/usage")]
+async fn account_usage(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, acnt_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecRead)?;
+        let usage = AuditOps::run(&mut conn, &actor, "acnt.usage", &Resource::Account(acnt_id.to_string()), &request_id(&req), |conn| {
+            AccountOps::usage(conn, token, &acnt_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(usage))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
 #[get("/api/v1/acnt")]
-async fn account_list(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn account_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        TokenOps::authorize(&mut conn, token, Scope::SecRead)?;
+        let accounts = AccountOps::list(&mut conn, token)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(accounts))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
 
+/// Delete the account pool. It is refused while any of the units is checked out.
 #[delete("/api/v1/acnt/{acnt_id}")]
-async fn account_delete(art_id: web::Path<String>) -> Result<HttpResponse> {
-    Ok(HttpResponse::build(StatusCode::OK).body(art_id.into_inner()))
+async fn account_delete(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, acnt_id: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::SecAdmin)?;
+        AuditOps::run(&mut conn, &actor, "acnt.delete", &Resource::Account(acnt_id.to_string()), &request_id(&req), |conn| {
+            AccountOps::delete(conn, token, &acnt_id)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
 }
+
+/// Issue an api token limited to the scopes, such as a borrow-only token for the CI. Only the
+/// team token can issue the api tokens. The token is returned only once.
+#[post("/api/v1/token")]
+async fn token_create(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, data: web::Json<TokenRequest>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::ArtRead)?;
+        let resource = Resource::Token(data.name.clone());
+        let issued = AuditOps::run(&mut conn, &actor, "token.create", &resource, &request_id(&req), |conn| {
+            TokenOps::create(conn, token, data.into_inner())
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).json(issued))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// List the api tokens of the team with their scopes, expiry and last used time.
+#[get("/api/v1/token")]
+async fn token_list(auth: BearerAuth, pool: web::Data<ConnectionPool>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let tokens = TokenOps::list(&mut conn, token)?;
+        Ok(HttpResponse::build(StatusCode::OK).json(tokens))
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// Revoke the api token. It is refused from then on.
+#[delete("/api/v1/token/{token_name}")]
+async fn token_revoke(req: HttpRequest, auth: BearerAuth, pool: web::Data<ConnectionPool>, token_name: web::Path<String>) -> Result<HttpResponse> {
+    let token = auth.token();
+    if let Ok(mut conn) = pool.get() {
+        let actor = TokenOps::authorize(&mut conn, token, Scope::ArtRead)?;
+        AuditOps::run(&mut conn, &actor, "token.revoke", &Resource::Token(token_name.to_string()), &request_id(&req), |conn| {
+            TokenOps::revoke(conn, token, &token_name)
+        })?;
+        Ok(HttpResponse::build(StatusCode::OK).into())
+    } else {
+        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+    }
+}
+
+/// The request id recorded in the audit log, taken from the header `X-Request-Id` so the records
+/// can be joined with the logs of the proxy.
+fn request_id(req: &HttpRequest) -> String {
+    audit::request_id(req.headers().get("X-Request-Id").and_then(|v| v.to_str().ok()))
+}
+
 #[actix_web::main]
 pub async fn main() -> std::io::Result<()>{
     env_logger::init();    
     log::info!("Starting API service at 3200");
+    crypto::check_key().expect("Failed to load the encryption key");
     let pool = initialize_db_pool();
+    if let Some(verifier) = oidc::Verifier::from_env().expect("Failed to load the OIDC config") {
+        oidc::install(verifier);
+    }
 
     HttpServer::new(move || {
        // let cors = actix_cors::Cors::default()
@@ -161,19 +437,26 @@ pub async fn main() -> std::io::Result<()>{
             .service(art_update)
             .service(art_delete)
             .service(art_borrow)
+            .service(art_instance_cred)
             .service(art_return)
+            .service(art_acknowledge)
             .service(art_pause)
             .service(art_resume)
             .service(secret_list)
             .service(secret_show)
+            .service(secret_usage)
             .service(secret_create)
             .service(secret_update)
             .service(secret_delete)
             .service(account_list)
             .service(account_show)
+            .service(account_usage)
             .service(account_create)
             .service(account_update)
             .service(account_delete)
+            .service(token_create)
+            .service(token_list)
+            .service(token_revoke)
     })
     .bind(("0.0.0.0", 3200))?
     .run()
@@ -188,14 +471,18 @@ mod tests {
     use super::*;
     use actix_web::test;
 
-    fn init_test() {
+    /// Return the bearer token of the admin team. The token of the existing team is rotated since
+    /// only its hash is saved.
+    fn init_test() -> String {
         let mut conn = get_connection();
-        conn.transaction(|conn| {
+        let token = conn.transaction(|conn| {
             if TeamOps::find_team_by_name_for_update(conn, "admin".to_owned()).is_err() {
-                TeamOps::create(conn, String::from("admin"), None);
-            };
-            Result::<_, error::GeneralError>::Ok(())
+                TeamOps::create(conn, String::from("admin"), None).map(|(_, token)| token)
+            } else {
+                TeamOps::rotate_token(conn, String::from("admin"), None)
+            }
         }).unwrap();
+        format!("Bearer {}", token)
     }
 
     fn clean_test() {
@@ -209,7 +496,7 @@ mod tests {
     #[actix_web::test]
     async fn test_create_artifact() {
         dotenvy::dotenv().ok();
-        init_test();
+        let bearer = init_test();
         env_logger::try_init_from_env(env_logger::Env::new().default_filter_or("info")).ok();
 
         let pool = initialize_db_pool();
@@ -227,7 +514,7 @@ mod tests {
 
         let mut json_data: ArtifactRequest = serde_json::from_reader(file).expect("Fail to parse the json ArtifactRequest");
         json_data.name = String::from("test-create-artifact");
-        let req = test::TestRequest::post().uri("/api/v1/art").set_json(&json_data).insert_header(("Authorization", "Bearer 123456")).to_request();
+        let req = test::TestRequest::post().uri("/api/v1/art").set_json(&json_data).insert_header(("Authorization", bearer.as_str())).to_request();
         let res = test::call_service(&app, req).await;
         println!("response: {:?}", res.response());
         assert_eq!(res.status(), StatusCode::OK);
@@ -237,7 +524,7 @@ mod tests {
     #[actix_web::test]
     async fn test_create_artifact_invalid_json() {
         dotenvy::dotenv().ok();
-        init_test();
+        let bearer = init_test();
         env_logger::try_init_from_env(env_logger::Env::new().default_filter_or("info")).ok();
 
         let pool = initialize_db_pool();
@@ -255,12 +542,33 @@ mod tests {
 
         // let json_data: ArtifactRequest = serde_json::from_reader(file).expect("Fail to parse the json ArtifactRequest");
         let json_data = serde_json::json!({ "name": "test-bad-artifact", "total": "1", "target":"1" });
-        let req = test::TestRequest::post().uri("/api/v1/art").set_json(&json_data).insert_header(("Authorization", "Bearer 123456")).to_request();
+        let req = test::TestRequest::post().uri("/api/v1/art").set_json(&json_data).insert_header(("Authorization", bearer.as_str())).to_request();
         let res = test::call_service(&app, req).await;
         println!("response: {:?}", res);
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
         clean_test();
     }
+
+    #[actix_web::test]
+    async fn test_create_secret_invalid_path() {
+        dotenvy::dotenv().ok();
+        let bearer = init_test();
+        let pool = initialize_db_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(secret_create)
+        )
+        .await;
+
+        // The validation errors are told to the client, unlike the internal ones.
+        let json_data = serde_json::json!({ "name": "test-bad-path", "backend": "file", "path": "../other" });
+        let req = test::TestRequest::post().uri("/api/v1/sec").set_json(&json_data).insert_header(("Authorization", bearer.as_str())).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = test::read_body(res).await;
+        assert_eq!(String::from_utf8_lossy(&body), "BadRequest: Invalid secret path: ../other");
+    }
 }
 /*
 fn apis() {
@@ -0,0 +1,37 @@
+//! The reconciller polls the runs of the instances from the executor, records their statuses and
+//! results, and queues the artifacts whose instances are changed to the scheduler.
+use train_lib::{crypto, error, executor, scheduler};
+use train_lib::scheduler::Executable;
+use train_lib::queue::{self, Queue};
+
+use train_lib::bo::{ConnectionPool, initialize_db_pool};
+
+/// The seconds between two polls of the runs, unless the env `TRAIN_RECONCILE_INTERVAL_SEC` is
+/// set.
+const POLL_INTERVAL_SEC: u64 = 10;
+
+/// Sync the instances of all the artifacts once.
+fn poll(pool: &ConnectionPool, executor: &dyn Executable, queue: &Queue, worker: &str) -> error::Result<usize> {
+    let mut conn = pool.get().map_err(|err| error::error(&format!("Out of database bandwith: {}", err)))?;
+    let mut redis_conn = queue::connection()?;
+    scheduler::sync_all(&mut conn, &mut redis_conn, executor, queue, worker)
+}
+
+pub fn main() {
+    env_logger::init();
+    let interval = std::env::var("TRAIN_RECONCILE_INTERVAL_SEC").ok().and_then(|sec| sec.parse().ok()).unwrap_or(POLL_INTERVAL_SEC);
+    let worker = queue::worker_id();
+    log::info!("Starting reconciller {} polling every {}s", worker, interval);
+    crypto::check_key().expect("Failed to load the encryption key");
+    let pool = initialize_db_pool();
+    let executor = executor::from_env().expect("Failed to open the executor");
+    let queue = Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    loop {
+        match poll(&pool, executor.as_ref(), &queue, &worker) {
+            Ok(changed) if changed > 0 => log::info!("synced the instances of {} artifacts", changed),
+            Ok(_) => {},
+            Err(err) => log::warn!("failed to poll the runs, error: {}", err)
+        }
+        std::thread::sleep(std::time::Duration::from_secs(interval));
+    }
+}
@@ -1,89 +1,167 @@
-use train_lib::error;
+use train_lib::{crypto, error, executor, scheduler};
+use train_lib::scheduler::Executable;
+use std::sync::Arc;
+use train_lib::queue::{self, Queue, DelayedQueue};
 
-use actix_web::{get, post, patch, put, delete, Result, web, App, middleware, HttpServer, HttpResponse, http::StatusCode};
+use actix_web::{post, Result, web, App, middleware, HttpServer, HttpResponse, http::StatusCode};
 
-use train_lib::bo::{ArtifactOps,artifact::ArtifactRequest, ConnectionPool, initialize_db_pool};
-
-//TODO: Think about the timing that enqueue the artifact 
-// 1. api call after the artifact creation.
-// 2. api call after the target number or the total number was changed.
-// 3. After the deploy fail. (enter maintainance once a artifact has more than 5 failed). Which may
-//    require another crate `worker`, and which sych the status of each instance.
-// 4. api call after the artifact delete.
+use train_lib::bo::{ArtifactOps, ConnectionPool, OutboxOps, initialize_db_pool};
+use train_lib::bo::instance::InstanceNumbers;
 
 #[post("/api/v1/sched/{art_id}")]
-async fn art_sched(pool: web::Data<ConnectionPool>, art_id: web::Path<i32>) -> Result<HttpResponse> {
+async fn art_sched(pool: web::Data<ConnectionPool>, executor: web::Data<dyn Executable>, art_id: web::Path<i32>) -> Result<HttpResponse> {
     if let Ok(mut conn) = pool.get() {
-        //TODO: schedule the artifact
-        let artifact = ArtifactOps::load_by_id(&mut conn, art_id.into_inner()).unwrap();
+        let art_id = art_id.into_inner();
+        let artifact = ArtifactOps::load_by_id(&mut conn, art_id)?;
         log::info!("received schedule request for art: {}", artifact.name);
+        // Re-apply the secrets, the artifact may be scheduled because one of its secrets is rotated.
+        if let Err(err) = ArtifactOps::apply_secrets(&mut conn, executor.get_ref(), art_id) {
+            log::warn!("failed to apply the secrets of art: {}, error: {}", artifact.name, err);
+        }
+        // The scheduler loop drains the outbox to the queue, as with the notifications of the API.
+        OutboxOps::notify(&mut conn, art_id, "sched")?;
         Ok(HttpResponse::build(StatusCode::OK).into())
     } else {
         Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
     }
 }
 
+/// The outcome of a poll, see `art_poll`.
+enum Polled {
+    Numbers(InstanceNumbers),
+    /// Another worker holds the lease of the artifact.
+    Busy(String),
+    /// The database pool is exhausted.
+    NoConnection
+}
+
+/// Sync the statuses of the instances of the artifact from the executor right away, and return the
+/// numbers of its instances by status. The sync blocks on the database, redis and the executor, so
+/// it runs on the blocking thread pool.
 #[post("/api/v1/poll/{art_id}")]
-async fn art_poll(pool: web::Data<ConnectionPool>, art_id: web::Path<i32>) -> Result<HttpResponse> {
-    if let Ok(mut conn) = pool.get() {
-        //TODO: schedule the artifact
-        let artifact = ArtifactOps::load_by_id(&mut conn, art_id.into_inner()).unwrap();
+async fn art_poll(pool: web::Data<ConnectionPool>, executor: web::Data<dyn Executable>, worker: web::Data<String>, art_id: web::Path<i32>) -> Result<HttpResponse> {
+    let art_id = art_id.into_inner();
+    let executor = executor.into_inner();
+    let worker = worker.into_inner();
+    let polled = web::block(move || -> error::Result<Polled> {
+        let mut conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(_) => return Ok(Polled::NoConnection)
+        };
+        let artifact = ArtifactOps::load_by_id(&mut conn, art_id)?;
         log::info!("received poll request for art: {}", artifact.name);
-        Ok(HttpResponse::build(StatusCode::OK).into())
-    } else {
-        Ok(HttpResponse::build(StatusCode::BAD_REQUEST).body("Out of database bandwith"))
+        let mut redis_conn = queue::connection()?;
+        let queue = Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+        Ok(match scheduler::poll(&mut conn, &mut redis_conn, executor.as_ref(), &queue, &worker, art_id)? {
+            Some(numbers) => Polled::Numbers(numbers),
+            None => Polled::Busy(artifact.name)
+        })
+    }).await??;
+    match polled {
+        Polled::Numbers(numbers) => Ok(HttpResponse::build(StatusCode::OK).json(numbers)),
+        Polled::Busy(name) => Ok(HttpResponse::build(StatusCode::CONFLICT).body(format!("The artifact {} is being reconciled, try again later", name))),
+        Polled::NoConnection => Ok(HttpResponse::build(StatusCode::SERVICE_UNAVAILABLE).body("Out of database bandwith"))
+    }
+}
+
+/// The seconds between two moves of the due artifacts from the delayed queue to the queue. It is
+/// also the longest time the loop blocks on the queue.
+const PROMOTE_INTERVAL_SEC: u64 = 5;
+
+/// The milliseconds between two heartbeats of the worker, the other workers wait for
+/// `queue::HEARTBEAT_TTL_MS` before they take over its artifacts.
+const HEARTBEAT_INTERVAL_MS: u64 = 2_000;
+
+/// Run the scheduler loop for good. It reconnects after the connections are lost.
+fn background(pool: ConnectionPool, executor: Arc<dyn Executable>, worker: String) {
+    let queue = Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    let delayed = DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    loop {
+        if let Err(err) = serve(&pool, executor.as_ref(), &queue, &delayed, &worker) {
+            log::warn!("the scheduler loop is interrupted, error: {}", err);
+            std::thread::sleep(std::time::Duration::from_secs(PROMOTE_INTERVAL_SEC));
+        }
+    }
+}
+
+/// Reconcile the queued artifacts one by one, and move the due artifacts from the delayed queue,
+/// and those of the legacy queue, in between. The artifacts of the workers gone are taken over. The failure of an artifact is
+/// logged and the loop goes on, only the failures of the connections stop it.
+fn serve(pool: &ConnectionPool, executor: &dyn Executable, queue: &Queue, delayed: &DelayedQueue, worker: &str) -> error::Result<()> {
+    let mut redis_conn = queue::connection()?;
+    let recovered = queue.recover(worker, &mut redis_conn)?;
+    if recovered > 0 {
+        log::info!("recovered {} artifacts left by the worker {}", recovered, worker);
+    }
+    loop {
+        queue.migrate(queue::LEGACY_QUEUE_NAME, &mut redis_conn)?;
+        delayed.promote_due(queue, &mut redis_conn)?;
+        queue.redeliver_expired(chrono::Utc::now().timestamp(), &mut redis_conn)?;
+        queue.redeliver_orphaned(&mut redis_conn)?;
+        let mut conn = pool.get().map_err(|err| error::error(&format!("Out of database bandwith: {}", err)))?;
+        OutboxOps::drain(&mut conn, &mut redis_conn, queue)?;
+        match scheduler::process(&mut conn, &mut redis_conn, executor, queue, worker, PROMOTE_INTERVAL_SEC as usize) {
+            Ok(started) if !started.is_empty() => log::info!("started the instances: {:?}", started),
+            Ok(_) => {},
+            Err(error::GeneralError::RedisError(err)) => return Err(err.into()),
+            Err(err) => log::warn!("failed to process the artifact, error: {}", err)
+        }
     }
 }
 
-async fn background() -> error::Result<()> {
-    //TODO:: loop to get the job done
-    Ok(())
+/// Send the heartbeats of the worker for good, on a connection of its own so a long
+/// reconciliation does not hold them up.
+fn heartbeat(worker: String) {
+    let queue = Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    loop {
+        if let Err(err) = beat(&queue, &worker) {
+            log::warn!("the heartbeat of the worker {} is interrupted, error: {}", worker, err);
+            std::thread::sleep(std::time::Duration::from_millis(HEARTBEAT_INTERVAL_MS));
+        }
+    }
+}
+
+fn beat(queue: &Queue, worker: &str) -> error::Result<()> {
+    let mut redis_conn = queue::connection()?;
+    loop {
+        queue.heartbeat(worker, queue::HEARTBEAT_TTL_MS, &mut redis_conn)?;
+        std::thread::sleep(std::time::Duration::from_millis(HEARTBEAT_INTERVAL_MS));
+    }
 }
 
 #[actix_web::main]
 pub async fn main() -> std::io::Result<()>{
     env_logger::init();    
-    log::info!("Starting internal scheduler service at 3201");
+    let port = std::env::var("TRAIN_SCHEDULER_PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(3202);
+    let worker = queue::worker_id();
+    log::info!("Starting internal scheduler service {} at {}", worker, port);
+    crypto::check_key().expect("Failed to load the encryption key");
     let pool = initialize_db_pool();
+    let executor: Arc<dyn Executable> = Arc::from(executor::from_env().expect("Failed to open the executor"));
 
-    actix_rt::spawn(background());
+    // The first heartbeat is sent before any artifact is reserved, so the worker is never taken
+    // for gone in between.
+    queue::connection()
+        .and_then(|mut redis_conn| Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned()).heartbeat(&worker, queue::HEARTBEAT_TTL_MS, &mut redis_conn))
+        .expect("Failed to send the first heartbeat");
+    let heartbeat_worker = worker.clone();
+    std::thread::spawn(move || heartbeat(heartbeat_worker));
+    let background_pool = pool.clone();
+    let background_executor = executor.clone();
+    let background_worker = worker.clone();
+    std::thread::spawn(move || background(background_pool, background_executor, background_worker));
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(executor.clone()))
+            .app_data(web::Data::new(worker.clone()))
             .wrap(middleware::Logger::default())
             .service(art_sched)
             .service(art_poll)
     })
-    .bind(("0.0.0.0", 3202))?
+    .bind(("0.0.0.0", port))?
     .run()
     .await
 }
 
-/*
-fn main() {
-    env_logger::init();
-    //TODO:
-    // 1. scheduler should read the redis list (from the head), if there is not item read, pending.
-    // 2. the api should push the new artifact to the end of the list.
-    log::info!("start scheduler");
-    loop {
-        if let Err(err) = scheduler::process(&queue) {
-            log::warn!("Fail to dequeue artifact with the error: {}", err);
-        }
-
-        // Once a item(the artifact record id) is read
-        // Read the hashset 'artifact:{record_id}:total' to total
-        // Read the hashset 'artifact:{record_id}:target' to target
-        // Read the hashset 'artifact:{art_id}:instance' to get the instance list
-        // Read the hashset 'artifact:{art_id}:instance:{inst_id}' to get the instance info.
-        // Iterate each of the instances of 'artifact:{art_id}:instance:{inst_id}' of 'artifact:{art_id}:instance', get the status of these instances
-        // Calculate the numbers of instances that is under 'succ'
-        // The number to be deploy:
-        //  buff = total - ready - in_proc - fail, need = target - ready - in_proc
-        // to_deploy = min(buff, need)
-        // Deploy '{to_deploy}' copies to tekton by calling rollout method of 'Artifact'
-
-    }
-}
-*/
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS sec_usage;
+DROP TABLE IF EXISTS sec_version;
+ALTER TABLE secret DROP COLUMN IF EXISTS version;
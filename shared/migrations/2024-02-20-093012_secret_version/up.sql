@@ -0,0 +1,24 @@
+ALTER TABLE secret ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE sec_version (
+  id SERIAL PRIMARY KEY,
+  secret_id INTEGER NOT NULL,
+  version INTEGER NOT NULL,
+  data TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(secret_id, version),
+  CONSTRAINT fk_secret FOREIGN KEY(secret_id) REFERENCES secret(id) ON DELETE CASCADE
+);
+
+INSERT INTO sec_version (secret_id, version, data) SELECT id, version, data FROM secret;
+
+CREATE TABLE sec_usage (
+  id SERIAL PRIMARY KEY,
+  artifact_id INTEGER NOT NULL,
+  inst_id TEXT NOT NULL,
+  secret_id INTEGER NOT NULL,
+  version INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT fk_artifact FOREIGN KEY(artifact_id) REFERENCES artifact(id) ON DELETE CASCADE,
+  CONSTRAINT fk_secret FOREIGN KEY(secret_id) REFERENCES secret(id) ON DELETE CASCADE
+);
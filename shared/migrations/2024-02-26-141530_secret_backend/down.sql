@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE secret DROP COLUMN IF EXISTS path;
+ALTER TABLE secret DROP COLUMN IF EXISTS backend;
@@ -0,0 +1,2 @@
+ALTER TABLE secret ADD COLUMN backend TEXT NOT NULL DEFAULT 'postgres';
+ALTER TABLE secret ADD COLUMN path TEXT;
@@ -0,0 +1,10 @@
+CREATE TABLE inst_cred (
+  id SERIAL PRIMARY KEY,
+  artifact_id INTEGER NOT NULL,
+  inst_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  data TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(artifact_id, inst_id, name),
+  CONSTRAINT fk_artifact FOREIGN KEY(artifact_id) REFERENCES artifact(id) ON DELETE CASCADE
+);
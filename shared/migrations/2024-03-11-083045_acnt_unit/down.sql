@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS acnt_usage;
+DROP TABLE IF EXISTS acnt_unit;
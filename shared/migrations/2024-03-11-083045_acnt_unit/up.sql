@@ -0,0 +1,20 @@
+CREATE TABLE acnt_unit (
+  id SERIAL PRIMARY KEY,
+  account_id INTEGER NOT NULL,
+  data TEXT NOT NULL,
+  stat TEXT NOT NULL DEFAULT 'Available',
+  CONSTRAINT fk_account FOREIGN KEY(account_id) REFERENCES account(id) ON DELETE CASCADE
+);
+
+CREATE TABLE acnt_usage (
+  id SERIAL PRIMARY KEY,
+  unit_id INTEGER NOT NULL,
+  account_id INTEGER NOT NULL,
+  artifact_id INTEGER NOT NULL,
+  inst_id TEXT NOT NULL,
+  checked_out_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  checked_in_at TIMESTAMP,
+  CONSTRAINT fk_unit FOREIGN KEY(unit_id) REFERENCES acnt_unit(id) ON DELETE CASCADE,
+  CONSTRAINT fk_account FOREIGN KEY(account_id) REFERENCES account(id) ON DELETE CASCADE,
+  CONSTRAINT fk_artifact FOREIGN KEY(artifact_id) REFERENCES artifact(id) ON DELETE CASCADE
+);
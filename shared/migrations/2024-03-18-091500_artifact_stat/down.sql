@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE artifact DROP COLUMN IF EXISTS blocked_by;
+ALTER TABLE artifact DROP COLUMN IF EXISTS stat;
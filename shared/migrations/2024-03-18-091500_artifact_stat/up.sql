@@ -0,0 +1,2 @@
+ALTER TABLE artifact ADD COLUMN stat TEXT NOT NULL DEFAULT 'NotScheduled';
+ALTER TABLE artifact ADD COLUMN blocked_by TEXT;
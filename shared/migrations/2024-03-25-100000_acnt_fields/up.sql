@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN fields JSON;
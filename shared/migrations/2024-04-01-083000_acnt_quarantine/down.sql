@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE account DROP COLUMN IF EXISTS scrub;
+ALTER TABLE acnt_unit DROP COLUMN IF EXISTS reason;
+ALTER TABLE acnt_unit DROP COLUMN IF EXISTS quarantined_at;
@@ -0,0 +1,3 @@
+ALTER TABLE acnt_unit ADD COLUMN quarantined_at TIMESTAMP;
+ALTER TABLE acnt_unit ADD COLUMN reason TEXT;
+ALTER TABLE account ADD COLUMN scrub JSON;
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+-- The plaintext tokens can not be restored, the tokens have to be rotated after the rollback.
+DROP INDEX IF EXISTS team_prev_token_prefix;
+DROP INDEX IF EXISTS team_token_prefix;
+ALTER TABLE team DROP COLUMN IF EXISTS prev_expires_at;
+ALTER TABLE team DROP COLUMN IF EXISTS prev_salt;
+ALTER TABLE team DROP COLUMN IF EXISTS prev_token_prefix;
+ALTER TABLE team DROP COLUMN IF EXISTS prev_token_hash;
+ALTER TABLE team DROP COLUMN IF EXISTS salt;
+ALTER TABLE team DROP COLUMN IF EXISTS token_prefix;
+ALTER TABLE team RENAME COLUMN token_hash TO token;
@@ -0,0 +1,17 @@
+ALTER TABLE team RENAME COLUMN token TO token_hash;
+ALTER TABLE team ADD COLUMN token_prefix TEXT;
+ALTER TABLE team ADD COLUMN salt TEXT NOT NULL DEFAULT '';
+ALTER TABLE team ADD COLUMN prev_token_hash TEXT;
+ALTER TABLE team ADD COLUMN prev_token_prefix TEXT;
+ALTER TABLE team ADD COLUMN prev_salt TEXT;
+ALTER TABLE team ADD COLUMN prev_expires_at TIMESTAMP;
+
+-- Hash the plaintext tokens in the same way as `crypto::hash_token`. The prefix is the head of the
+-- token as `crypto::token_prefix` does.
+UPDATE team SET salt = md5(random()::text), token_prefix = left(token_hash, 12);
+UPDATE team SET token_hash = encode(sha256(convert_to(salt || token_hash, 'UTF8')), 'hex');
+
+ALTER TABLE team ALTER COLUMN token_prefix SET NOT NULL;
+ALTER TABLE team ALTER COLUMN salt DROP DEFAULT;
+CREATE UNIQUE INDEX team_token_prefix ON team(token_prefix);
+CREATE INDEX team_prev_token_prefix ON team(prev_token_prefix);
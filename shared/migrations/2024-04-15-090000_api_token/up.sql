@@ -0,0 +1,18 @@
+-- The named tokens of the teams, each of them is limited to its scopes.
+CREATE TABLE api_token (
+  id SERIAL PRIMARY KEY,
+  team_id INT4 NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL,
+  token_prefix TEXT NOT NULL,
+  salt TEXT NOT NULL,
+  scopes JSON NOT NULL,
+  expires_at TIMESTAMP,
+  last_used_at TIMESTAMP,
+  revoked_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX api_token_prefix ON api_token(token_prefix);
+-- The name of a revoked token can be reused.
+CREATE UNIQUE INDEX api_token_team_name ON api_token(team_id, name) WHERE revoked_at IS NULL;
@@ -0,0 +1,23 @@
+-- The users acting inside the teams. The global admins can do everything.
+CREATE TABLE users (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  admin BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The role of the user in the team. Each membership has its own token, which acts for the user in
+-- the team.
+CREATE TABLE membership (
+  id SERIAL PRIMARY KEY,
+  user_id INT4 NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  team_id INT4 NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+  role TEXT NOT NULL,
+  token_hash TEXT NOT NULL,
+  token_prefix TEXT NOT NULL,
+  salt TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (user_id, team_id)
+);
+
+CREATE UNIQUE INDEX membership_token_prefix ON membership(token_prefix);
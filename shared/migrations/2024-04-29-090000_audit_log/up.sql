@@ -0,0 +1,25 @@
+-- The append-only audit log of the mutating operations and the reads of the secrets.
+CREATE TABLE audit_log (
+  id BIGSERIAL PRIMARY KEY,
+  actor TEXT NOT NULL,
+  actor_kind TEXT NOT NULL,
+  team TEXT,
+  action TEXT NOT NULL,
+  resource TEXT NOT NULL,
+  before_digest TEXT,
+  after_digest TEXT,
+  request_id TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_resource ON audit_log(resource, created_at);
+CREATE INDEX audit_log_actor ON audit_log(actor, created_at);
+
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+  RAISE EXCEPTION 'The audit log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
@@ -0,0 +1,8 @@
+-- The quota of a team. A null limit is unlimited, and a team without a row has no limits.
+CREATE TABLE quota (
+  team_id INT PRIMARY KEY REFERENCES team(id) ON DELETE CASCADE,
+  max_artifacts INT,
+  max_total INT,
+  max_running INT,
+  max_borrowed INT
+);
@@ -0,0 +1,8 @@
+-- The notifications to the scheduler written in the transaction of the change, and drained into
+-- the redis queue after the commit.
+CREATE TABLE sched_outbox (
+  id BIGSERIAL PRIMARY KEY,
+  artifact_id INT NOT NULL,
+  reason TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
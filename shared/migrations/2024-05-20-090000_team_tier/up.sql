@@ -0,0 +1,2 @@
+-- The tier of the team raises the priority of its artifacts in the scheduler queue.
+ALTER TABLE team ADD COLUMN tier INT NOT NULL DEFAULT 0;
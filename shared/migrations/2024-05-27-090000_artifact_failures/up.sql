@@ -0,0 +1,2 @@
+-- The consecutive build failures of the artifact, it goes into maintenance after too many.
+ALTER TABLE artifact ADD COLUMN failures INT NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- The highest fencing token of the scheduler leases on the artifact, the writes with a lower one
+-- are refused.
+ALTER TABLE artifact ADD COLUMN fence BIGINT NOT NULL DEFAULT 0;
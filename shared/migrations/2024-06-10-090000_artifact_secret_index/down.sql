@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX artifact_clean_refs;
+DROP INDEX artifact_build_refs;
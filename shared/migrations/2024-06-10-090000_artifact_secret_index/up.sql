@@ -0,0 +1,4 @@
+-- Find the artifacts referring to a secret by its name without scanning the table, see
+-- `ArtifactDao::list_by_secret`.
+CREATE INDEX artifact_build_refs ON artifact USING GIN ((build::jsonb) jsonb_path_ops);
+CREATE INDEX artifact_clean_refs ON artifact USING GIN ((clean::jsonb) jsonb_path_ops);
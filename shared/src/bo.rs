@@ -1,9 +1,31 @@
 pub mod artifact;
-mod manifest;
+pub mod audit;
+pub mod secret;
+pub mod account;
+pub mod credential;
+pub mod instance;
+pub mod pipeline;
+pub mod quota;
+pub mod team;
+pub mod token;
+pub mod user;
+pub mod manifest;
+pub(crate) mod naming;
 pub(crate) mod dao;
-use crate::error;
+use crate::{crypto, error, lease, oidc, queue};
+use crate::lease::Lease;
 use diesel::{Connection, PgConnection};
-use artifact::{ArtifactRequest, Rollout};
+use std::collections::{BTreeMap, HashMap};
+use artifact::{AccountRef, ArtifactInfo, ArtifactRequest, ArtifactStatus, DeployUnit, Rollout, SecretRef};
+use crate::redact::Redactor;
+use crate::scheduler::Executable;
+use account::{AccountField, AccountRequest, AccountUpdateRequest, AccountInfo, AccountUnitInfo, AccountUsageInfo, CheckedOutUnit, QuarantinedUnitInfo, UnitStatus};
+use secret::{backend, SecretRequest, SecretInfo, SecretVersionInfo, SecretUsageInfo, SecretValue};
+use quota::{QuotaInfo, QuotaRequest, QuotaUsage, RunUsage};
+use team::{TeamOwnership, TeamUpdateRequest};
+use audit::{AuditQuery, AuditRecord, Resource};
+use token::{Actor, IssuedToken, Scope, TokenInfo, TokenRequest};
+use user::{MemberInfo, MemberRequest, MemberUpdateRequest, Role, UserRequest};
 
 pub use dao::{initialize_db_pool, ConnectionPool};
 
@@ -15,6 +37,11 @@ use artifact::{Validable, ArtifactValidator};
 pub struct ArtifactOps;
 
 impl ArtifactOps {
+    /// The most priority the waiting borrowers add to the artifact.
+    pub const MAX_BORROWER_BOOST: i64 = 3;
+    /// The priority added to the artifact without any ready instance.
+    pub const EMPTY_POOL_BOOST: i64 = 2;
+
     /// Create the artifact object in database
     pub fn create(conn: &mut PgConnection, token: &str, mut req: ArtifactRequest) -> error::Result<i32> {
         let mut validator = ArtifactValidator{conn, artifact: &mut req};
@@ -34,11 +61,14 @@ impl ArtifactOps {
                 };
 
                 conn.transaction(|connection| {
-                    dao::ArtifactDao::create(connection, art)
+                    QuotaOps::admit(connection, &team.name, &art.name, art.total, true)?;
+                    let art_id = dao::ArtifactDao::create(connection, art)?;
+                    OutboxOps::notify(connection, art_id, "create")?;
+                    Ok(art_id)
                 })
             },
             None => {
-                Err(error::error("The team token does not match any team"))
+                Err(error::GeneralError::Unauthorized)
             }
         }
     }
@@ -47,29 +77,29 @@ impl ArtifactOps {
         let mut validator = ArtifactValidator{conn, artifact: &mut req};
         validator.validate()?;
         req.format()?;
-        let team = dao::TeamDao::find_team_by_token(conn, token)?;
-        match team.id {
-            Some(team_id) => {
-                let art = dao::model::Artifact {
-                    id: None,
-                    name: req.name,
-                    total: req.total,
-                    target: req.target,
-                    team_id,
-                    build: serde_json::to_value(req.build)?,
-                    clean: Some(serde_json::to_value(req.clean)?)
-                };
-
-                // TODO: notify the engine that the new art is ready.
-                // TODO: save the create script and destroy script
-                conn.transaction(|connection| {
-                    dao::ArtifactDao::update(connection, art)
-                })
-            },
-            None => {
-                Err(error::error("The team token does not match any team"))
+        let caller = Caller::of_token(conn, token)?;
+        conn.transaction(|connection| {
+            let existing = dao::ArtifactDao::load_by_name(connection, req.name.clone())?;
+            if !caller.owns(Some(existing.team_id)) {
+                return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", existing.name, caller.team.name)));
             }
-        }
+            // The artifact stays with its team when an admin updates it.
+            let team = dao::TeamDao::find_team_by_id(connection, existing.team_id)?;
+            let art = dao::model::Artifact {
+                id: existing.id,
+                name: req.name,
+                total: req.total,
+                target: req.target,
+                team_id: existing.team_id,
+                build: serde_json::to_value(req.build)?,
+                clean: Some(serde_json::to_value(req.clean)?)
+            };
+            let art_id = existing.id.ok_or_else(|| error::error("Null artifact id"))?;
+            let reason = if existing.target != art.target { "target" } else { "update" };
+            QuotaOps::admit(connection, &team.name, &art.name, art.total, false)?;
+            dao::ArtifactDao::update(connection, art)?;
+            OutboxOps::notify(connection, art_id, reason)
+        })
     }
 
     pub fn update_build_script(_conn: &mut PgConnection, mut req: Rollout) -> error::Result<()> {
@@ -89,6 +119,49 @@ impl ArtifactOps {
         Ok(())
     }
 
+    /// The priority of the artifact in the scheduler queue. The tier of the team counts, and the
+    /// demand raises it: the borrowers waiting for the instances, up to `MAX_BORROWER_BOOST`, and
+    /// `EMPTY_POOL_BOOST` more if no instance is ready while the target is not zero. A deleted
+    /// artifact has no priority.
+    pub fn priority(conn: &mut PgConnection, redis_conn: &mut dyn redis::ConnectionLike, art_id: i32, borrowers: i64) -> error::Result<i64> {
+        let art = match dao::ArtifactDao::load_by_id(conn, art_id) {
+            Ok(art) => art,
+            Err(error::GeneralError::DBError(diesel::result::Error::NotFound)) => return Ok(0),
+            Err(err) => return Err(err)
+        };
+        let team = dao::TeamDao::find_team_by_id(conn, art.team_id)?;
+        let instances = dao::InstanceDao::many(&art_id.to_string(), redis_conn)?;
+        let ready = instances.iter().filter(|inst| inst.is_ready()).count();
+        let mut priority = i64::from(team.tier) + borrowers.min(Self::MAX_BORROWER_BOOST);
+        if art.target > 0 && ready == 0 {
+            priority += Self::EMPTY_POOL_BOOST;
+        }
+        Ok(priority.min(queue::MAX_PRIORITY))
+    }
+
+    /// Notify the scheduler of the change of the artifact owned by the team of the token, such as
+    /// the borrow or the return of an instance.
+    pub fn notify(conn: &mut PgConnection, token: &str, name: String, reason: &str) -> error::Result<()> {
+        let caller = Caller::of_token(conn, token)?;
+        let art = dao::ArtifactDao::load_by_name(conn, name)?;
+        if !caller.owns(Some(art.team_id)) {
+            return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", art.name, caller.team.name)));
+        }
+        OutboxOps::notify(conn, art.id.ok_or_else(|| error::error("Null artifact id"))?, reason)
+    }
+
+    /// Borrow an instance of the artifact owned by the team of the token, refused once the team
+    /// holds its max borrowed instances.
+    pub fn borrow(conn: &mut PgConnection, redis_conn: &mut dyn redis::ConnectionLike, token: &str, name: String) -> error::Result<()> {
+        let caller = Caller::of_token(conn, token)?;
+        let art = dao::ArtifactDao::load_by_name(conn, name)?;
+        if !caller.owns(Some(art.team_id)) {
+            return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", art.name, caller.team.name)));
+        }
+        QuotaOps::check_borrow(conn, redis_conn, art.team_id)?;
+        OutboxOps::notify(conn, art.id.ok_or_else(|| error::error("Null artifact id"))?, "borrow")
+    }
+
     pub fn load_by_id(conn: &mut PgConnection, id: i32) -> error::Result<model::Artifact> {
         dao::ArtifactDao::load_by_id(conn, id)
     }
@@ -97,6 +170,169 @@ impl ArtifactOps {
         dao::ArtifactDao::load_by_name(conn, name)
     }
 
+    /// Show the artifact owned by the team of the token, including the account pool blocking it.
+    pub fn show(conn: &mut PgConnection, token: &str, name: String) -> error::Result<ArtifactInfo> {
+        let caller = Caller::of_token(conn, token)?;
+        let art = dao::ArtifactDao::load_by_name(conn, name)?;
+        if !caller.owns(Some(art.team_id)) {
+            return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", art.name, caller.team.name)));
+        }
+        let art_id = art.id.ok_or_else(|| error::error("Null artifact id"))?;
+        let (stat, blocked_by) = dao::ArtifactDao::load_stat(conn, art_id)?;
+        Ok(ArtifactInfo {
+            name: art.name,
+            total: art.total,
+            target: art.target,
+            stat,
+            blocked_by,
+            failures: dao::ArtifactDao::load_failures(conn, art_id)?
+        })
+    }
+
+    /// Take the artifact owned by the team of the token out of `Maintenance`, once its owner has
+    /// looked into the failed builds. The failures are reset and the artifact is queued again.
+    pub fn acknowledge(conn: &mut PgConnection, token: &str, name: String) -> error::Result<()> {
+        let caller = Caller::of_token(conn, token)?;
+        let art = dao::ArtifactDao::load_by_name(conn, name)?;
+        if !caller.owns(Some(art.team_id)) {
+            return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", art.name, caller.team.name)));
+        }
+        let art_id = art.id.ok_or_else(|| error::error("Null artifact id"))?;
+        conn.transaction(|connection| {
+            let (stat, _) = dao::ArtifactDao::load_stat(connection, art_id)?;
+            if ArtifactStatus::from(&stat) != ArtifactStatus::Maintenance {
+                return Err(error::conflict(&format!("The artifact {} is not in maintenance", art.name)));
+            }
+            dao::ArtifactDao::reset_failures(connection, art_id)?;
+            dao::ArtifactDao::update_stat(connection, art_id, &ArtifactStatus::NotScheduled.to_string(), None)?;
+            OutboxOps::notify(connection, art_id, "ack")
+        })
+    }
+
+    /// Check out the accounts referred by the artifact for the instance. If any of the pools is
+    /// exhausted, the artifact goes into `PendingAccount`, blocked by the pool, and the
+    /// `GeneralError::PendingAccount` is returned so the caller parks it.
+    pub fn check_out_accounts(conn: &mut PgConnection, id: i32, inst_id: &str) -> error::Result<Vec<CheckedOutUnit>> {
+        let art = dao::ArtifactDao::load_by_id(conn, id)?;
+        let refs = Self::account_refs(&art)?;
+        match AccountOps::check_out(conn, id, inst_id, &refs) {
+            Ok(units) => {
+                let (stat, _) = dao::ArtifactDao::load_stat(conn, id)?;
+                if ArtifactStatus::from(&stat) == ArtifactStatus::PendingAccount {
+                    dao::ArtifactDao::update_stat(conn, id, &ArtifactStatus::NotScheduled.to_string(), None)?;
+                }
+                Ok(units)
+            },
+            Err(error::GeneralError::PendingAccount(pool)) => {
+                log::info!("artifact {} is pending on the account {}", art.name, pool);
+                dao::ArtifactDao::update_stat(conn, id, &ArtifactStatus::PendingAccount.to_string(), Some(&pool))?;
+                Err(error::GeneralError::PendingAccount(pool))
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Resolve the secrets referenced by the artifact and apply them as Kubernetes Secret objects
+    /// named `sec-{artifact}-{secret}`. This picks up the rotated versions of the secrets.
+    pub fn apply_secrets(conn: &mut PgConnection, executor: &dyn Executable, id: i32) -> error::Result<Vec<SecretValue>> {
+        let art = dao::ArtifactDao::load_by_id(conn, id)?;
+        let mut values = Vec::new();
+        for sec_ref in Self::secret_refs(&art)? {
+            values.push(Self::resolve_as_system(conn, &art, &sec_ref)?);
+        }
+        let secrets: Vec<manifest::Secret> = values.iter().map(|v| {
+            let kvs = v.data.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            manifest::Secret::new(format!("sec-{}-{}", art.name, v.name), artifact::DEFAULT_NAMESPACE, kvs)
+        }).collect();
+        if !secrets.is_empty() {
+            Rollout::apply_secrets(executor, &secrets)?;
+        }
+        Ok(values)
+    }
+
+    /// The redactor masking the values of all the secrets and accounts attached to the artifact.
+    pub fn redactor(conn: &mut PgConnection, id: i32) -> error::Result<Redactor> {
+        let art = dao::ArtifactDao::load_by_id(conn, id)?;
+        let mut redactor = Redactor::default();
+        for sec_ref in Self::secret_refs(&art)? {
+            let value = Self::resolve_as_system(conn, &art, &sec_ref)?;
+            for v in value.data.values() {
+                redactor.add(v);
+            }
+        }
+        for acnt_ref in Self::account_refs(&art)? {
+            let acnt = dao::AccountDao::load_by_name(conn, &acnt_ref.name)?;
+            redactor.add_data(&acnt.data);
+            let fields = AccountOps::fields(&acnt)?;
+            for unit in dao::AccountDao::list_units(conn, acnt.id.unwrap_or_default())? {
+                match &fields {
+                    // Only the sensitive fields are masked, the others such as the project id
+                    // are kept to make the logs readable.
+                    Some(fields) => {
+                        let keys = account::secret_keys(Some(fields), &unit.data)?;
+                        for field in fields.iter().filter(|field| field.sensitive) {
+                            if let Some(value) = keys.get(&field.name) {
+                                redactor.add(value);
+                                // Mask the values inside the json key as well, such as the
+                                // private key of a GCP service account.
+                                if field.tpe == account::FieldType::Json {
+                                    redactor.add_data(value);
+                                }
+                            }
+                        }
+                    },
+                    None => redactor.add_data(&unit.data)
+                }
+            }
+        }
+        Ok(redactor)
+    }
+
+    /// Resolve the secret of the artifact for train itself, to apply it or to mask it. The read is
+    /// recorded in the audit log as the system, like the reads of the users.
+    fn resolve_as_system(conn: &mut PgConnection, art: &model::Artifact, sec_ref: &SecretRef) -> error::Result<SecretValue> {
+        AuditOps::run(conn, &Actor::System, "sec.resolve", &Resource::Secret(sec_ref.name.clone()), &audit::request_id(None), |conn| {
+            SecretOps::resolve(conn, art.team_id, sec_ref)
+        })
+    }
+
+    /// The logs of the pipeline run of the artifact, with the values of the secrets and accounts
+    /// masked.
+    pub fn logs(conn: &mut PgConnection, executor: &dyn Executable, id: i32, run_name: &str) -> error::Result<String> {
+        let redactor = Self::redactor(conn, id)?;
+        executor.logs(run_name, &redactor)
+    }
+
+    fn deploy_units(art: &model::Artifact) -> error::Result<Vec<DeployUnit>> {
+        let mut units: Vec<DeployUnit> = vec![serde_json::from_value(art.build.clone())?];
+        if let Some(clean) = &art.clean {
+            units.push(serde_json::from_value(clean.clone())?);
+        }
+        Ok(units)
+    }
+
+    fn account_refs(art: &model::Artifact) -> error::Result<Vec<AccountRef>> {
+        let mut refs: Vec<AccountRef> = Vec::new();
+        for acnt_ref in Self::deploy_units(art)?.into_iter().flat_map(|unit| unit.accounts.unwrap_or_default()) {
+            if !refs.iter().any(|r| r.name == acnt_ref.name) {
+                refs.push(acnt_ref);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// The secrets referenced by the build and clean of the artifact. A secret referenced by both
+    /// is returned once, and the reference of the build wins.
+    fn secret_refs(art: &model::Artifact) -> error::Result<Vec<SecretRef>> {
+        let mut refs: Vec<SecretRef> = Vec::new();
+        for sec_ref in Self::deploy_units(art)?.into_iter().flat_map(|unit| unit.secrets.unwrap_or_default()) {
+            if !refs.iter().any(|r| r.name == sec_ref.name) {
+                refs.push(sec_ref);
+            }
+        }
+        Ok(refs)
+    }
+
     pub fn deploy(_id: &str) -> error::Result<()> {
         Ok(())
     }
@@ -106,18 +342,597 @@ impl ArtifactOps {
     }
 }
 
-pub struct TeamOps;
-impl TeamOps {
-    pub fn create(conn: &mut PgConnection, team_name: String, desp: Option<String>) -> error::Result<i32> {
-        let token = Self::generate();
-        let team = dao::model::Team {
-            id: None,
-            name: team_name,
-            token,
-            desp
+pub struct SecretOps;
+
+impl SecretOps {
+    /// Create the secret owned by the team of the token. The data is saved as the version 1.
+    pub fn create(conn: &mut PgConnection, token: &str, req: SecretRequest) -> error::Result<i32> {
+        let team = dao::TeamDao::find_team_by_token(conn, token)?;
+        let kind = req.backend.unwrap_or_else(|| backend::POSTGRES.to_owned());
+        if !backend::is_known(&kind) {
+            return Err(error::bad_request(&format!("Unknown secret backend: {}", kind)));
+        }
+        match (kind.as_str(), &req.path) {
+            (backend::POSTGRES, Some(_)) => return Err(error::bad_request("The secret of the postgres backend takes no path")),
+            (backend::POSTGRES, None) => {},
+            (_, Some(path)) if req.data.is_empty() => backend::check_path(path)?,
+            _ => return Err(error::bad_request(&format!("The secret of the {} backend requires the path and no data", kind)))
+        }
+        let data = serde_json::to_string(&req.data)?;
+        conn.transaction(|connection| {
+            let sec_id = dao::SecretDao::create(connection, model::Secret {
+                id: None,
+                name: req.name,
+                data: data.clone(),
+                owner: team.id,
+                desp: req.desp,
+                version: 1,
+                backend: kind,
+                path: req.path
+            })?;
+            dao::SecretDao::create_version(connection, model::SecretVersion {
+                id: None,
+                secret_id: sec_id,
+                version: 1,
+                data,
+                created_at: None
+            })?;
+            Ok(sec_id)
+        })
+    }
+
+    /// Save the data as the next version of the secret.
+    /// Return the ids of the artifacts following the latest version of the secret. They have to
+    /// re-apply the secret before the next run.
+    pub fn rotate(conn: &mut PgConnection, token: &str, sec_name: &str, data: HashMap<String, String>) -> error::Result<Vec<i32>> {
+        let caller = Caller::of_token(conn, token)?;
+        let data = serde_json::to_string(&data)?;
+        conn.transaction(|connection| {
+            let sec = dao::SecretDao::load_by_name_for_update(connection, sec_name)?;
+            Self::check_owner(&sec, &caller)?;
+            if sec.backend != backend::POSTGRES {
+                return Err(error::bad_request(&format!("The secret {} is managed by the {} backend", sec_name, sec.backend)));
+            }
+            let sec_id = sec.id.ok_or_else(|| error::error("Null secret id"))?;
+            let version = dao::SecretDao::bump_version(connection, sec_id, &data)?;
+            dao::SecretDao::create_version(connection, model::SecretVersion {
+                id: None,
+                secret_id: sec_id,
+                version,
+                data,
+                created_at: None
+            })?;
+            log::info!("secret {} is rotated to the version {}", sec_name, version);
+            let affected = Self::artifacts_following_latest(connection, sec_name)?;
+            for art_id in &affected {
+                OutboxOps::notify(connection, *art_id, "secret")?;
+            }
+            Ok(affected)
+        })
+    }
+
+    pub fn show(conn: &mut PgConnection, token: &str, sec_name: &str) -> error::Result<SecretInfo> {
+        let caller = Caller::of_token(conn, token)?;
+        let sec = dao::SecretDao::load_by_name(conn, sec_name)?;
+        Self::check_owner(&sec, &caller)?;
+        let sec_id = sec.id.ok_or_else(|| error::error("Null secret id"))?;
+        let mut versions = Vec::new();
+        for ver in dao::SecretDao::list_versions(conn, sec_id)? {
+            let data: HashMap<String, String> = serde_json::from_str(&ver.data)?;
+            let mut keys: Vec<String> = data.into_keys().collect();
+            keys.sort();
+            versions.push(SecretVersionInfo {
+                version: ver.version,
+                keys,
+                created_at: ver.created_at
+            });
+        }
+        Ok(SecretInfo {
+            name: sec.name,
+            version: sec.version,
+            desp: sec.desp,
+            backend: sec.backend,
+            path: sec.path,
+            versions
+        })
+    }
+
+    /// List the instances built with the secret, and the version each of them was built with.
+    pub fn usage(conn: &mut PgConnection, token: &str, sec_name: &str) -> error::Result<Vec<SecretUsageInfo>> {
+        let caller = Caller::of_token(conn, token)?;
+        let sec = dao::SecretDao::load_by_name(conn, sec_name)?;
+        Self::check_owner(&sec, &caller)?;
+        let sec_id = sec.id.ok_or_else(|| error::error("Null secret id"))?;
+        let mut result = Vec::new();
+        for usage in dao::SecretDao::list_usage(conn, sec_id)? {
+            let art = dao::ArtifactDao::load_by_id(conn, usage.artifact_id)?;
+            result.push(SecretUsageInfo {
+                artifact: art.name,
+                inst_id: usage.inst_id,
+                version: usage.version,
+                created_at: usage.created_at
+            });
+        }
+        Ok(result)
+    }
+
+    /// Resolve the reference to the pinned version of the secret, or to the latest version if it
+    /// is not pinned. The secret must be owned by the team. The value is read from the backend of
+    /// the secret, the paths of the external backends are under the name of the team.
+    pub fn resolve(conn: &mut PgConnection, team_id: i32, sec_ref: &SecretRef) -> error::Result<SecretValue> {
+        let sec = dao::SecretDao::load_by_name(conn, &sec_ref.name)?;
+        // The artifact is held to the secrets of its team, whoever runs it.
+        let owner = Caller { team: dao::TeamDao::find_team_by_id(conn, team_id)?, admin: false };
+        Self::check_owner(&sec, &owner)?;
+        let sec_id = sec.id.ok_or_else(|| error::error("Null secret id"))?;
+        let path = match &sec.path {
+            Some(path) if sec.backend != backend::POSTGRES => format!("{}/{}", owner.team.name, path),
+            _ => sec.name.clone()
+        };
+        let (version, data) = backend::open(conn, &sec.backend)?.read(&path, sec_ref.version)?;
+        Ok(SecretValue {
+            secret_id: sec_id,
+            name: sec.name,
+            version,
+            data
+        })
+    }
+
+    /// Record the versions of the secrets that the instance is built with.
+    pub fn record_usage(conn: &mut PgConnection, art_id: i32, inst_id: &str, values: &[SecretValue]) -> error::Result<()> {
+        for value in values {
+            dao::SecretDao::create_usage(conn, model::SecretUsage {
+                id: None,
+                artifact_id: art_id,
+                inst_id: inst_id.to_owned(),
+                secret_id: value.secret_id,
+                version: value.version,
+                created_at: None
+            })?;
+        }
+        Ok(())
+    }
+
+    fn artifacts_following_latest(conn: &mut PgConnection, sec_name: &str) -> error::Result<Vec<i32>> {
+        let mut result = Vec::new();
+        for art in dao::ArtifactDao::list_by_secret(conn, sec_name)? {
+            let follows = ArtifactOps::secret_refs(&art)?.iter().any(|r| r.name == sec_name && r.version.is_none());
+            if let (true, Some(art_id)) = (follows, art.id) {
+                result.push(art_id);
+            }
+        }
+        Ok(result)
+    }
+
+    fn check_owner(sec: &model::Secret, caller: &Caller) -> error::Result<()> {
+        if caller.owns(sec.owner) {
+            Ok(())
+        } else {
+            Err(error::GeneralError::Forbidden(format!("The secret {} is not owned by the team {}", sec.name, caller.team.name)))
+        }
+    }
+}
+
+pub struct AccountOps;
+
+impl AccountOps {
+    /// Create the account pool owned by the team of the token. Each of the units is available.
+    pub fn create(conn: &mut PgConnection, token: &str, req: AccountRequest) -> error::Result<i32> {
+        let team = dao::TeamDao::find_team_by_token(conn, token)?;
+        conn.transaction(|connection| {
+            let total = req.units.len() as i32;
+            let acnt_id = dao::AccountDao::create(connection, model::Account {
+                id: None,
+                name: req.name,
+                total,
+                in_stock: total,
+                data: req.data,
+                owner: team.id,
+                desp: req.desp,
+                fields: req.fields.as_ref().map(serde_json::to_value).transpose()?,
+                scrub: req.scrub.as_ref().map(serde_json::to_value).transpose()?
+            })?;
+            for data in req.units {
+                Self::create_unit(connection, acnt_id, req.fields.as_deref(), data)?;
+            }
+            Ok(acnt_id)
+        })
+    }
+
+    pub fn update(conn: &mut PgConnection, token: &str, acnt_name: &str, req: AccountUpdateRequest) -> error::Result<()> {
+        let caller = Caller::of_token(conn, token)?;
+        conn.transaction(|connection| {
+            let mut acnt = dao::AccountDao::load_by_name_for_update(connection, acnt_name)?;
+            Self::check_owner(&acnt, &caller)?;
+            let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+            if req.data.is_some() || req.desp.is_some() || req.scrub.is_some() {
+                acnt.data = req.data.unwrap_or(acnt.data);
+                acnt.desp = req.desp.or(acnt.desp);
+                if let Some(scrub) = &req.scrub {
+                    acnt.scrub = Some(serde_json::to_value(scrub)?);
+                }
+                dao::AccountDao::update(connection, &acnt)?;
+            }
+            let fields = Self::fields(&acnt)?;
+            for data in req.add_units {
+                Self::create_unit(connection, acnt_id, fields.as_deref(), data)?;
+                dao::AccountDao::adjust_stock(connection, acnt_id, 1, 1)?;
+            }
+            for unit_id in req.remove_units {
+                let unit = dao::AccountDao::load_unit(connection, unit_id)?;
+                if unit.account_id != acnt_id || UnitStatus::from(&unit.stat) != UnitStatus::Available {
+                    return Err(error::conflict(&format!("The unit {} is not an available unit of the account {}", unit_id, acnt_name)));
+                }
+                dao::AccountDao::delete_unit(connection, unit_id)?;
+                dao::AccountDao::adjust_stock(connection, acnt_id, -1, -1)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn show(conn: &mut PgConnection, token: &str, acnt_name: &str) -> error::Result<AccountInfo> {
+        let caller = Caller::of_token(conn, token)?;
+        let acnt = dao::AccountDao::load_by_name(conn, acnt_name)?;
+        Self::check_owner(&acnt, &caller)?;
+        Self::info(conn, acnt)
+    }
+
+    pub fn list(conn: &mut PgConnection, token: &str) -> error::Result<Vec<AccountInfo>> {
+        let team = dao::TeamDao::find_team_by_token(conn, token)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        let mut result = Vec::new();
+        for acnt in dao::AccountDao::list_by_owner(conn, team_id)? {
+            result.push(Self::info(conn, acnt)?);
+        }
+        Ok(result)
+    }
+
+    /// Delete the account pool. It is refused if any of the units is still in use.
+    pub fn delete(conn: &mut PgConnection, token: &str, acnt_name: &str) -> error::Result<()> {
+        let caller = Caller::of_token(conn, token)?;
+        conn.transaction(|connection| {
+            let acnt = dao::AccountDao::load_by_name_for_update(connection, acnt_name)?;
+            Self::check_owner(&acnt, &caller)?;
+            let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+            let units = dao::AccountDao::list_units(connection, acnt_id)?;
+            if units.iter().any(|unit| UnitStatus::from(&unit.stat) != UnitStatus::Available) {
+                return Err(error::conflict(&format!("The account {} still has units in use", acnt_name)));
+            }
+            dao::AccountDao::delete(connection, acnt_id)?;
+            Ok(())
+        })
+    }
+
+    /// List the check-outs of the units of the account pool.
+    pub fn usage(conn: &mut PgConnection, token: &str, acnt_name: &str) -> error::Result<Vec<AccountUsageInfo>> {
+        let caller = Caller::of_token(conn, token)?;
+        let acnt = dao::AccountDao::load_by_name(conn, acnt_name)?;
+        Self::check_owner(&acnt, &caller)?;
+        let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+        let mut result = Vec::new();
+        for usage in dao::AccountDao::list_usage(conn, acnt_id)? {
+            let art = dao::ArtifactDao::load_by_id(conn, usage.artifact_id)?;
+            result.push(AccountUsageInfo {
+                unit_id: usage.unit_id,
+                artifact: art.name,
+                inst_id: usage.inst_id,
+                checked_out_at: usage.checked_out_at,
+                checked_in_at: usage.checked_in_at
+            });
+        }
+        Ok(result)
+    }
+
+    /// Check out one unit of each of the account pools for the instance, all or nothing.
+    /// The pools are locked by `SELECT ... FOR UPDATE` in the order of the names to avoid dead
+    /// locks. `GeneralError::PendingAccount` is returned if any of the pools is exhausted.
+    pub fn check_out(conn: &mut PgConnection, art_id: i32, inst_id: &str, refs: &[AccountRef]) -> error::Result<Vec<CheckedOutUnit>> {
+        let mut names: Vec<&str> = refs.iter().map(|r| r.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        conn.transaction(|connection| {
+            let mut result = Vec::new();
+            for acnt_name in names {
+                let acnt = dao::AccountDao::load_by_name_for_update(connection, acnt_name)?;
+                let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+                let unit = match dao::AccountDao::find_unit_in_stat(connection, acnt_id, &UnitStatus::Available.to_string())? {
+                    Some(unit) => unit,
+                    None => return Err(error::GeneralError::PendingAccount(acnt.name))
+                };
+                let unit_id = unit.id.ok_or_else(|| error::error("Null unit id"))?;
+                let keys = account::secret_keys(Self::fields(&acnt)?.as_deref(), &unit.data)?;
+                dao::AccountDao::update_unit_stat(connection, unit_id, &UnitStatus::CheckedOut.to_string())?;
+                dao::AccountDao::adjust_stock(connection, acnt_id, 0, -1)?;
+                dao::AccountDao::create_usage(connection, model::AccountUsage {
+                    id: None,
+                    unit_id,
+                    account_id: acnt_id,
+                    artifact_id: art_id,
+                    inst_id: inst_id.to_owned(),
+                    checked_out_at: None,
+                    checked_in_at: None
+                })?;
+                log::info!("unit {} of account {} is checked out by instance {}", unit_id, acnt.name, inst_id);
+                result.push(CheckedOutUnit {
+                    account: acnt.name,
+                    unit_id,
+                    data: unit.data,
+                    keys
+                });
+            }
+            Ok(result)
+        })
+    }
+
+    /// Check in the units checked out by the instance once it is cleaned. The artifacts pending on
+    /// the pools are released from `PendingAccount`, and their ids are returned so the caller
+    /// resumes them.
+    pub fn check_in(conn: &mut PgConnection, art_id: i32, inst_id: &str) -> error::Result<Vec<i32>> {
+        conn.transaction(|connection| {
+            let usages = dao::AccountDao::list_open_usage(connection, art_id, inst_id)?;
+            let mut pools = Vec::new();
+            for usage in &usages {
+                let acnt = dao::AccountDao::load_by_id_for_update(connection, usage.account_id)?;
+                dao::AccountDao::update_unit_stat(connection, usage.unit_id, &UnitStatus::Available.to_string())?;
+                dao::AccountDao::adjust_stock(connection, usage.account_id, 0, 1)?;
+                dao::AccountDao::close_usage(connection, usage.id.ok_or_else(|| error::error("Null usage id"))?)?;
+                log::info!("unit {} of account {} is checked in by instance {}", usage.unit_id, acnt.name, inst_id);
+                if !pools.contains(&acnt.name) {
+                    pools.push(acnt.name);
+                }
+            }
+            let mut resumed = Vec::new();
+            for pool in pools {
+                resumed.extend(Self::release_pending(connection, &pool)?);
+            }
+            Ok(resumed)
+        })
+    }
+
+    /// Quarantine the units checked out by the instance, because its clean run failed and the
+    /// units may still hold resources. They stay out of stock until an admin releases them.
+    pub fn quarantine(conn: &mut PgConnection, art_id: i32, inst_id: &str, reason: &str) -> error::Result<usize> {
+        conn.transaction(|connection| {
+            let usages = dao::AccountDao::list_open_usage(connection, art_id, inst_id)?;
+            for usage in &usages {
+                let acnt = dao::AccountDao::load_by_id_for_update(connection, usage.account_id)?;
+                dao::AccountDao::quarantine_unit(connection, usage.unit_id, &UnitStatus::Quarantined.to_string(), reason)?;
+                dao::AccountDao::close_usage(connection, usage.id.ok_or_else(|| error::error("Null usage id"))?)?;
+                log::warn!("unit {} of account {} is quarantined after instance {}: {}", usage.unit_id, acnt.name, inst_id, reason);
+            }
+            Ok(usages.len())
+        })
+    }
+
+    /// List the quarantined units of all the accounts, without their data.
+    pub fn list_quarantined(conn: &mut PgConnection) -> error::Result<Vec<QuarantinedUnitInfo>> {
+        let mut result = Vec::new();
+        for unit in dao::AccountDao::list_units_in_stat(conn, &UnitStatus::Quarantined.to_string())? {
+            let mut info = Self::quarantined_info(conn, &unit)?;
+            info.data = None;
+            result.push(info);
+        }
+        Ok(result)
+    }
+
+    /// Inspect the quarantined unit, including its data, to find the resources left in it.
+    pub fn inspect(conn: &mut PgConnection, unit_id: i32) -> error::Result<QuarantinedUnitInfo> {
+        let unit = Self::load_quarantined(conn, unit_id)?;
+        Self::quarantined_info(conn, &unit)
+    }
+
+    /// Release the quarantined unit back to the stock, and the artifacts pending on the account.
+    /// The artifacts are resumed through the outbox in the same transaction, and their ids are
+    /// returned.
+    pub fn release(conn: &mut PgConnection, unit_id: i32) -> error::Result<Vec<i32>> {
+        conn.transaction(|connection| {
+            let unit = Self::load_quarantined(connection, unit_id)?;
+            let acnt = dao::AccountDao::load_by_id_for_update(connection, unit.account_id)?;
+            dao::AccountDao::release_unit(connection, unit_id, &UnitStatus::Available.to_string())?;
+            dao::AccountDao::adjust_stock(connection, unit.account_id, 0, 1)?;
+            log::info!("unit {} of account {} is released from quarantine", unit_id, acnt.name);
+            let resumed = Self::release_pending(connection, &acnt.name)?;
+            for art_id in &resumed {
+                OutboxOps::notify(connection, *art_id, "resume")?;
+            }
+            Ok(resumed)
+        })
+    }
+
+    /// Run the scrub pipeline of the account against the quarantined unit, and return the name of
+    /// the pipeline run. The unit stays quarantined until it is released.
+    pub fn scrub(conn: &mut PgConnection, executor: &dyn Executable, unit_id: i32) -> error::Result<String> {
+        let unit = Self::load_quarantined(conn, unit_id)?;
+        let acnt = dao::AccountDao::load_by_id(conn, unit.account_id)?;
+        let scrub: DeployUnit = match &acnt.scrub {
+            Some(scrub) => serde_json::from_value(scrub.clone())?,
+            None => return Err(error::conflict(&format!("The account {} has no scrub pipeline", acnt.name)))
         };
+        let keys = account::secret_keys(Self::fields(&acnt)?.as_deref(), &unit.data)?;
+        let kvs = keys.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        Rollout::apply_secrets(executor, &vec![manifest::Secret::new(format!("acnt-scrub-{}-{}", acnt.name, unit_id), artifact::DEFAULT_NAMESPACE, kvs)])?;
+        let mut redactor = Redactor::default();
+        for value in keys.values() {
+            redactor.add_data(value);
+        }
+        let pipeline_name = format!("scrub-{}", acnt.name);
+        executor.apply(&pipeline_name, &scrub.to_manifest_yaml(&pipeline_name)?, &redactor)?;
+        let params = vec![
+            ("account".to_owned(), acnt.name.clone()),
+            ("unit_id".to_owned(), unit_id.to_string())
+        ];
+        let run_name = executor.start(&pipeline_name, &params, &redactor)?;
+        log::info!("scrubbing unit {} of account {} by {}", unit_id, acnt.name, run_name);
+        Ok(run_name)
+    }
+
+    fn load_quarantined(conn: &mut PgConnection, unit_id: i32) -> error::Result<model::AccountUnit> {
+        let unit = dao::AccountDao::load_unit(conn, unit_id)?;
+        if UnitStatus::from(&unit.stat) != UnitStatus::Quarantined {
+            return Err(error::conflict(&format!("The unit {} is not quarantined", unit_id)));
+        }
+        Ok(unit)
+    }
+
+    fn quarantined_info(conn: &mut PgConnection, unit: &model::AccountUnit) -> error::Result<QuarantinedUnitInfo> {
+        let acnt = dao::AccountDao::load_by_id(conn, unit.account_id)?;
+        let (artifact, inst_id) = match dao::AccountDao::last_usage(conn, unit.id.unwrap_or_default())? {
+            Some(usage) => (Some(dao::ArtifactDao::load_by_id(conn, usage.artifact_id)?.name), Some(usage.inst_id)),
+            None => (None, None)
+        };
+        Ok(QuarantinedUnitInfo {
+            account: acnt.name,
+            unit_id: unit.id.unwrap_or_default(),
+            artifact,
+            inst_id,
+            reason: unit.reason.clone(),
+            quarantined_at: unit.quarantined_at,
+            data: Some(unit.data.clone())
+        })
+    }
+
+    /// Release the artifacts pending on the account from `PendingAccount`.
+    fn release_pending(conn: &mut PgConnection, pool: &str) -> error::Result<Vec<i32>> {
+        let mut released = Vec::new();
+        for id in dao::ArtifactDao::list_blocked_by(conn, &ArtifactStatus::PendingAccount.to_string(), pool)? {
+            dao::ArtifactDao::update_stat(conn, id, &ArtifactStatus::NotScheduled.to_string(), None)?;
+            released.push(id);
+        }
+        Ok(released)
+    }
+
+    /// Apply the units as the Kubernetes Secrets `acnt-{artifact}-{instance}-{account}`, which
+    /// are mounted by the build run. Each field of the unit is a key of the secret, or the data
+    /// of the unit is under the key `data` if the account has no fields.
+    pub fn apply(executor: &dyn Executable, art_name: &str, inst_id: &str, units: &[CheckedOutUnit]) -> error::Result<()> {
+        let secrets: Vec<manifest::Secret> = units.iter().map(|unit| {
+            let kvs = unit.keys.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            manifest::Secret::new(format!("acnt-{}-{}-{}", art_name, inst_id, unit.account), artifact::DEFAULT_NAMESPACE, kvs)
+        }).collect();
+        if !secrets.is_empty() {
+            Rollout::apply_secrets(executor, &secrets)?;
+        }
+        Ok(())
+    }
+
+    /// The fields of the account, `None` if the data of the units are opaque.
+    pub fn fields(acnt: &model::Account) -> error::Result<Option<Vec<AccountField>>> {
+        acnt.fields.clone().map(serde_json::from_value).transpose().map_err(|err| err.into())
+    }
+
+    fn create_unit(conn: &mut PgConnection, acnt_id: i32, fields: Option<&[AccountField]>, data: String) -> error::Result<i32> {
+        if let Some(fields) = fields {
+            account::validate_unit(fields, &data)?;
+        }
+        dao::AccountDao::create_unit(conn, model::AccountUnit {
+            id: None,
+            account_id: acnt_id,
+            data,
+            stat: UnitStatus::Available.to_string(),
+            quarantined_at: None,
+            reason: None
+        })
+    }
+
+    fn info(conn: &mut PgConnection, acnt: model::Account) -> error::Result<AccountInfo> {
+        let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+        let units = dao::AccountDao::list_units(conn, acnt_id)?.into_iter()
+            .map(|unit| AccountUnitInfo { id: unit.id.unwrap_or_default(), stat: unit.stat })
+            .collect();
+        let fields = Self::fields(&acnt)?;
+        Ok(AccountInfo {
+            name: acnt.name,
+            total: acnt.total,
+            in_stock: acnt.in_stock,
+            fields,
+            desp: acnt.desp,
+            units
+        })
+    }
+
+    fn check_owner(acnt: &model::Account, caller: &Caller) -> error::Result<()> {
+        if caller.owns(acnt.owner) {
+            Ok(())
+        } else {
+            Err(error::GeneralError::Forbidden(format!("The account {} is not owned by the team {}", acnt.name, caller.team.name)))
+        }
+    }
+}
 
-        dao::TeamDao::create(conn, team)
+pub struct CredentialOps;
+
+impl CredentialOps {
+    /// Generate the credentials declared by the build of the artifact for the instance. They are
+    /// saved encrypted.
+    pub fn issue(conn: &mut PgConnection, art_id: i32, inst_id: &str) -> error::Result<HashMap<String, HashMap<String, String>>> {
+        let art = dao::ArtifactDao::load_by_id(conn, art_id)?;
+        let build: DeployUnit = serde_json::from_value(art.build.clone())?;
+        let mut creds = HashMap::new();
+        for generated in build.generated.unwrap_or_default() {
+            creds.insert(generated.name.clone(), generated.generate()?);
+        }
+        conn.transaction(|connection| {
+            for (name, data) in &creds {
+                dao::CredentialDao::create(connection, model::InstCredential {
+                    id: None,
+                    artifact_id: art_id,
+                    inst_id: inst_id.to_owned(),
+                    name: name.clone(),
+                    data: crypto::encrypt(&serde_json::to_string(data)?)?,
+                    created_at: None
+                })?;
+            }
+            error::Result::Ok(())
+        })?;
+        Ok(creds)
+    }
+
+    /// Apply the credentials of the instance as the Kubernetes Secrets
+    /// `gen-{artifact}-{instance}-{name}`, which are mounted by the build run.
+    pub fn apply(executor: &dyn Executable, art_name: &str, inst_id: &str, creds: &HashMap<String, HashMap<String, String>>) -> error::Result<()> {
+        let secrets: Vec<manifest::Secret> = creds.iter().map(|(name, data)| {
+            let kvs = data.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            manifest::Secret::new(format!("gen-{}-{}-{}", art_name, inst_id, name), artifact::DEFAULT_NAMESPACE, kvs)
+        }).collect();
+        if !secrets.is_empty() {
+            Rollout::apply_secrets(executor, &secrets)?;
+        }
+        Ok(())
+    }
+
+    /// The credentials generated for the instance, they are returned to the borrower.
+    pub fn show(conn: &mut PgConnection, token: &str, art_name: String, inst_id: &str) -> error::Result<HashMap<String, HashMap<String, String>>> {
+        let caller = Caller::of_token(conn, token)?;
+        let art = dao::ArtifactDao::load_by_name(conn, art_name)?;
+        if !caller.owns(Some(art.team_id)) {
+            return Err(error::GeneralError::Forbidden(format!("The artifact {} is not owned by the team {}", art.name, caller.team.name)));
+        }
+        Self::load(conn, art.id.ok_or_else(|| error::error("Null artifact id"))?, inst_id)
+    }
+
+    pub fn load(conn: &mut PgConnection, art_id: i32, inst_id: &str) -> error::Result<HashMap<String, HashMap<String, String>>> {
+        let mut creds = HashMap::new();
+        for cred in dao::CredentialDao::list_by_instance(conn, art_id, inst_id)? {
+            creds.insert(cred.name, serde_json::from_str(&crypto::decrypt(&cred.data)?)?);
+        }
+        Ok(creds)
+    }
+
+    /// Remove the credentials of the instance once it is cleaned.
+    pub fn revoke(conn: &mut PgConnection, art_id: i32, inst_id: &str) -> error::Result<usize> {
+        dao::CredentialDao::delete_by_instance(conn, art_id, inst_id)
+    }
+}
+
+pub struct TeamOps;
+impl TeamOps {
+    /// Create the team with a random token. The token is returned only here, since only its hash
+    /// is saved.
+    pub fn create(conn: &mut PgConnection, team_name: String, desp: Option<String>) -> error::Result<(i32, String)> {
+        let token = crypto::generate_token();
+        let team = dao::model::Team::new(team_name, &token, desp);
+        let team_id = dao::TeamDao::create(conn, team)?;
+        Ok((team_id, token))
     }
 
     pub fn find_team_by_token(conn: &mut PgConnection, team_token: String) -> error::Result<dao::model::Team> {
@@ -140,26 +955,640 @@ impl TeamOps {
         dao::TeamDao::delete(conn, team_id)
     }
 
-    pub fn rotate_token(conn: &mut PgConnection, team_name: String) -> error::Result<()> {
-        let token = Self::generate();
-        dao::TeamDao::rotate_token(conn, team_name, token)
+    /// Rename the team or update its description.
+    pub fn update(conn: &mut PgConnection, team_name: String, req: TeamUpdateRequest) -> error::Result<()> {
+        conn.transaction(|connection| {
+            let mut team = dao::TeamDao::find_team_by_name_for_update(connection, &team_name)?;
+            if let Some(name) = req.name {
+                if name.is_empty() {
+                    return Err(error::bad_request("The team name must not be empty"));
+                }
+                team.name = name;
+            }
+            team.desp = req.desp.or(team.desp);
+            if let Some(tier) = req.tier {
+                if !(0..=team::MAX_TIER).contains(&tier) {
+                    return Err(error::bad_request(&format!("The tier of the team must be from 0 to {}", team::MAX_TIER)));
+                }
+                team.tier = tier;
+            }
+            dao::TeamDao::update(connection, &team)?;
+            Ok(())
+        })
+    }
+
+    /// The numbers of the artifacts, secrets and account pools owned by the team.
+    pub fn ownership(conn: &mut PgConnection, team_id: i32) -> error::Result<TeamOwnership> {
+        Ok(TeamOwnership {
+            artifacts: dao::ArtifactDao::count_by_team(conn, team_id)?,
+            secrets: dao::SecretDao::count_by_owner(conn, team_id)?,
+            accounts: dao::AccountDao::list_by_owner(conn, team_id)?.len() as i64
+        })
+    }
+
+    /// Delete the team. It is refused while the team owns any artifacts, secrets or account
+    /// pools, unless `cascade` is given to delete them along with the team. Even with the cascade,
+    /// the team is not deleted while any unit of its account pools is in use, or any artifact of
+    /// the team is being reconciled, has instances or has credentials issued to its instances, so
+    /// the targets of the artifacts are to be set to 0 first and their instances left to clean.
+    /// The secret and account usages of the artifacts are deleted along with them, and the
+    /// entries still queued for them are skipped by the scheduler once they are gone.
+    pub fn delete_by_name(conn: &mut PgConnection, team_name: String, cascade: bool) -> error::Result<()> {
+        conn.transaction(|connection| {
+            let team = dao::TeamDao::find_team_by_name_for_update(connection, &team_name)?;
+            let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+            let ownership = Self::ownership(connection, team_id)?;
+            if !ownership.is_empty() {
+                if !cascade {
+                    return Err(error::conflict(&format!("The team {} still owns {} artifacts, {} secrets and {} accounts",
+                        team_name, ownership.artifacts, ownership.secrets, ownership.accounts)));
+                }
+                for acnt in dao::AccountDao::list_by_owner(connection, team_id)? {
+                    let acnt_id = acnt.id.ok_or_else(|| error::error("Null account id"))?;
+                    let units = dao::AccountDao::list_units(connection, acnt_id)?;
+                    if units.iter().any(|unit| UnitStatus::from(&unit.stat) != UnitStatus::Available) {
+                        return Err(error::conflict(&format!("The account {} still has units in use", acnt.name)));
+                    }
+                }
+                let art_ids = dao::ArtifactDao::list_ids_by_team(connection, team_id)?;
+                if !art_ids.is_empty() {
+                    let mut redis_conn = queue::connection()?;
+                    let leases = Self::lease_idle(connection, &mut redis_conn, &art_ids)?;
+                    // The leases are left to expire once the artifacts are deleted, so no
+                    // reconcile of them starts before the deletion is committed.
+                    if let Err(err) = Self::delete_artifacts(connection, team_id, &art_ids) {
+                        for lease in leases {
+                            lease.release(&mut redis_conn)?;
+                        }
+                        return Err(err);
+                    }
+                }
+                dao::SecretDao::delete_by_owner(connection, team_id)?;
+                dao::AccountDao::delete_by_owner(connection, team_id)?;
+            }
+            dao::TeamDao::delete(connection, team_id)?;
+            Ok(())
+        })
+    }
+
+    /// Take the leases of the artifacts, so the scheduler leaves them alone, and check that none
+    /// of them has instances. The leases taken are released if any check fails.
+    fn lease_idle(conn: &mut PgConnection, redis_conn: &mut redis::Connection, art_ids: &[i32]) -> error::Result<Vec<Lease>> {
+        let mut leases = Vec::new();
+        let mut checked = Ok(());
+        for art_id in art_ids {
+            match Lease::acquire(&format!("artifact:{}", art_id), "team-delete", lease::DEFAULT_LEASE_MS, redis_conn)? {
+                Some(lease) => leases.push(lease),
+                None => {
+                    checked = Err(error::conflict(&format!("The artifact {} is being reconciled, try again later", art_id)));
+                    break;
+                }
+            }
+            let instances = dao::InstanceDao::many(&art_id.to_string(), redis_conn)?.len();
+            if instances > 0 {
+                checked = Err(error::conflict(&format!("The artifact {} still has {} instances, set its target to 0 and wait for them to be cleaned", art_id, instances)));
+                break;
+            }
+        }
+        if checked.is_ok() && dao::CredentialDao::count_by_artifacts(conn, art_ids)? > 0 {
+            checked = Err(error::conflict("The artifacts of the team still have credentials issued to their instances"));
+        }
+        if let Err(err) = checked {
+            for lease in leases {
+                lease.release(redis_conn)?;
+            }
+            return Err(err);
+        }
+        Ok(leases)
+    }
+
+    fn delete_artifacts(conn: &mut PgConnection, team_id: i32, art_ids: &[i32]) -> error::Result<()> {
+        dao::OutboxDao::delete_by_artifacts(conn, art_ids)?;
+        dao::ArtifactDao::delete_by_team(conn, team_id)?;
+        Ok(())
+    }
+
+    /// Issue a new token to the team and return it. The current token stays valid for `grace_sec`
+    /// seconds if it is given, so the clients can switch to the new token without an outage.
+    pub fn rotate_token(conn: &mut PgConnection, team_name: String, grace_sec: Option<i64>) -> error::Result<String> {
+        let token = crypto::generate_token();
+        conn.transaction(|connection| {
+            let mut team = dao::TeamDao::find_team_by_name_for_update(connection, &team_name)?;
+            team.rotate(&token, grace_sec);
+            dao::TeamDao::update(connection, &team)
+        })?;
+        Ok(token)
+    }
+}
+
+pub struct QuotaOps;
+
+impl QuotaOps {
+    /// Set the quota of the team, the absent limits are unlimited. It does not touch the
+    /// artifacts already over the new limits, but refuses to grow them further.
+    pub fn set(conn: &mut PgConnection, team_name: &str, req: QuotaRequest) -> error::Result<()> {
+        if [req.max_artifacts, req.max_total, req.max_running, req.max_borrowed].iter().flatten().any(|limit| *limit < 0) {
+            return Err(error::bad_request("The limits of the quota must not be negative"));
+        }
+        let team = dao::TeamDao::find_team_by_name(conn, team_name)?;
+        dao::TeamDao::save_quota(conn, &model::Quota {
+            team_id: team.id.ok_or_else(|| error::error("Null team id"))?,
+            max_artifacts: req.max_artifacts,
+            max_total: req.max_total,
+            max_running: req.max_running,
+            max_borrowed: req.max_borrowed
+        })?;
+        Ok(())
+    }
+
+    pub fn show(conn: &mut PgConnection, team_name: &str) -> error::Result<QuotaInfo> {
+        let team = dao::TeamDao::find_team_by_name(conn, team_name)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        Ok(QuotaInfo {
+            limits: Self::limits(conn, team_id)?,
+            usage: QuotaUsage {
+                artifacts: dao::ArtifactDao::count_by_team(conn, team_id)?,
+                total: dao::ArtifactDao::sum_total_by_team(conn, team_id, None)?
+            }
+        })
+    }
+
+    /// Check the number of the artifacts and their summed `total` before the artifact is created,
+    /// or updated to the `total`. The team is locked, so the concurrent requests of the team are
+    /// admitted one by one. It must be called in the transaction of the change.
+    fn admit(conn: &mut PgConnection, team_name: &str, art_name: &str, total: i32, creating: bool) -> error::Result<()> {
+        let team = dao::TeamDao::find_team_by_name_for_update(conn, team_name)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        let limits = Self::limits(conn, team_id)?;
+        if creating {
+            quota::check(&team.name, "maxArtifacts", dao::ArtifactDao::count_by_team(conn, team_id)?, 1, limits.max_artifacts)?;
+        }
+        let others = dao::ArtifactDao::sum_total_by_team(conn, team_id, if creating { None } else { Some(art_name) })?;
+        quota::check(&team.name, "maxTotal", others, total as i64, limits.max_total)
+    }
+
+    /// Check the quota of the team owning the artifact before the rollout of the runs, a negative
+    /// number of which are the clean runs. The clean runs are never refused, they release the
+    /// resources. The builds are capped at the headroom left under max running, and refused only
+    /// if there is none. It returns the number of the runs allowed.
+    pub fn check_run(conn: &mut PgConnection, art_id: i32, runs: i32, usage: &RunUsage) -> error::Result<i32> {
+        if runs <= 0 {
+            return Ok(runs);
+        }
+        let art = dao::ArtifactDao::load_by_id(conn, art_id)?;
+        let team = dao::TeamDao::find_team_by_id(conn, art.team_id)?;
+        let limits = Self::limits(conn, art.team_id)?;
+        quota::check(&team.name, "maxRunning", usage.running, 1, limits.max_running)?;
+        Ok(limits.max_running
+            .map_or(runs, |limit| (limit as i64 - usage.running).min(runs as i64) as i32))
+    }
+
+    /// Check the quota of the team before one more instance is borrowed.
+    pub fn check_borrow(conn: &mut PgConnection, redis_conn: &mut dyn redis::ConnectionLike, team_id: i32) -> error::Result<()> {
+        let team = dao::TeamDao::find_team_by_id(conn, team_id)?;
+        let limits = Self::limits(conn, team_id)?;
+        let usage = Self::run_usage(conn, redis_conn, team_id)?;
+        quota::check(&team.name, "maxBorrowed", usage.borrowed, 1, limits.max_borrowed)
+    }
+
+    /// The running and borrowed instances of all the artifacts of the team, read from redis in one
+    /// round trip.
+    pub fn run_usage(conn: &mut PgConnection, redis_conn: &mut dyn redis::ConnectionLike, team_id: i32) -> error::Result<RunUsage> {
+        let art_ids: Vec<String> = dao::ArtifactDao::list_ids_by_team(conn, team_id)?.iter().map(|id| id.to_string()).collect();
+        let mut usage = RunUsage::default();
+        for inst in dao::InstanceDao::many_of(&art_ids, redis_conn)? {
+            if inst.stat == instance::InstanceStatus::Running {
+                usage.running += 1;
+            } else if inst.is_borrowed() {
+                usage.borrowed += 1;
+            }
+        }
+        Ok(usage)
+    }
+
+    fn limits(conn: &mut PgConnection, team_id: i32) -> error::Result<QuotaRequest> {
+        Ok(dao::TeamDao::load_quota(conn, team_id)?
+            .map(|quota| QuotaRequest {
+                max_artifacts: quota.max_artifacts,
+                max_total: quota.max_total,
+                max_running: quota.max_running,
+                max_borrowed: quota.max_borrowed
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// The team acting with the token, see `TeamDao::find_team_by_token`. The global admins act in
+/// all the teams, they are not held to the ownership of the team they are a member of.
+struct Caller {
+    team: model::Team,
+    admin: bool
+}
+
+impl Caller {
+    fn of_token(conn: &mut PgConnection, token: &str) -> error::Result<Self> {
+        let team = dao::TeamDao::find_team_by_token(conn, token)?;
+        let admin = TokenOps::is_admin(conn, token)?;
+        Ok(Caller { team, admin })
+    }
+
+    /// The resource owned by the team is in the reach of the caller.
+    fn owns(&self, owner: Option<i32>) -> bool {
+        self.admin || (owner.is_some() && owner == self.team.id)
+    }
+}
+
+pub struct TokenOps;
+
+impl TokenOps {
+    /// Issue an api token limited to the scopes. Only the team token can issue the api tokens, so
+    /// a leaked api token cannot be used to gain more scopes.
+    pub fn create(conn: &mut PgConnection, team_token: &str, req: TokenRequest) -> error::Result<IssuedToken> {
+        let team = Self::team_of_team_token(conn, team_token)?;
+        if req.name.is_empty() || req.scopes.is_empty() {
+            return Err(error::bad_request("The token must have a name and at least one scope"));
+        }
+        if req.expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now().naive_utc()) {
+            return Err(error::bad_request("The token must expire in the future"));
+        }
+        let token = crypto::generate_token();
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        dao::ApiTokenDao::create(conn, model::ApiToken::new(team_id, req.name.clone(), &token, serde_json::to_value(&req.scopes)?, req.expires_at))?;
+        Ok(IssuedToken { name: req.name, token })
+    }
+
+    /// List the api tokens of the team, including the revoked ones.
+    pub fn list(conn: &mut PgConnection, team_token: &str) -> error::Result<Vec<TokenInfo>> {
+        let team = Self::team_of_team_token(conn, team_token)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        dao::ApiTokenDao::list_by_team(conn, team_id)?
+            .into_iter()
+            .map(|api_token| Ok(TokenInfo {
+                name: api_token.name,
+                prefix: api_token.token_prefix,
+                scopes: serde_json::from_value(api_token.scopes)?,
+                expires_at: api_token.expires_at,
+                last_used_at: api_token.last_used_at,
+                revoked_at: api_token.revoked_at,
+                created_at: api_token.created_at
+            }))
+            .collect()
+    }
+
+    pub fn revoke(conn: &mut PgConnection, team_token: &str, token_name: &str) -> error::Result<()> {
+        let team = Self::team_of_team_token(conn, team_token)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        if dao::ApiTokenDao::revoke(conn, team_id, token_name)? == 0 {
+            return Err(error::not_found(&format!("Unable to find the token: {}", token_name)));
+        }
+        Ok(())
+    }
+
+    /// Check the token is allowed to do the operation of the scope, and return who is acting. The
+    /// team token is allowed to do everything. The api tokens are limited to their scopes, and the
+    /// members to the scopes of their roles. The last used time of the api token is updated.
+    pub fn authorize(conn: &mut PgConnection, token: &str, scope: Scope) -> error::Result<Actor> {
+        if oidc::is_jwt(token) {
+            let (identity, role, team) = Self::identity_of_jwt(conn, token)?;
+            if !role.scopes().iter().any(|granted| granted.grants(scope)) {
+                return Err(error::GeneralError::Forbidden(format!("The {} {} has no scope {}", role, identity.user, scope)));
+            }
+            return Ok(Actor::User { team: team.name, name: identity.user });
+        }
+        if let Some(api_token) = dao::ApiTokenDao::find_by_token(conn, token)? {
+            let team = dao::TeamDao::find_team_by_id(conn, api_token.team_id)?;
+            let scopes: Vec<Scope> = serde_json::from_value(api_token.scopes)?;
+            if !scopes.iter().any(|granted| granted.grants(scope)) {
+                return Err(error::GeneralError::Forbidden(format!("The token {} has no scope {}", api_token.name, scope)));
+            }
+            dao::ApiTokenDao::touch(conn, api_token.id.ok_or_else(|| error::error("Null token id"))?)?;
+            return Ok(Actor::ApiToken { team: team.name, name: api_token.name });
+        }
+        if let Some((user, role, team)) = Self::member_of_token(conn, token)? {
+            if !role.scopes().iter().any(|granted| granted.grants(scope)) {
+                return Err(error::GeneralError::Forbidden(format!("The {} {} has no scope {}", role, user.name, scope)));
+            }
+            return Ok(Actor::User { team: team.name, name: user.name });
+        }
+        Self::team_of_token(conn, token).map(|team| Actor::Team(team.name))
+    }
+
+    /// Check the token is the admin token from the env `TRAIN_ADMIN_TOKEN`, or the token of a
+    /// global admin. Only the admin users are accepted if the env is not set.
+    pub fn authorize_admin(conn: &mut PgConnection, token: &str) -> error::Result<Actor> {
+        if oidc::is_jwt(token) {
+            let (identity, _, team) = Self::identity_of_jwt(conn, token)?;
+            if !identity.admin {
+                return Err(error::GeneralError::Forbidden(format!("The user {} is not an admin", identity.user)));
+            }
+            return Ok(Actor::User { team: team.name, name: identity.user });
+        }
+        dotenvy::dotenv().ok();
+        match std::env::var("TRAIN_ADMIN_TOKEN") {
+            Ok(admin_token) if !admin_token.is_empty() && crypto::verify_secret(&admin_token, token) => Ok(Actor::Admin),
+            _ => match Self::member_of_token(conn, token)? {
+                Some((user, Role::Admin, team)) => Ok(Actor::User { team: team.name, name: user.name }),
+                Some((user, _, _)) => Err(error::GeneralError::Forbidden(format!("The user {} is not an admin", user.name))),
+                None => Err(error::GeneralError::Unauthorized)
+            }
+        }
+    }
+
+    /// The token is the JWT or the member token of a global admin.
+    fn is_admin(conn: &mut PgConnection, token: &str) -> error::Result<bool> {
+        if oidc::is_jwt(token) {
+            return Ok(Self::identity_of_jwt(conn, token)?.0.admin);
+        }
+        Ok(matches!(Self::member_of_token(conn, token)?, Some((_, Role::Admin, _))))
+    }
+
+    /// The user of the JWT, its role in its team and the team. The JWTs are refused if the OIDC
+    /// is not configured, or if the claims map to no role in the team.
+    fn identity_of_jwt(conn: &mut PgConnection, token: &str) -> error::Result<(oidc::Identity, Role, model::Team)> {
+        let identity = oidc::verifier().ok_or(error::GeneralError::Unauthorized)?.verify(token)?;
+        let team = dao::TeamDao::find_team_by_identity(conn, &identity)?;
+        let role = identity.role(&team.name)
+            .ok_or_else(|| error::GeneralError::Forbidden(format!("The JWT of {} maps to no role in the team {}", identity.user, team.name)))?;
+        Ok((identity, role, team))
+    }
+
+    /// The user of the member token, its role in the team and the team. The global admin has the
+    /// role admin in every team it belongs to.
+    fn member_of_token(conn: &mut PgConnection, token: &str) -> error::Result<Option<(model::User, Role, model::Team)>> {
+        let member = match dao::UserDao::find_membership_by_token(conn, token)? {
+            Some(member) => member,
+            None => return Ok(None)
+        };
+        let user = dao::UserDao::load_by_id(conn, member.user_id)?;
+        let team = dao::TeamDao::find_team_by_id(conn, member.team_id)?;
+        let role = if user.admin { Role::Admin } else { member.role.parse::<Role>()? };
+        Ok(Some((user, role, team)))
+    }
+
+    /// The team of the team token. The api tokens are refused.
+    fn team_of_team_token(conn: &mut PgConnection, token: &str) -> error::Result<model::Team> {
+        let team = Self::team_of_token(conn, token)?;
+        if !team.verify(token) {
+            return Err(error::GeneralError::Forbidden("Only the team token can manage the api tokens".to_owned()));
+        }
+        Ok(team)
+    }
+
+    fn team_of_token(conn: &mut PgConnection, token: &str) -> error::Result<model::Team> {
+        match dao::TeamDao::find_team_by_token(conn, token) {
+            Err(error::GeneralError::DBError(diesel::result::Error::NotFound)) => Err(error::GeneralError::Unauthorized),
+            result => result
+        }
+    }
+}
+
+/// The notifications to the scheduler. They are written to the outbox in the transaction of the
+/// change, then drained into the redis queue, so none is lost if the redis server or the
+/// scheduler is down when the change is committed.
+pub struct OutboxOps;
+
+impl OutboxOps {
+    /// The number of the notifications moved to the queue in one transaction.
+    pub const DRAIN_BATCH: i64 = 100;
+
+    pub fn notify(conn: &mut PgConnection, art_id: i32, reason: &str) -> error::Result<()> {
+        log::debug!("Notify the scheduler of the artifact: {} for {}", art_id, reason);
+        dao::OutboxDao::create(conn, model::SchedOutbox {
+            id: None,
+            artifact_id: art_id,
+            reason: reason.to_owned(),
+            created_at: None
+        })?;
+        Ok(())
+    }
+
+    /// Move the pending notifications to the queue, each artifact is enqueued once per batch with
+    /// its priority, see `ArtifactOps::priority`. The notifications are kept in the outbox if the
+    /// queue is unavailable. It returns the number of the notifications drained. The artifacts
+    /// notified to `resume` are taken out of the delayed queue, and those to `replay` off the
+    /// dead-letter list of the queue, before they are queued.
+    ///
+    /// The artifacts are enqueued inside the transaction, before the notifications are deleted, so
+    /// a notification is never deleted without being queued. If the commit fails, the notifications
+    /// stay and are enqueued again by the next drain, which is harmless: an artifact is pending at
+    /// most once, and queuing it again only keeps the earlier place (`ZADD LT`).
+    pub fn drain(conn: &mut PgConnection, redis_conn: &mut dyn redis::ConnectionLike, queue: &queue::Queue) -> error::Result<usize> {
+        let mut drained = 0;
+        loop {
+            let count = conn.transaction(|connection| {
+                let records = dao::OutboxDao::lock_oldest(connection, Self::DRAIN_BATCH)?;
+                // The borrows notified for each artifact, which are waiting for its instances.
+                let mut borrowers: BTreeMap<i32, i64> = BTreeMap::new();
+                for record in &records {
+                    *borrowers.entry(record.artifact_id).or_default() += i64::from(record.reason == "borrow");
+                    match record.reason.as_str() {
+                        "resume" => {
+                            queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned()).remove(&record.artifact_id.to_string(), redis_conn)?;
+                        },
+                        "replay" => {
+                            queue.remove_dead(&record.artifact_id.to_string(), redis_conn)?;
+                        },
+                        _ => {}
+                    }
+                }
+                for (art_id, waiting) in borrowers {
+                    let priority = ArtifactOps::priority(connection, redis_conn, art_id, waiting)?;
+                    queue.enqueue_with_priority(&art_id.to_string(), priority, redis_conn)?;
+                }
+                let ids: Vec<i64> = records.iter().filter_map(|record| record.id).collect();
+                dao::OutboxDao::delete(connection, &ids)
+            })?;
+            drained += count;
+            if (count as i64) < Self::DRAIN_BATCH {
+                return Ok(drained);
+            }
+        }
+    }
+}
+
+pub struct AuditOps;
+
+impl AuditOps {
+    /// Run the operation and append its record to the audit log in the same transaction, so the
+    /// change is never left unrecorded. The state of the resource is digested before and after
+    /// the operation.
+    pub fn run<T>(conn: &mut PgConnection, actor: &Actor, action: &str, resource: &Resource, request_id: &str,
+                  op: impl FnOnce(&mut PgConnection) -> error::Result<T>) -> error::Result<T> {
+        conn.transaction(|connection| {
+            let before_digest = Self::digest(connection, resource)?;
+            let result = op(connection)?;
+            let after_digest = Self::digest(connection, resource)?;
+            dao::AuditDao::create(connection, model::AuditLog {
+                id: None,
+                actor: actor.name().to_owned(),
+                actor_kind: actor.kind().to_owned(),
+                team: actor.team().map(str::to_owned),
+                action: action.to_owned(),
+                resource: resource.to_string(),
+                before_digest,
+                after_digest,
+                request_id: request_id.to_owned(),
+                created_at: None
+            })?;
+            Ok(result)
+        })
+    }
+
+    pub fn query(conn: &mut PgConnection, query: AuditQuery) -> error::Result<Vec<AuditRecord>> {
+        let filter = model::AuditFilter {
+            actor: query.actor.as_deref(),
+            team: query.team.as_deref(),
+            action: query.action.as_deref(),
+            resource: query.resource.as_deref(),
+            since: query.since,
+            until: query.until,
+            limit: query.limit.unwrap_or(audit::DEFAULT_QUERY_LIMIT).clamp(1, audit::MAX_QUERY_LIMIT)
+        };
+        Ok(dao::AuditDao::query(conn, &filter)?
+            .into_iter()
+            .map(|record| AuditRecord {
+                actor: record.actor,
+                actor_kind: record.actor_kind,
+                team: record.team,
+                action: record.action,
+                resource: record.resource,
+                before_digest: record.before_digest,
+                after_digest: record.after_digest,
+                request_id: record.request_id,
+                created_at: record.created_at
+            })
+            .collect())
+    }
+
+    /// The digest of the state of the resource, or None if it does not exist. The data of the
+    /// secrets and the accounts are left out, their versions and units tell the changes.
+    fn digest(conn: &mut PgConnection, resource: &Resource) -> error::Result<Option<String>> {
+        let state = match resource {
+            Resource::Artifact(name) => Self::found(dao::ArtifactDao::load_by_name(conn, name.clone()))?
+                .map(|art| serde_json::json!({"total": art.total, "target": art.target, "build": art.build, "clean": art.clean})),
+            Resource::Secret(name) => Self::found(dao::SecretDao::load_by_name(conn, name))?
+                .map(|sec| serde_json::json!({"version": sec.version, "desp": sec.desp, "backend": sec.backend, "path": sec.path})),
+            Resource::Account(name) => match Self::found(dao::AccountDao::load_by_name(conn, name))? {
+                Some(acnt) => {
+                    let units: Vec<_> = dao::AccountDao::list_units(conn, acnt.id.ok_or_else(|| error::error("Null account id"))?)?
+                        .into_iter()
+                        .map(|unit| serde_json::json!([unit.id, unit.stat]))
+                        .collect();
+                    Some(serde_json::json!({"total": acnt.total, "in_stock": acnt.in_stock, "desp": acnt.desp, "fields": acnt.fields, "scrub": acnt.scrub, "units": units}))
+                },
+                None => None
+            },
+            Resource::Unit(unit_id) => Self::found(dao::AccountDao::load_unit(conn, *unit_id))?
+                .map(|unit| serde_json::json!({"stat": unit.stat, "reason": unit.reason})),
+            Resource::Team(name) => match Self::found(dao::TeamDao::find_team_by_name(conn, name))? {
+                Some(team) => {
+                    let limits = QuotaOps::limits(conn, team.id.ok_or_else(|| error::error("Null team id"))?)?;
+                    Some(serde_json::json!({"name": team.name, "desp": team.desp, "token_prefix": team.token_prefix, "tier": team.tier, "quota": limits}))
+                },
+                None => None
+            },
+            _ => None
+        };
+        Ok(state.map(|state| crypto::digest(&state.to_string())))
     }
 
-    fn generate() -> String {
-        //TODO: generate the token
-        String::from("123456")
+    fn found<T>(result: error::Result<T>) -> error::Result<Option<T>> {
+        match result {
+            Ok(found) => Ok(Some(found)),
+            Err(error::GeneralError::DBError(diesel::result::Error::NotFound)) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+pub struct UserOps;
+
+impl UserOps {
+    pub fn create(conn: &mut PgConnection, req: UserRequest) -> error::Result<i32> {
+        if req.name.is_empty() {
+            return Err(error::bad_request("The user name must not be empty"));
+        }
+        dao::UserDao::create(conn, model::User { id: None, name: req.name, admin: req.admin, created_at: None })
+    }
+
+    /// Delete the user along with its memberships.
+    pub fn delete(conn: &mut PgConnection, user_name: &str) -> error::Result<()> {
+        let user = dao::UserDao::load_by_name(conn, user_name)?;
+        dao::UserDao::delete(conn, user.id.ok_or_else(|| error::error("Null user id"))?)?;
+        Ok(())
+    }
+
+    /// Add the user to the team with the role, and return the token of the user in the team. The
+    /// token is returned only once.
+    pub fn add_member(conn: &mut PgConnection, team_name: &str, req: MemberRequest) -> error::Result<IssuedToken> {
+        Self::check_member_role(req.role)?;
+        let (user_id, team_id) = Self::ids(conn, team_name, &req.user)?;
+        let token = crypto::generate_token();
+        dao::UserDao::create_membership(conn, model::Membership::new(user_id, team_id, req.role.to_string(), &token))?;
+        Ok(IssuedToken { name: req.user, token })
+    }
+
+    pub fn update_member(conn: &mut PgConnection, team_name: &str, user_name: &str, req: MemberUpdateRequest) -> error::Result<()> {
+        Self::check_member_role(req.role)?;
+        let (user_id, team_id) = Self::ids(conn, team_name, user_name)?;
+        if dao::UserDao::update_role(conn, user_id, team_id, &req.role.to_string())? == 0 {
+            return Err(error::not_found(&format!("The user {} is not a member of the team {}", user_name, team_name)));
+        }
+        Ok(())
+    }
+
+    /// Remove the user from the team. The token of the user in the team is refused from then on.
+    pub fn remove_member(conn: &mut PgConnection, team_name: &str, user_name: &str) -> error::Result<()> {
+        let (user_id, team_id) = Self::ids(conn, team_name, user_name)?;
+        if dao::UserDao::delete_membership(conn, user_id, team_id)? == 0 {
+            return Err(error::not_found(&format!("The user {} is not a member of the team {}", user_name, team_name)));
+        }
+        Ok(())
+    }
+
+    pub fn list_members(conn: &mut PgConnection, team_name: &str) -> error::Result<Vec<MemberInfo>> {
+        let team = dao::TeamDao::find_team_by_name(conn, team_name)?;
+        let team_id = team.id.ok_or_else(|| error::error("Null team id"))?;
+        dao::UserDao::list_memberships_by_team(conn, team_id)?
+            .into_iter()
+            .map(|member| {
+                let user = dao::UserDao::load_by_id(conn, member.user_id)?;
+                Ok(MemberInfo { user: user.name, role: member.role.parse::<Role>()? })
+            })
+            .collect()
+    }
+
+    /// The global admin is a flag of the user, it is not given in a team.
+    fn check_member_role(role: Role) -> error::Result<()> {
+        if role == Role::Admin {
+            return Err(error::bad_request("The admin is a flag of the user, it cannot be given in a team"));
+        }
+        Ok(())
+    }
+
+    fn ids(conn: &mut PgConnection, team_name: &str, user_name: &str) -> error::Result<(i32, i32)> {
+        let user = dao::UserDao::load_by_name(conn, user_name)?;
+        let team = dao::TeamDao::find_team_by_name(conn, team_name)?;
+        Ok((user.id.ok_or_else(|| error::error("Null user id"))?, team.id.ok_or_else(|| error::error("Null team id"))?))
     }
 }
 
 pub mod tests {
     use diesel::{Connection, PgConnection};
 
-    use super::dao::{ArtifactDao, TeamDao, get_connection};
+    use super::artifact::ArtifactRequest;
+    use super::dao::{AccountDao, ApiTokenDao, ArtifactDao, SecretDao, TeamDao, UserDao, get_connection, model};
     use crate::error;
 
+    static KEY: std::sync::Once = std::sync::Once::new();
+
     pub struct Environment;
     impl Environment {
         pub fn init(clean: bool, func: impl FnOnce(&mut PgConnection) -> error::Result<()>) -> error::Result<()> {
+            // No key is committed, the tests encrypt with a random one unless it is set.
+            KEY.call_once(|| if std::env::var("TRAIN_ENCRYPTION_KEY").is_err() {
+                std::env::set_var("TRAIN_ENCRYPTION_KEY", crate::crypto::random_hex(32));
+            });
             let mut conn = get_connection();
             conn.test_transaction(|connection| {
                 if clean {
@@ -173,16 +1602,55 @@ pub mod tests {
         fn clean(conn: &mut PgConnection) -> error::Result<()> {
             // Clean artifact
             ArtifactDao::delete_all(conn).expect("Failed to clean artifact");
+            // Clean secret
+            SecretDao::delete_all(conn).expect("Failed to clean secret");
+            // Clean account
+            AccountDao::delete_all(conn).expect("Failed to clean account");
             // Clean team
+            ApiTokenDao::delete_all(conn).expect("Failed to clean api token");
+            UserDao::delete_all(conn).expect("Failed to clean user");
             TeamDao::delete_all(conn).expect("Failed to clean team");
             Ok(())
         }
 
     }
 
+    /// The sample artifact request named `name`, without the references to the other artifacts,
+    /// the secrets and the accounts, so it is created without them.
+    pub fn sample_request(name: &str) -> ArtifactRequest {
+        let file = std::fs::File::open("../asset/sample-artifact-request.json").expect("Unable to open the sample artifact request");
+        let mut request: ArtifactRequest = serde_json::from_reader(file).expect("Fail to parse the json ArtifactRequest");
+        request.name = name.to_owned();
+        request.refs = None;
+        request.build.secrets = None;
+        request.build.accounts = None;
+        request.clean.secrets = None;
+        request.clean.accounts = None;
+        request
+    }
+
+    /// The sample artifact of the team with a single instance to save with `ArtifactDao::create`,
+    /// with the build of `sample_request` and no clean.
+    pub fn sample_artifact(team_id: i32, name: &str) -> model::Artifact {
+        let request = sample_request(name);
+        model::Artifact {
+            id: None,
+            name: request.name,
+            total: 1,
+            target: 1,
+            team_id,
+            build: serde_json::to_value(request.build).expect("Fail to convert the build to json"),
+            clean: None
+        }
+    }
+
     pub fn clean(conn: &mut PgConnection) {
         ArtifactDao::delete_all(conn).expect("Failed to clean artifact");
+        SecretDao::delete_all(conn).expect("Failed to clean secret");
+        AccountDao::delete_all(conn).expect("Failed to clean account");
         // Clean team
+        ApiTokenDao::delete_all(conn).expect("Failed to clean api token");
+        UserDao::delete_all(conn).expect("Failed to clean user");
         TeamDao::delete_all(conn).expect("Failed to clean team");
     }
 }
@@ -0,0 +1,246 @@
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::error;
+use super::artifact::DeployUnit;
+
+/// The payload to create an account pool. Each of the `units` is the data of an account in the
+/// pool, and the `data` is shared by all the units.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountRequest {
+    pub name: String,
+    #[serde(default)]
+    pub data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    /// The fields of the units. If it is present, the data of each unit is a json object
+    /// validated against the fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<AccountField>>,
+    /// The pipeline run against a quarantined unit to remove the resources left in it. The unit
+    /// is mounted as the Kubernetes Secret `acnt-scrub-{account}-{unit_id}`, and the pipeline gets
+    /// the params `account` and `unit_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrub: Option<DeployUnit>,
+    #[serde(default)]
+    pub units: Vec<String>
+}
+
+/// The payload to update an account pool. Only the available units can be removed.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountUpdateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    #[serde(default, rename(serialize = "addUnits", deserialize = "addUnits"))]
+    pub add_units: Vec<String>,
+    #[serde(default, rename(serialize = "removeUnits", deserialize = "removeUnits"))]
+    pub remove_units: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrub: Option<DeployUnit>
+}
+
+/// The account pool as it is shown to the users. The data of the units are not returned.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub name: String,
+    pub total: i32,
+    pub in_stock: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<AccountField>>,
+    pub units: Vec<AccountUnitInfo>
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountUnitInfo {
+    pub id: i32,
+    pub stat: String
+}
+
+/// A check-out of an account unit by an instance.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountUsageInfo {
+    pub unit_id: i32,
+    pub artifact: String,
+    pub inst_id: String,
+    pub checked_out_at: Option<NaiveDateTime>,
+    pub checked_in_at: Option<NaiveDateTime>
+}
+
+/// The quarantined unit as it is shown to the admins. The data is returned only when the unit is
+/// inspected.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct QuarantinedUnitInfo {
+    pub account: String,
+    pub unit_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inst_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub quarantined_at: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>
+}
+
+/// The unit checked out for an instance.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct CheckedOutUnit {
+    pub account: String,
+    pub unit_id: i32,
+    pub data: String,
+    /// The keys of the Kubernetes Secret of the unit, see `secret_keys`.
+    pub keys: HashMap<String, String>
+}
+
+/// A named field of the data of the account units.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountField {
+    pub name: String,
+    #[serde(rename(serialize = "type", deserialize = "type"))]
+    pub tpe: FieldType,
+    /// The values of the sensitive fields are masked in the logs and results.
+    #[serde(default)]
+    pub sensitive: bool,
+    #[serde(default = "default_required")]
+    pub required: bool
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    Boolean,
+    /// A json object, such as the key of a GCP service account.
+    Json
+}
+
+fn default_required() -> bool { true }
+
+/// The key of the Kubernetes Secret holding the data of a unit without fields.
+pub const DATA_KEY: &str = "data";
+
+/// Validate the data of the unit against the fields. The data is a json object, the unknown keys
+/// are refused to catch the typos early.
+pub fn validate_unit(fields: &[AccountField], data: &str) -> error::Result<()> {
+    let object = match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => return Err(error::bad_request("The unit data must be a json object"))
+    };
+    for field in fields {
+        match object.get(&field.name) {
+            None | Some(serde_json::Value::Null) => if field.required {
+                return Err(error::bad_request(&format!("The field {} is missing", field.name)));
+            },
+            Some(value) => {
+                let valid = match field.tpe {
+                    FieldType::String => value.is_string(),
+                    FieldType::Number => value.is_number(),
+                    FieldType::Boolean => value.is_boolean(),
+                    FieldType::Json => is_json_object(value)
+                };
+                if !valid {
+                    return Err(error::bad_request(&format!("The field {} is not a valid {:?}", field.name, field.tpe)));
+                }
+            }
+        }
+    }
+    if let Some(unknown) = object.keys().find(|key| !fields.iter().any(|field| &field.name == *key)) {
+        return Err(error::bad_request(&format!("The field {} is not declared", unknown)));
+    }
+    Ok(())
+}
+
+/// The json field is either an object or a string of the serialized object.
+fn is_json_object(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(_) => true,
+        serde_json::Value::String(s) => matches!(serde_json::from_str(s), Ok(serde_json::Value::Object(_))),
+        _ => false
+    }
+}
+
+/// The keys of the Kubernetes Secret of the unit. Each of the fields is a key, the json fields
+/// are serialized. A unit without fields has its data under the key `data`.
+pub fn secret_keys(fields: Option<&[AccountField]>, data: &str) -> error::Result<HashMap<String, String>> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(HashMap::from([(DATA_KEY.to_owned(), data.to_owned())]))
+    };
+    let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(data)?;
+    let mut keys = HashMap::new();
+    for field in fields {
+        let value = match object.get(&field.name) {
+            None | Some(serde_json::Value::Null) => continue,
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(other) => other.to_string()
+        };
+        keys.insert(field.name.clone(), value);
+    }
+    Ok(keys)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnitStatus {
+    Available,
+    CheckedOut,
+    /// The clean run of the instance using the unit failed, so the unit may still hold resources.
+    /// It is not issued until an admin releases it.
+    Quarantined
+}
+
+impl std::fmt::Display for UnitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Available => "Available",
+            Self::CheckedOut => "CheckedOut",
+            Self::Quarantined => "Quarantined"
+        })
+    }
+}
+
+impl <R: AsRef<str>> From<R> for UnitStatus {
+    fn from(value: R) -> Self {
+        match value.as_ref() {
+            "CheckedOut" => Self::CheckedOut,
+            "Quarantined" => Self::Quarantined,
+            _ => Self::Available
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gcp_fields() -> Vec<AccountField> {
+        serde_json::from_str(r#"[{"name":"project_id","type":"string"},{"name":"key","type":"json","sensitive":true},{"name":"quota","type":"number","required":false}]"#).unwrap()
+    }
+
+    #[test]
+    fn test_validate_unit() {
+        let fields = gcp_fields();
+        assert!(validate_unit(&fields, r#"{"project_id":"p-1","key":{"type":"service_account","private_key":"-----BEGIN"}}"#).is_ok());
+        assert!(validate_unit(&fields, r#"{"project_id":"p-1","key":"{\"type\":\"service_account\"}","quota":4}"#).is_ok());
+        assert!(validate_unit(&fields, r#"{"project_id":"p-1"}"#).unwrap_err().to_string().contains("The field key is missing"));
+        assert!(validate_unit(&fields, r#"{"project_id":"p-1","key":"not json"}"#).is_err());
+        assert!(validate_unit(&fields, r#"{"project_id":1,"key":{}}"#).is_err());
+        assert!(validate_unit(&fields, r#"{"project_id":"p-1","key":{},"projectid":"p-1"}"#).is_err());
+        assert!(validate_unit(&fields, "p-1").is_err());
+    }
+
+    #[test]
+    fn test_secret_keys() {
+        let fields = gcp_fields();
+        let keys = secret_keys(Some(&fields), r#"{"project_id":"p-1","key":{"type":"service_account"},"quota":4}"#).unwrap();
+        assert_eq!(keys["project_id"], "p-1");
+        assert_eq!(keys["key"], r#"{"type":"service_account"}"#);
+        assert_eq!(keys["quota"], "4");
+        assert_eq!(secret_keys(None, "p-1").unwrap()[DATA_KEY], "p-1");
+    }
+}
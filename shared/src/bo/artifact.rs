@@ -2,10 +2,13 @@ use chrono::{DateTime,  Local};
 use diesel::PgConnection;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use super::{dao, manifest};
+use super::{credential, dao, manifest};
+use super::secret::backend;
 use crate::error;
+use crate::redact::Redactor;
+use crate::scheduler::Executable;
 
-const DEFAULT_NAMESPACE: &'static str = "train";
+pub(crate) const DEFAULT_NAMESPACE: &str = "train";
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ArtifactRequest {
@@ -20,6 +23,20 @@ pub struct ArtifactRequest {
     pub clean: DeployUnit
 }
 
+/// The artifact as it is shown in the detail view.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ArtifactInfo {
+    pub name: String,
+    pub total: i32,
+    pub target: i32,
+    pub stat: String,
+    /// The account pool the artifact is waiting for, if it is `PendingAccount`.
+    #[serde(rename(serialize = "blockedBy", deserialize = "blockedBy"), skip_serializing_if = "Option::is_none")]
+    pub blocked_by: Option<String>,
+    /// The consecutive build failures, the artifact goes into `Maintenance` after too many.
+    pub failures: i32
+}
+
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct DeployUnit {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,17 +46,34 @@ pub struct DeployUnit {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub secrets: Option<Vec<SecretRef>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub accounts: Option<Vec<AccountRef>>
+    pub accounts: Option<Vec<AccountRef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated: Option<Vec<credential::GeneratedSecret>>
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct SecretRef {
-    pub name: String
+    pub name: String,
+    /// Pin the secret to the given version. The latest version is used if it is absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct AccountRef {
-    pub name: String
+    pub name: String,
+    /// Expose the fields of the checked out unit to each step of the tasks as env vars.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<AccountEnv>>
+}
+
+/// The env var `name` of the steps is the `field` of the account unit, read from the key of the
+/// Kubernetes Secret `acnt-$(params.art_id)-$(params.inst_id)-{account}`. So the tasks must
+/// declare the params `art_id` and `inst_id`.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountEnv {
+    pub name: String,
+    pub field: String
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
@@ -59,7 +93,10 @@ pub struct Artifact {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Rollout {
+    /// The name of the artifact, passed to the runs as the param `art_id`.
     pub name: String,
+    /// The Tekton pipeline of the rollout, `build-{artifact}` or `clean-{artifact}`.
+    pub pipeline: String,
     pub stats: ArtifactStatus,
     pub last_sched: DateTime<Local>,
     pub accounts: Vec<AccountRef>,
@@ -75,7 +112,9 @@ pub enum ArtifactStatus {
     PendingAccount,
     PendingArtRef,
     Failed,
-    Succeeded
+    Succeeded,
+    /// The builds failed too many times in a row, they are stopped until the owner acknowledges.
+    Maintenance
 }
 
 
@@ -87,7 +126,8 @@ impl Artifact {
             total,
             target,
             build: Rollout {
-                name: "build-".to_owned() + art_id,
+                name: art_id.to_owned(),
+                pipeline: "build-".to_owned() + art_id,
                 stats: ArtifactStatus::NotScheduled,
                 last_sched: "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().expect("Failed to parse datetime string to last_sched"),
                 accounts: Vec::new(),
@@ -96,7 +136,8 @@ impl Artifact {
                 manifest: String::new()
             },
             clean: Rollout {
-                name: "clean-".to_owned() + art_id,
+                name: art_id.to_owned(),
+                pipeline: "clean-".to_owned() + art_id,
                 stats: ArtifactStatus::NotScheduled,
                 last_sched: "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().expect("Failed to parse datetime string to last_sched"),
                 accounts: Vec::new(),
@@ -113,10 +154,48 @@ impl ArtifactRequest {
         for task in &mut self.build.tasks {
             task.name = format!("{}-{}", self.name, task.name);
         }
+        self.build.inject_account_env();
+        self.clean.inject_account_env();
         Ok(())
     }
 }
 
+impl DeployUnit {
+    /// The yaml of the Tekton pipeline `name` and its tasks.
+    pub(crate) fn to_manifest_yaml(&self, name: &str) -> error::Result<String> {
+        to_manifest_with_optional_args(name, self.tasks.clone(), self.params.clone(), self.results.clone()).to_yaml()
+    }
+
+    /// Add the env vars of the account fields to each step of the tasks, see `AccountEnv`.
+    fn inject_account_env(&mut self) {
+        let mut envs = Vec::new();
+        for acnt_ref in self.accounts.iter().flatten() {
+            for env in acnt_ref.env.iter().flatten() {
+                envs.push(manifest::TaskStepEnvKV {
+                    name: env.name.clone(),
+                    value: manifest::EnvValue::SecretKeyRef(manifest::SecretKeyRef {
+                        name: format!("acnt-$(params.art_id)-$(params.inst_id)-{}", acnt_ref.name),
+                        key: env.field.clone()
+                    })
+                });
+            }
+        }
+        if envs.is_empty() {
+            return;
+        }
+        for task in &mut self.tasks {
+            for step in &mut task.spec.steps {
+                let step_env = step.env.get_or_insert_with(Vec::new);
+                for env in &envs {
+                    if !step_env.iter().any(|e| e.name == env.name) {
+                        step_env.push(env.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl  TryFrom<ArtifactRequest> for Artifact {
     type Error = error::GeneralError;
     fn try_from(value: ArtifactRequest) -> Result<Self, Self::Error> {
@@ -134,6 +213,7 @@ impl  TryFrom<ArtifactRequest> for Artifact {
             target: value.target,
             build: Rollout {
                 name: value.name.to_owned(),
+                pipeline: build_name,
                 stats: ArtifactStatus::NotScheduled,
                 last_sched: "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().expect("Failed to parse datetime string to last_sched"),
                 accounts: value.build.accounts.unwrap_or(Vec::new()),
@@ -143,6 +223,7 @@ impl  TryFrom<ArtifactRequest> for Artifact {
             },
             clean: Rollout {
                 name: value.name.to_owned(),
+                pipeline: clean_name,
                 stats: ArtifactStatus::NotScheduled,
                 last_sched: "2012-12-12T12:12:12Z".parse::<DateTime<Local>>().expect("Failed to parse datetime string to last_sched"),
                 accounts: Vec::new(),
@@ -154,88 +235,70 @@ impl  TryFrom<ArtifactRequest> for Artifact {
     }
 }
 
-impl Rollout {
-    pub fn run(&mut self, _copies: i32) -> error::Result<Vec<String>> {
-        //TODO: The accounts and art_ref not ready will cause an error, then mark the artifact
-        //status to be pending, this should be rescheduled by another module `reconciller`.
-        //Update the last_sched
-        /*self.last_sched = Local::now();
-        let mut result = Vec::new();
-        let secrets = self.prepare_secrets(&self.name)?;
-        log::info!("applying secrets");
-        Self::apply_secrets(&secrets)?;
-        log::info!("applied.");
-        // to remove the instance.
-        // Check the ArtRefs 
-        // Apply secret
-        // Make sure the manifest is updated
-        log::info!("applying manifest: {}", self.manifest);
-        pipeline::apply(self.manifest.clone(), DEFAULT_NAMESPACE)?;
-        log::info!("applied.");
-        let pipeline_prefix = if copies >= 0 { "build" } else { copies = -copies; "clean" };
-        for _i in 0..copies {
-            let inst_id = format!("{}-{}", naming::word(None), naming::random_id());
-            // Prepare refs
-            let refs = self.prepare_refs(&self.name, &inst_id)?;
-            Self::apply_secrets(&refs)?;
-            // Prepare accounts
-            let accounts = self.prepare_accounts(&self.name, &inst_id)?;
-            Self::apply_secrets(&accounts)?;
-
-            let arg_art_id = format!("art_id={}", self.name);
-            let arg_inst_id = format!("inst_id={}", inst_id);
-            let params: Vec<&str> = vec![&arg_art_id, &arg_inst_id];
-            let run_name = pipeline::run(&format!("{}-{}", pipeline_prefix, self.name), DEFAULT_NAMESPACE, &params)?;
-            result.push(Instance{
-                id: inst_id,
-                art_id: self.name.clone(),
-                run_name,
-                dirt: false,
-                stat: InstanceStatus::Running,
-                results: None
-            });
-        }
-        */
-        let result = Vec::new();
-        Ok(result)
+/// The artifact as it is saved, with the manifests of its build and clean rendered.
+impl TryFrom<&dao::model::Artifact> for Artifact {
+    type Error = error::GeneralError;
+    fn try_from(value: &dao::model::Artifact) -> Result<Self, Self::Error> {
+        let build: DeployUnit = serde_json::from_value(value.build.clone())?;
+        let clean: DeployUnit = match &value.clean {
+            Some(clean) => serde_json::from_value(clean.clone())?,
+            None => DeployUnit::default()
+        };
+        Ok(Artifact {
+            id: value.name.clone(),
+            tags: HashMap::new(),
+            total: value.total,
+            target: value.target,
+            build: Rollout::new(&value.name, "build", build)?,
+            clean: Rollout::new(&value.name, "clean", clean)?
+        })
     }
+}
 
-    pub fn validate(&self) -> error::Result<()> {
-        Ok(())
+impl Rollout {
+    fn new(art_name: &str, prefix: &str, unit: DeployUnit) -> error::Result<Self> {
+        let pipeline = format!("{}-{}", prefix, art_name);
+        Ok(Rollout {
+            name: art_name.to_owned(),
+            manifest: unit.to_manifest_yaml(&pipeline)?,
+            pipeline,
+            stats: ArtifactStatus::NotScheduled,
+            last_sched: Local::now(),
+            accounts: unit.accounts.unwrap_or_default(),
+            secrets: unit.secrets.unwrap_or_default(),
+            art_refs: Vec::new()
+        })
     }
 
-    pub fn format(&mut self) -> error::Result<()> {
-        Ok(())
+    /// Apply the pipeline of the rollout along with its tasks to the executor.
+    pub fn apply(&self, executor: &dyn Executable, redactor: &Redactor) -> error::Result<()> {
+        executor.apply(&self.pipeline, &self.manifest, redactor)
     }
 
-    fn prepare_refs(&self, art_id: &str, inst_id: &str) -> error::Result<Vec<manifest::Secret>> {
-        //TODO: This function is going to claim the artifact that the artifact depends on, and then
-        // serialize the json result value as string if it is not a string
-        Ok(vec![manifest::Secret::new(format!("ref-{}-{}", art_id, inst_id), DEFAULT_NAMESPACE, HashMap::from([("user_id", "user123456"), ("secret", "{\"user_secret\": \"value1\"}")]))])
+    /// Start the pipeline run of the rollout for the instance, and return the name of the run. The
+    /// secrets, accounts and credentials of the instance must be applied beforehand, they are
+    /// found by the params `art_id` and `inst_id`.
+    pub fn run(&mut self, executor: &dyn Executable, inst_id: &str, redactor: &Redactor) -> error::Result<String> {
+        self.last_sched = Local::now();
+        let params = vec![
+            ("art_id".to_owned(), self.name.clone()),
+            ("inst_id".to_owned(), inst_id.to_owned())
+        ];
+        log::info!("starting {} for the instance {}", self.pipeline, inst_id);
+        executor.start(&self.pipeline, &params, redactor)
     }
 
-    fn prepare_secrets(&self, art_id: &str) -> error::Result<Vec<manifest::Secret>> {
-        //TODO: This is a mock function need to be implemented
-        Ok(vec![
-           manifest::Secret::new(format!("sec-{}-aws-route53", art_id), DEFAULT_NAMESPACE, HashMap::from([("user_id", "user123456"), ("secret", "user_secret")])),
-           manifest::Secret::new(format!("sec-{}-pivnet", art_id), DEFAULT_NAMESPACE, HashMap::from([("user_id", "pivnet123456"), ("pivnet-token", "user-token-123456")]))
-        ])
+    pub fn validate(&self) -> error::Result<()> {
+        Ok(())
     }
 
-    fn prepare_accounts(&self, art_id: &str, inst_id: &str) -> error::Result<Vec<manifest::Secret>> {
-        //TODO: This is a mock function need to be implemented
-        Ok(vec![manifest::Secret::new(format!("acnt-{}-{}-gcp-environment", art_id, inst_id), DEFAULT_NAMESPACE, HashMap::from([("user_id", "user123456"), ("secret", "user_secret")]))])
+    pub fn format(&mut self) -> error::Result<()> {
+        Ok(())
     }
 
-    fn apply_secrets(secrets: &Vec<manifest::Secret>) -> error::Result<()> {
-        let mut buff = String::new();
-        for sec in secrets {
-            buff.push_str("---\n");
-            let sec_yaml = serde_yaml::to_string(sec)?;
-            buff.push_str(&sec_yaml);
-        }
-        //pipeline::apply(buff, DEFAULT_NAMESPACE)?;
-        Ok(())
+    pub(crate) fn apply_secrets(executor: &dyn Executable, secrets: &Vec<manifest::Secret>) -> error::Result<()> {
+        let redactor = Redactor::new(secrets.iter().flat_map(|sec| sec.string_data.values()));
+        executor.apply_secrets(secrets, &redactor)
     }
 }
 
@@ -305,7 +368,8 @@ impl ToString for ArtifactStatus {
             Self::PendingAccount => "PendingAccount",
             Self::PendingArtRef => "PendingArtRef",
             Self::Failed => "Failed",
-            Self::Succeeded=> "Succeeded"
+            Self::Succeeded=> "Succeeded",
+            Self::Maintenance => "Maintenance"
         }.to_owned()
     }
 }
@@ -316,9 +380,10 @@ impl <R: AsRef<str>> From<R> for ArtifactStatus {
         match val_ref {
             "Running" => Self::Running,
             "PendingAccount" => Self::PendingAccount,
-            "PendingArfRef" => Self::PendingArtRef,
+            "PendingArtRef" => Self::PendingArtRef,
             "Failed" => Self::Failed,
             "Succeeded" => Self::Succeeded,
+            "Maintenance" => Self::Maintenance,
             _ => Self::NotScheduled
         }
     }
@@ -344,7 +409,7 @@ impl <'a>Validable for ArtifactValidator<'a> {
                 }
             }
             if !err_msg.is_empty() {
-                return Err(error::error(&err_msg));
+                return Err(error::bad_request(&err_msg));
             }
         }
 
@@ -353,13 +418,15 @@ impl <'a>Validable for ArtifactValidator<'a> {
             for account in accounts {
                 if !dao::AccountDao::exist_name(self.conn, &account.name)? {
                     err_msg += &format!("Unable to find the account: {}\n", account.name);
+                } else {
+                    err_msg += &check_account_env(self.conn, account)?;
                 }
 
                 //TODO: check if the account exists
                 //TODO: check if an account is available
             }
             if !err_msg.is_empty() {
-                return Err(error::error(&err_msg));
+                return Err(error::bad_request(&err_msg));
             }
         }
 
@@ -368,13 +435,15 @@ impl <'a>Validable for ArtifactValidator<'a> {
             for account in accounts {
                 if !dao::AccountDao::exist_name(self.conn, &account.name)? {
                     err_msg += &format!("Unable to find the account: {}\n", account.name);
+                } else {
+                    err_msg += &check_account_env(self.conn, account)?;
                 }
 
                 //TODO: check if the account exists
                 //TODO: check if an account is available
             }
             if !err_msg.is_empty() {
-                return Err(error::error(&err_msg));
+                return Err(error::bad_request(&err_msg));
             }
         }
 
@@ -383,10 +452,16 @@ impl <'a>Validable for ArtifactValidator<'a> {
             for secret in secrets {
                 if !dao::SecretDao::exist_name(self.conn, &secret.name)? {
                     err_msg += &format!("Unable to find the secret: {}\n", secret.name);
+                } else if let Some(version) = secret.version {
+                    let sec = dao::SecretDao::load_by_name(self.conn, &secret.name)?;
+                    // The versions of the external backends are checked at the time of resolving.
+                    if sec.backend == backend::POSTGRES && (version < 1 || version > sec.version) {
+                        err_msg += &format!("Unable to find the version {} of the secret: {}\n", version, secret.name);
+                    }
                 }
             }
             if !err_msg.is_empty() {
-                return Err(error::error(&err_msg));
+                return Err(error::bad_request(&err_msg));
             }
         }
 
@@ -394,6 +469,25 @@ impl <'a>Validable for ArtifactValidator<'a> {
     }
 }
 
+/// Check the env vars refer to the fields of the account. The account without fields has only
+/// the field `data`.
+fn check_account_env(conn: &mut PgConnection, account: &AccountRef) -> error::Result<String> {
+    let mut err_msg = String::new();
+    if let Some(envs) = &account.env {
+        let acnt = dao::AccountDao::load_by_name(conn, &account.name)?;
+        let names: Vec<String> = match super::AccountOps::fields(&acnt)? {
+            Some(fields) => fields.into_iter().map(|field| field.name).collect(),
+            None => vec![super::account::DATA_KEY.to_owned()]
+        };
+        for env in envs {
+            if !names.contains(&env.field) {
+                err_msg += &format!("Unable to find the field {} of the account: {}\n", env.field, account.name);
+            }
+        }
+    }
+    Ok(err_msg)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,94 @@
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use crate::crypto;
+
+/// The default and the max numbers of the records returned by a query.
+pub const DEFAULT_QUERY_LIMIT: i64 = 100;
+pub const MAX_QUERY_LIMIT: i64 = 1000;
+
+/// The resource an audited operation acts on. It is recorded as `kind/name`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Resource {
+    Artifact(String),
+    /// The credentials of an instance, as `artifact/instance`.
+    Credential(String, String),
+    Secret(String),
+    Account(String),
+    /// A unit of an account pool.
+    Unit(i32),
+    Token(String),
+    Team(String),
+    User(String),
+    /// The membership of the user in the team, as `team/user`.
+    Member(String, String),
+    /// The artifact id in the dead-letter list of the scheduler queue, or `*` for all of them.
+    DeadLetter(String)
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Artifact(name) => write!(f, "artifact/{}", name),
+            Self::Credential(art, inst) => write!(f, "credential/{}/{}", art, inst),
+            Self::Secret(name) => write!(f, "secret/{}", name),
+            Self::Account(name) => write!(f, "account/{}", name),
+            Self::Unit(id) => write!(f, "unit/{}", id),
+            Self::Token(name) => write!(f, "token/{}", name),
+            Self::Team(name) => write!(f, "team/{}", name),
+            Self::User(name) => write!(f, "user/{}", name),
+            Self::Member(team, user) => write!(f, "member/{}/{}", team, user),
+            Self::DeadLetter(art_id) => write!(f, "dead-letter/{}", art_id)
+        }
+    }
+}
+
+/// A record of the audit log as it is returned to the admins. The digests are the sha256 of the
+/// state of the resource before and after the operation, so the changes can be told without
+/// keeping the sensitive data in the log.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub actor: String,
+    pub actor_kind: String,
+    pub team: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub before_digest: Option<String>,
+    pub after_digest: Option<String>,
+    pub request_id: String,
+    pub created_at: Option<NaiveDateTime>
+}
+
+/// The filters of the audit log query, such as `?resource=artifact/pcf&action=art.update`.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub team: Option<String>,
+    pub action: Option<String>,
+    pub resource: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub limit: Option<i64>
+}
+
+/// The request id from the header `X-Request-Id`, or a random one if it is absent.
+pub fn request_id(header: Option<&str>) -> String {
+    match header.map(str::trim) {
+        Some(id) if !id.is_empty() => id.chars().take(128).collect(),
+        _ => crypto::random_hex(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_and_request_id() {
+        assert_eq!(Resource::Artifact("pcf".to_owned()).to_string(), "artifact/pcf");
+        assert_eq!(Resource::Credential("pcf".to_owned(), "cold-1234".to_owned()).to_string(), "credential/pcf/cold-1234");
+        assert_eq!(Resource::DeadLetter("12".to_owned()).to_string(), "dead-letter/12");
+        assert_eq!(request_id(Some("req-1")), "req-1");
+        assert_eq!(request_id(Some(" ")).len(), 16);
+        assert_eq!(request_id(None).len(), 16);
+    }
+}
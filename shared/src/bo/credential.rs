@@ -0,0 +1,197 @@
+//! The credentials generated fresh for each instance, so no two environments share a password.
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::command;
+use crate::error;
+
+/// A credential that is generated for each instance of the artifact. It is injected to the
+/// build run as the Kubernetes Secret `gen-{artifact}-{instance}-{name}`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GeneratedSecret {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: GeneratedKind
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum GeneratedKind {
+    /// The key of the secret is `password`.
+    #[serde(rename(serialize = "password", deserialize = "password"))]
+    Password(PasswordPolicy),
+    /// The keys of the secret are `private_key` and `public_key`.
+    #[serde(rename(serialize = "sshKeypair", deserialize = "sshKeypair"))]
+    SshKeypair,
+    /// The keys of the secret are `tls.crt` and `tls.key`.
+    #[serde(rename(serialize = "tlsCert", deserialize = "tlsCert"))]
+    TlsCert(TlsPolicy)
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    #[serde(default = "default_password_length")]
+    pub length: usize,
+    #[serde(default = "default_true")]
+    pub lowercase: bool,
+    #[serde(default = "default_true")]
+    pub uppercase: bool,
+    #[serde(default = "default_true")]
+    pub digits: bool,
+    #[serde(default)]
+    pub symbols: bool
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TlsPolicy {
+    #[serde(rename(serialize = "commonName", deserialize = "commonName"))]
+    pub common_name: String,
+    #[serde(default = "default_tls_days")]
+    pub days: u32
+}
+
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!#%+-.:=?@^_~";
+
+fn default_password_length() -> usize { 24 }
+fn default_true() -> bool { true }
+fn default_tls_days() -> u32 { 90 }
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            length: default_password_length(),
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: false
+        }
+    }
+}
+
+impl GeneratedSecret {
+    pub fn generate(&self) -> error::Result<HashMap<String, String>> {
+        match &self.kind {
+            GeneratedKind::Password(policy) => Ok(HashMap::from([("password".to_owned(), password(policy)?)])),
+            GeneratedKind::SshKeypair => ssh_keypair(),
+            GeneratedKind::TlsCert(policy) => tls_cert(policy)
+        }
+    }
+}
+
+/// Generate the password with at least one character of each of the enabled classes.
+pub fn password(policy: &PasswordPolicy) -> error::Result<String> {
+    let classes: Vec<&[u8]> = [(policy.lowercase, LOWERCASE), (policy.uppercase, UPPERCASE), (policy.digits, DIGITS), (policy.symbols, SYMBOLS)]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, class)| class)
+        .collect();
+    if classes.is_empty() {
+        return Err(error::bad_request("The password policy enables no character class"));
+    }
+    if policy.length < classes.len() {
+        return Err(error::bad_request(&format!("The password length must be at least {}", classes.len())));
+    }
+    let mut rng = rand::rngs::OsRng;
+    let mut chars: Vec<u8> = classes.iter().map(|class| class[rng.gen_range(0..class.len())]).collect();
+    let all: Vec<u8> = classes.concat();
+    while chars.len() < policy.length {
+        chars.push(all[rng.gen_range(0..all.len())]);
+    }
+    chars.shuffle(&mut rng);
+    Ok(String::from_utf8(chars).expect("The password characters are ascii"))
+}
+
+fn ssh_keypair() -> error::Result<HashMap<String, String>> {
+    let dir = TempDir::new("ssh")?;
+    let key_path = dir.path.join("id_ed25519");
+    let key_file = key_path.to_string_lossy().to_string();
+    let output = command::command_with_args("ssh-keygen", ["-q", "-t", "ed25519", "-N", "", "-C", "train", "-f", &key_file]).output()?;
+    if !output.status.success() {
+        return Err(error::error(&format!("Failed to generate the ssh keypair: {}", command::stringfy(&output.stderr))));
+    }
+    Ok(HashMap::from([
+        ("private_key".to_owned(), std::fs::read_to_string(&key_path)?),
+        ("public_key".to_owned(), std::fs::read_to_string(dir.path.join("id_ed25519.pub"))?)
+    ]))
+}
+
+fn tls_cert(policy: &TlsPolicy) -> error::Result<HashMap<String, String>> {
+    let dir = TempDir::new("tls")?;
+    let key_file = dir.path.join("tls.key").to_string_lossy().to_string();
+    let crt_file = dir.path.join("tls.crt").to_string_lossy().to_string();
+    let subject = format!("/CN={}", policy.common_name);
+    let days = policy.days.to_string();
+    let output = command::command_with_args("openssl", ["req", "-x509", "-newkey", "rsa:2048", "-nodes",
+        "-keyout", &key_file, "-out", &crt_file, "-days", &days, "-subj", &subject]).output()?;
+    if !output.status.success() {
+        return Err(error::error(&format!("Failed to generate the tls certificate: {}", command::stringfy(&output.stderr))));
+    }
+    Ok(HashMap::from([
+        ("tls.key".to_owned(), std::fs::read_to_string(&key_file)?),
+        ("tls.crt".to_owned(), std::fs::read_to_string(&crt_file)?)
+    ]))
+}
+
+/// The directory holding the generated key files, it is removed once the keys are read.
+struct TempDir {
+    path: std::path::PathBuf
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> error::Result<Self> {
+        let mut bytes = [0u8; 8];
+        rand::rngs::OsRng.fill(&mut bytes);
+        let path = std::env::temp_dir().join(format!("train-{}-{}", prefix, hex::encode(bytes)));
+        std::fs::create_dir(&path)?;
+        Ok(TempDir { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy { length: 32, symbols: true, ..Default::default() };
+        let pwd = password(&policy).unwrap();
+        assert_eq!(pwd.len(), 32);
+        assert!(pwd.bytes().any(|c| LOWERCASE.contains(&c)));
+        assert!(pwd.bytes().any(|c| UPPERCASE.contains(&c)));
+        assert!(pwd.bytes().any(|c| DIGITS.contains(&c)));
+        assert!(pwd.bytes().any(|c| SYMBOLS.contains(&c)));
+        assert_ne!(pwd, password(&policy).unwrap());
+
+        let digits_only = PasswordPolicy { length: 6, lowercase: false, uppercase: false, ..Default::default() };
+        assert!(password(&digits_only).unwrap().bytes().all(|c| DIGITS.contains(&c)));
+        let nothing = PasswordPolicy { length: 6, lowercase: false, uppercase: false, digits: false, symbols: false };
+        assert!(password(&nothing).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_generated_secret() {
+        let json = r#"[{"name":"admin","type":"password","length":16},{"name":"ssh","type":"sshKeypair"},{"name":"tls","type":"tlsCert","commonName":"opsman.example.com"}]"#;
+        let generated: Vec<GeneratedSecret> = serde_json::from_str(json).unwrap();
+        assert_eq!(generated[0].kind, GeneratedKind::Password(PasswordPolicy { length: 16, ..Default::default() }));
+        assert_eq!(generated[1].kind, GeneratedKind::SshKeypair);
+        assert_eq!(generated[2].kind, GeneratedKind::TlsCert(TlsPolicy { common_name: "opsman.example.com".to_owned(), days: 90 }));
+    }
+
+    #[test]
+    fn test_ssh_keypair() {
+        let ssh = GeneratedSecret { name: "ssh".to_owned(), kind: GeneratedKind::SshKeypair };
+        let data = ssh.generate().unwrap();
+        assert!(data["private_key"].contains("PRIVATE KEY"));
+        assert!(data["public_key"].starts_with("ssh-ed25519 "));
+    }
+}
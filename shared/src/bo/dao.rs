@@ -1,14 +1,26 @@
 mod artifact_dao;
+mod audit_dao;
 mod team_dao;
 mod account_dao;
 mod secret_dao;
+mod credential_dao;
+mod instance_dao;
+mod outbox_dao;
+mod token_dao;
+mod user_dao;
 pub(crate) mod model;
 mod schema;
 
 pub use artifact_dao::ArtifactDao;
+pub use audit_dao::AuditDao;
 pub use account_dao::AccountDao;
 pub use secret_dao::SecretDao;
+pub use credential_dao::CredentialDao;
+pub use instance_dao::InstanceDao;
+pub use outbox_dao::OutboxDao;
 pub use team_dao::TeamDao;
+pub use token_dao::ApiTokenDao;
+pub use user_dao::UserDao;
 
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
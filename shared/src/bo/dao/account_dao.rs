@@ -5,9 +5,249 @@ use super::model;
 pub struct AccountDao;
 
 impl AccountDao {
+    pub fn create(conn: &mut PgConnection, acnt: model::Account) -> error::Result<i32> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(account)
+            .values(&acnt)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_name(conn: &mut PgConnection, acnt_name: &str) -> error::Result<model::Account> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        account.filter(name.eq(acnt_name))
+            .select(model::Account::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_name_for_update(conn: &mut PgConnection, acnt_name: &str) -> error::Result<model::Account> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        account.filter(name.eq(acnt_name))
+            .select(model::Account::as_select())
+            .for_update()
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_id(conn: &mut PgConnection, acnt_id: i32) -> error::Result<model::Account> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        account.filter(id.eq(acnt_id))
+            .select(model::Account::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_id_for_update(conn: &mut PgConnection, acnt_id: i32) -> error::Result<model::Account> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        account.filter(id.eq(acnt_id))
+            .select(model::Account::as_select())
+            .for_update()
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_by_owner(conn: &mut PgConnection, team_id: i32) -> error::Result<Vec<model::Account>> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        account.filter(owner.eq(team_id))
+            .order(name.asc())
+            .select(model::Account::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn update(conn: &mut PgConnection, acnt: &model::Account) -> error::Result<usize> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(account)
+            .filter(id.eq(acnt.id.ok_or_else(|| error::error("Null account id"))?))
+            .set(acnt)
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Add the deltas to the `total` and `in_stock` of the account.
+    pub fn adjust_stock(conn: &mut PgConnection, acnt_id: i32, total_delta: i32, in_stock_delta: i32) -> error::Result<usize> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(account.filter(id.eq(acnt_id)))
+            .set((total.eq(total + total_delta), in_stock.eq(in_stock + in_stock_delta)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete(conn: &mut PgConnection, acnt_id: i32) -> error::Result<usize> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(account.filter(id.eq(acnt_id))).execute(conn).map_err(|err|err.into())
+    }
+
+    /// Delete the account pools owned by the team, along with the grants of them to the other
+    /// teams. The units and their usage are deleted by the DB.
+    pub fn delete_by_owner(conn: &mut PgConnection, team_id: i32) -> error::Result<usize> {
+        use super::schema::{account, acnt_ctl};
+        use diesel::prelude::*;
+        let owned = account::table.filter(account::owner.eq(team_id)).select(account::id.nullable());
+        diesel::delete(acnt_ctl::table.filter(acnt_ctl::account_id.eq_any(owned))).execute(conn)?;
+        diesel::delete(account::table.filter(account::owner.eq(team_id))).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
+        use super::schema::account::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(account).execute(conn).map_err(|err|err.into())
+    }
+
     pub fn exist_name(conn: &mut PgConnection, acnt_name: &str) -> error::Result<bool> {
         use super::schema::account::dsl::*;
         use diesel::prelude::*;
         diesel::dsl::select(diesel::dsl::exists(account.filter(name.eq(acnt_name)))).get_result(conn).map_err(|err| err.into())
     }
+
+    pub fn create_unit(conn: &mut PgConnection, unit: model::AccountUnit) -> error::Result<i32> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(acnt_unit)
+            .values(&unit)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_unit(conn: &mut PgConnection, unit_id: i32) -> error::Result<model::AccountUnit> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        acnt_unit.filter(id.eq(unit_id))
+            .select(model::AccountUnit::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_units(conn: &mut PgConnection, acnt_id: i32) -> error::Result<Vec<model::AccountUnit>> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        acnt_unit.filter(account_id.eq(acnt_id))
+            .order(id.asc())
+            .select(model::AccountUnit::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Find a unit of the account in the status. The account row should be locked by
+    /// `load_by_name_for_update` in the same transaction.
+    pub fn find_unit_in_stat(conn: &mut PgConnection, acnt_id: i32, unit_stat: &str) -> error::Result<Option<model::AccountUnit>> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        acnt_unit.filter(account_id.eq(acnt_id).and(stat.eq(unit_stat)))
+            .order(id.asc())
+            .select(model::AccountUnit::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|err| err.into())
+    }
+
+    pub fn update_unit_stat(conn: &mut PgConnection, unit_id: i32, unit_stat: &str) -> error::Result<usize> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(acnt_unit.filter(id.eq(unit_id)))
+            .set(stat.eq(unit_stat))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Move the unit to the status, e.g. `Quarantined`, with the reason.
+    pub fn quarantine_unit(conn: &mut PgConnection, unit_id: i32, unit_stat: &str, unit_reason: &str) -> error::Result<usize> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(acnt_unit.filter(id.eq(unit_id)))
+            .set((stat.eq(unit_stat), quarantined_at.eq(diesel::dsl::now), reason.eq(unit_reason)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Move the unit to the status, e.g. `Available`, and clear the quarantine.
+    pub fn release_unit(conn: &mut PgConnection, unit_id: i32, unit_stat: &str) -> error::Result<usize> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(acnt_unit.filter(id.eq(unit_id)))
+            .set((stat.eq(unit_stat), quarantined_at.eq(None::<chrono::NaiveDateTime>), reason.eq(None::<String>)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The units in the status of all the accounts.
+    pub fn list_units_in_stat(conn: &mut PgConnection, unit_stat: &str) -> error::Result<Vec<model::AccountUnit>> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        acnt_unit.filter(stat.eq(unit_stat))
+            .order(id.asc())
+            .select(model::AccountUnit::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete_unit(conn: &mut PgConnection, unit_id: i32) -> error::Result<usize> {
+        use super::schema::acnt_unit::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(acnt_unit.filter(id.eq(unit_id))).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn create_usage(conn: &mut PgConnection, usage: model::AccountUsage) -> error::Result<i32> {
+        use super::schema::acnt_usage::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(acnt_usage)
+            .values(&usage)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The check-outs of the instance that are not checked in yet.
+    pub fn list_open_usage(conn: &mut PgConnection, art_id: i32, instance_id: &str) -> error::Result<Vec<model::AccountUsage>> {
+        use super::schema::acnt_usage::dsl::*;
+        use diesel::prelude::*;
+        acnt_usage.filter(artifact_id.eq(art_id).and(inst_id.eq(instance_id)).and(checked_in_at.is_null()))
+            .order(id.asc())
+            .select(model::AccountUsage::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_usage(conn: &mut PgConnection, acnt_id: i32) -> error::Result<Vec<model::AccountUsage>> {
+        use super::schema::acnt_usage::dsl::*;
+        use diesel::prelude::*;
+        acnt_usage.filter(account_id.eq(acnt_id))
+            .order(id.asc())
+            .select(model::AccountUsage::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The latest check-out of the unit.
+    pub fn last_usage(conn: &mut PgConnection, unit: i32) -> error::Result<Option<model::AccountUsage>> {
+        use super::schema::acnt_usage::dsl::*;
+        use diesel::prelude::*;
+        acnt_usage.filter(unit_id.eq(unit))
+            .order(id.desc())
+            .select(model::AccountUsage::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|err| err.into())
+    }
+
+    pub fn close_usage(conn: &mut PgConnection, usage_id: i32) -> error::Result<usize> {
+        use super::schema::acnt_usage::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(acnt_usage.filter(id.eq(usage_id)))
+            .set(checked_in_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
 }
@@ -47,24 +47,127 @@ impl ArtifactDao {
         Ok(())
     }
 
-    pub fn update_build_script(_conn: &mut PgConnection, _art_id: i32, _rollout: serde_json::Value) -> error::Result<i32> {
-        Ok(0)
+    /// The status of the artifact and the account pool blocking it, if it is `PendingAccount`.
+    pub fn load_stat(conn: &mut PgConnection, art_id: i32) -> error::Result<(String, Option<String>)> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        artifact.filter(id.eq(art_id))
+            .select((stat, blocked_by))
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn update_stat(conn: &mut PgConnection, art_id: i32, art_stat: &str, pool: Option<&str>) -> error::Result<usize> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(artifact.filter(id.eq(art_id)))
+            .set((stat.eq(art_stat), blocked_by.eq(pool)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Add to the consecutive build failures of the artifact, and return them.
+    pub fn add_failures(conn: &mut PgConnection, art_id: i32, count: i32) -> error::Result<i32> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(artifact.filter(id.eq(art_id)))
+            .set(failures.eq(failures + count))
+            .returning(failures)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn reset_failures(conn: &mut PgConnection, art_id: i32) -> error::Result<usize> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(artifact.filter(id.eq(art_id).and(failures.ne(0))))
+            .set(failures.eq(0))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_failures(conn: &mut PgConnection, art_id: i32) -> error::Result<i32> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        artifact.filter(id.eq(art_id))
+            .select(failures)
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Record the fencing token of the lease on the artifact. It returns false if a lease with a
+    /// higher token was taken since, or the artifact is deleted.
+    pub fn fence(conn: &mut PgConnection, art_id: i32, token: i64) -> error::Result<bool> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        let updated = diesel::update(artifact.filter(id.eq(art_id).and(fence.le(token))))
+            .set(fence.eq(token))
+            .execute(conn)?;
+        Ok(updated == 1)
+    }
+
+    /// The ids of the artifacts in the status and blocked by the account pool.
+    pub fn list_blocked_by(conn: &mut PgConnection, art_stat: &str, pool: &str) -> error::Result<Vec<i32>> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        artifact.filter(stat.eq(art_stat).and(blocked_by.eq(pool)))
+            .order(id.asc())
+            .select(id)
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The artifacts whose build or clean refers to the secret, matched by the indexes on the json.
+    pub fn list_by_secret(conn: &mut PgConnection, sec_name: &str) -> error::Result<Vec<model::Artifact>> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        use diesel::sql_types::{Bool, Text};
+        let refs = serde_json::json!({"secrets": [{"name": sec_name}]}).to_string();
+        artifact
+            .filter(diesel::dsl::sql::<Bool>("(build::jsonb @> ")
+                .bind::<Text, _>(refs.clone())
+                .sql("::jsonb OR clean::jsonb @> ")
+                .bind::<Text, _>(refs)
+                .sql("::jsonb)"))
+            .select(model::Artifact::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn count_by_team(conn: &mut PgConnection, owner_id: i32) -> error::Result<i64> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        artifact.filter(team_id.eq(owner_id)).count().get_result(conn).map_err(|err| err.into())
     }
 
-    pub fn update_clean_script(_conn: &mut PgConnection, _art_id: i32, _rollout: serde_json::Value) -> error::Result<i32> {
-        Ok(0)
+    pub fn list_ids(conn: &mut PgConnection) -> error::Result<Vec<i32>> {
+        use super::schema::artifact::dsl::*;
+        use diesel::prelude::*;
+        artifact.order(id.asc()).select(id).load(conn).map_err(|err| err.into())
     }
 
-    pub fn delete_by_id(conn: &mut PgConnection, art_id: i32) -> error::Result<usize> {
+    pub fn list_ids_by_team(conn: &mut PgConnection, owner_id: i32) -> error::Result<Vec<i32>> {
+        use super::schema::artifact::dsl::*;
         use diesel::prelude::*;
+        artifact.filter(team_id.eq(owner_id)).select(id).load(conn).map_err(|err| err.into())
+    }
+
+    /// The summed `total` of the artifacts of the team, leaving out the artifact being updated.
+    pub fn sum_total_by_team(conn: &mut PgConnection, owner_id: i32, except: Option<&str>) -> error::Result<i64> {
         use super::schema::artifact::dsl::*;
-        diesel::delete(artifact.filter(id.eq(art_id))).execute(conn).map_err(|err|err.into())
+        use diesel::prelude::*;
+        let mut query = artifact.filter(team_id.eq(owner_id)).into_boxed();
+        if let Some(except) = except {
+            query = query.filter(name.ne(except));
+        }
+        let sum: Option<i64> = query.select(diesel::dsl::sum(total)).first(conn)?;
+        Ok(sum.unwrap_or(0))
     }
 
-    pub fn delete_by_name(conn: &mut PgConnection, art_name: &str) -> error::Result<usize> {
+    pub fn delete_by_team(conn: &mut PgConnection, owner_id: i32) -> error::Result<usize> {
         use diesel::prelude::*;
         use super::schema::artifact::dsl::*;
-        diesel::delete(artifact.filter(name.eq(art_name))).execute(conn).map_err(|err|err.into())
+        diesel::delete(artifact.filter(team_id.eq(owner_id))).execute(conn).map_err(|err|err.into())
     }
 
     pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
@@ -88,12 +191,7 @@ mod tests {
     use crate::bo::dao::TeamDao;
 
     fn run_case(conn: &mut PgConnection, case: impl FnOnce(&mut PgConnection) -> error::Result<()>)-> error::Result<()>  {
-        let team = model::Team {
-            id: None,
-            name: "Team C".to_owned(),
-            token: "234567".to_owned(),
-            desp: None
-        };
+        let team = model::Team::new("Team C".to_owned(), "234567", None);
         match TeamDao::create(conn, team) {
             Ok(team_id) => {
                 case(conn).expect("Fail to run the test case");
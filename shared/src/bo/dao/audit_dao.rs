@@ -0,0 +1,47 @@
+use crate::error;
+use diesel::pg::PgConnection;
+use super::model;
+
+pub struct AuditDao;
+
+impl AuditDao {
+    pub fn create(conn: &mut PgConnection, record: model::AuditLog) -> error::Result<i64> {
+        use super::schema::audit_log::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(audit_log)
+            .values(&record)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The records matching the filter, the latest first.
+    pub fn query(conn: &mut PgConnection, filter: &model::AuditFilter) -> error::Result<Vec<model::AuditLog>> {
+        use super::schema::audit_log::dsl::*;
+        use diesel::prelude::*;
+        let mut query = audit_log.into_boxed();
+        if let Some(value) = filter.actor {
+            query = query.filter(actor.eq(value));
+        }
+        if let Some(value) = filter.team {
+            query = query.filter(team.eq(value));
+        }
+        if let Some(value) = filter.action {
+            query = query.filter(action.eq(value));
+        }
+        if let Some(value) = filter.resource {
+            query = query.filter(resource.eq(value));
+        }
+        if let Some(value) = filter.since {
+            query = query.filter(created_at.ge(value));
+        }
+        if let Some(value) = filter.until {
+            query = query.filter(created_at.lt(value));
+        }
+        query.order((created_at.desc(), id.desc()))
+            .limit(filter.limit)
+            .select(model::AuditLog::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+}
@@ -0,0 +1,39 @@
+use crate::error;
+use diesel::pg::PgConnection;
+use super::model;
+
+pub struct CredentialDao;
+
+impl CredentialDao {
+    pub fn create(conn: &mut PgConnection, cred: model::InstCredential) -> error::Result<i32> {
+        use super::schema::inst_cred::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(inst_cred)
+            .values(&cred)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_by_instance(conn: &mut PgConnection, art_id: i32, instance_id: &str) -> error::Result<Vec<model::InstCredential>> {
+        use super::schema::inst_cred::dsl::*;
+        use diesel::prelude::*;
+        inst_cred.filter(artifact_id.eq(art_id).and(inst_id.eq(instance_id)))
+            .order(name.asc())
+            .select(model::InstCredential::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete_by_instance(conn: &mut PgConnection, art_id: i32, instance_id: &str) -> error::Result<usize> {
+        use super::schema::inst_cred::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(inst_cred.filter(artifact_id.eq(art_id).and(inst_id.eq(instance_id)))).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn count_by_artifacts(conn: &mut PgConnection, art_ids: &[i32]) -> error::Result<i64> {
+        use super::schema::inst_cred::dsl::*;
+        use diesel::prelude::*;
+        inst_cred.filter(artifact_id.eq_any(art_ids)).count().get_result(conn).map_err(|err| err.into())
+    }
+}
@@ -4,6 +4,22 @@ use crate::error;
 
 use std::collections::HashMap;
 
+/// The fields of all the instances of the artifacts in KEYS, flattened.
+const MANY_OF_SCRIPT: &str = r"
+local fields = {}
+for _, art_id in ipairs(KEYS) do
+  for _, id in ipairs(redis.call('SMEMBERS', 'instance:' .. art_id)) do
+    local record = redis.call('HMGET', 'instance:' .. art_id .. ':' .. id, 'id', 'art_id', 'run_name', 'dirt', 'stat')
+    if record[1] then
+      for _, field in ipairs(record) do
+        table.insert(fields, field)
+      end
+    end
+  end
+end
+return fields
+";
+
 pub struct InstanceDao;
 
 impl InstanceDao {
@@ -40,6 +56,27 @@ impl InstanceDao {
         Ok(result)
     }
 
+    /// The instances of all the artifacts in one round trip, without their results.
+    pub fn many_of(art_ids: &[String], conn: &mut dyn ConnectionLike) -> error::Result<Vec<Instance>> {
+        if art_ids.is_empty() { return Ok(Vec::new()) }
+        let script = redis::Script::new(MANY_OF_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        for art_id in art_ids {
+            invocation.key(art_id);
+        }
+        let fields: Vec<String> = invocation.invoke(conn)?;
+        fields.chunks(5)
+            .map(|r| Ok(Instance {
+                id: r[0].clone(),
+                art_id: r[1].clone(),
+                run_name: r[2].clone(),
+                dirt: r[3].parse().map_err(|err| error::error(&format!("Failed to parse value to bool: {}", err)))?,
+                stat: r[4].clone().into(),
+                results: None
+            }))
+            .collect()
+    }
+
     pub fn delete(id: &str, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<()> {
         redis::pipe()
             .del(format!("instance:{}:{}:results", art_id, id)).ignore()
@@ -53,11 +90,6 @@ impl InstanceDao {
         Self::persist(instance, true, conn)
     }
 
-    pub fn update_stats(id: &str, art_id: &str, stats: &str, conn: &mut dyn ConnectionLike) -> error::Result<()> {
-        redis::Cmd::hset(format!("instance:{}:{}", art_id, id), "stat", stats).execute(conn);
-        Ok(())
-    }
-
     pub fn save(instance: Instance, conn: &mut dyn ConnectionLike) -> error::Result<()> {
         Self::persist(instance, false, conn)
     }
@@ -93,7 +125,7 @@ impl InstanceDao {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::artifact::instance::InstanceStatus;
+    use crate::bo::instance::InstanceStatus;
 
     #[test]
     fn test_save_and_load_instance() {
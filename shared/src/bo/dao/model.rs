@@ -1,8 +1,10 @@
 use diesel::prelude::*;
+use chrono::NaiveDateTime;
 use serde_json;
 //use crate::artifact::ArtifactStatus;
 
 use super::schema;
+use crate::crypto;
 
 #[derive(Insertable)]
 #[diesel(table_name=schema::artifact)]
@@ -27,12 +29,235 @@ pub struct Artifact {
 #[derive(Queryable, Selectable, Insertable, AsChangeset)]
 #[diesel(table_name=schema::team)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
 pub struct Team {
     #[diesel(deserialize_as = i32)]
     pub id: Option<i32>,
     pub name: String,
-    pub token: String,
-    pub desp: Option<String>
+    /// The salted hash of the token, see `crypto::hash_token`. The token itself is not saved.
+    pub token_hash: String,
+    pub desp: Option<String>,
+    pub token_prefix: String,
+    pub salt: String,
+    /// The token before the last rotation, it is valid until `prev_expires_at`.
+    pub prev_token_hash: Option<String>,
+    pub prev_token_prefix: Option<String>,
+    pub prev_salt: Option<String>,
+    pub prev_expires_at: Option<NaiveDateTime>,
+    /// The tier of the team from 0 to `team::MAX_TIER`, see `ArtifactOps::priority`.
+    pub tier: i32
+}
+
+impl Team {
+    /// The team authenticated by the token.
+    pub fn new(name: String, token: &str, desp: Option<String>) -> Self {
+        let salt = crypto::generate_salt();
+        Team {
+            id: None,
+            name,
+            token_hash: crypto::hash_token(&salt, token),
+            desp,
+            token_prefix: crypto::token_prefix(token).to_owned(),
+            salt,
+            prev_token_hash: None,
+            prev_token_prefix: None,
+            prev_salt: None,
+            prev_expires_at: None,
+            tier: 0
+        }
+    }
+
+    /// Check the token is the current token, or the previous token in its grace period.
+    pub fn verify(&self, token: &str) -> bool {
+        if crypto::verify_token(&self.salt, token, &self.token_hash) {
+            return true;
+        }
+        match (&self.prev_salt, &self.prev_token_hash, self.prev_expires_at) {
+            (Some(salt), Some(hash), Some(expires_at)) => expires_at > chrono::Utc::now().naive_utc() && crypto::verify_token(salt, token, hash),
+            _ => false
+        }
+    }
+
+    /// Replace the token by the new one. The current token stays valid for `grace_sec` seconds if
+    /// it is given, otherwise it is revoked at once.
+    pub fn rotate(&mut self, token: &str, grace_sec: Option<i64>) {
+        let salt = crypto::generate_salt();
+        let hash = crypto::hash_token(&salt, token);
+        let prefix = crypto::token_prefix(token).to_owned();
+        match grace_sec {
+            Some(sec) if sec > 0 => {
+                self.prev_token_hash = Some(std::mem::replace(&mut self.token_hash, hash));
+                self.prev_token_prefix = Some(std::mem::replace(&mut self.token_prefix, prefix));
+                self.prev_salt = Some(std::mem::replace(&mut self.salt, salt));
+                self.prev_expires_at = Some(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(sec));
+            },
+            _ => {
+                self.token_hash = hash;
+                self.token_prefix = prefix;
+                self.salt = salt;
+                self.prev_token_hash = None;
+                self.prev_token_prefix = None;
+                self.prev_salt = None;
+                self.prev_expires_at = None;
+            }
+        }
+    }
+}
+
+/// The limits of a team. A `None` limit is unlimited.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Default)]
+#[diesel(table_name=schema::quota)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+pub struct Quota {
+    pub team_id: i32,
+    pub max_artifacts: Option<i32>,
+    pub max_total: Option<i32>,
+    pub max_running: Option<i32>,
+    pub max_borrowed: Option<i32>
+}
+
+/// A named token of the team limited to its scopes. Like the team token, only the salted hash is
+/// saved.
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::api_token)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiToken {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub team_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub salt: String,
+    /// The `Scope`s granted to the token.
+    pub scopes: serde_json::Value,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+impl ApiToken {
+    pub fn new(team_id: i32, name: String, token: &str, scopes: serde_json::Value, expires_at: Option<NaiveDateTime>) -> Self {
+        let salt = crypto::generate_salt();
+        ApiToken {
+            id: None,
+            team_id,
+            name,
+            token_hash: crypto::hash_token(&salt, token),
+            token_prefix: crypto::token_prefix(token).to_owned(),
+            salt,
+            scopes,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+            created_at: None
+        }
+    }
+
+    /// Check the token matches, and it is neither revoked nor expired.
+    pub fn verify(&self, token: &str) -> bool {
+        let now = chrono::Utc::now().naive_utc();
+        self.revoked_at.is_none()
+            && self.expires_at.is_none_or(|expires_at| expires_at > now)
+            && crypto::verify_token(&self.salt, token, &self.token_hash)
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct User {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub name: String,
+    /// The global admin can do everything in all the teams.
+    pub admin: bool,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+/// The `Role` of the user in the team. The token of the membership acts for the user in the team,
+/// only the salted hash of it is saved.
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::membership)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Membership {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub user_id: i32,
+    pub team_id: i32,
+    pub role: String,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub salt: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+impl Membership {
+    pub fn new(user_id: i32, team_id: i32, role: String, token: &str) -> Self {
+        let salt = crypto::generate_salt();
+        Membership {
+            id: None,
+            user_id,
+            team_id,
+            role,
+            token_hash: crypto::hash_token(&salt, token),
+            token_prefix: crypto::token_prefix(token).to_owned(),
+            salt,
+            created_at: None
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> bool {
+        crypto::verify_token(&self.salt, token, &self.token_hash)
+    }
+}
+
+/// The filters of the audit log. The absent filters match everything.
+#[derive(Debug, Default)]
+pub struct AuditFilter<'a> {
+    pub actor: Option<&'a str>,
+    pub team: Option<&'a str>,
+    pub action: Option<&'a str>,
+    pub resource: Option<&'a str>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub limit: i64
+}
+
+/// A record of the audit log. The records are never updated or deleted.
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditLog {
+    #[diesel(deserialize_as = i64)]
+    pub id: Option<i64>,
+    pub actor: String,
+    pub actor_kind: String,
+    pub team: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub before_digest: Option<String>,
+    pub after_digest: Option<String>,
+    pub request_id: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::sched_outbox)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SchedOutbox {
+    #[diesel(deserialize_as = i64)]
+    pub id: Option<i64>,
+    pub artifact_id: i32,
+    pub reason: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
 }
 
 #[derive(Queryable, Selectable, Insertable, AsChangeset)]
@@ -46,7 +271,39 @@ pub struct Account {
     pub in_stock: i32,
     pub data: String,
     pub owner: Option<i32>,
-    pub desp: Option<String>
+    pub desp: Option<String>,
+    /// The `AccountField`s the data of the units are validated against.
+    pub fields: Option<serde_json::Value>,
+    /// The `DeployUnit` run against a quarantined unit to remove the resources left in it.
+    pub scrub: Option<serde_json::Value>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::acnt_unit)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AccountUnit {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub account_id: i32,
+    pub data: String,
+    pub stat: String,
+    pub quarantined_at: Option<NaiveDateTime>,
+    pub reason: Option<String>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::acnt_usage)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AccountUsage {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub unit_id: i32,
+    pub account_id: i32,
+    pub artifact_id: i32,
+    pub inst_id: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub checked_out_at: Option<NaiveDateTime>,
+    pub checked_in_at: Option<NaiveDateTime>
 }
 
 #[derive(Queryable, Selectable, Insertable, AsChangeset)]
@@ -58,5 +315,49 @@ pub struct Secret {
     pub name: String,
     pub data: String,
     pub owner: Option<i32>,
-    pub desp: Option<String>
+    pub desp: Option<String>,
+    pub version: i32,
+    pub backend: String,
+    pub path: Option<String>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::sec_version)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SecretVersion {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub secret_id: i32,
+    pub version: i32,
+    pub data: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::sec_usage)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SecretUsage {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub artifact_id: i32,
+    pub inst_id: String,
+    pub secret_id: i32,
+    pub version: i32,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name=schema::inst_cred)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct InstCredential {
+    #[diesel(deserialize_as = i32)]
+    pub id: Option<i32>,
+    pub artifact_id: i32,
+    pub inst_id: String,
+    pub name: String,
+    pub data: String,
+    #[diesel(deserialize_as = NaiveDateTime)]
+    pub created_at: Option<NaiveDateTime>
 }
@@ -0,0 +1,48 @@
+use crate::error;
+use diesel::pg::PgConnection;
+use super::model;
+
+pub struct OutboxDao;
+
+impl OutboxDao {
+    pub fn create(conn: &mut PgConnection, record: model::SchedOutbox) -> error::Result<i64> {
+        use super::schema::sched_outbox::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(sched_outbox)
+            .values(&record)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Lock the oldest `limit` records, the records locked by other transactions are skipped so
+    /// the drainers never block each other.
+    pub fn lock_oldest(conn: &mut PgConnection, limit: i64) -> error::Result<Vec<model::SchedOutbox>> {
+        use super::schema::sched_outbox::dsl::*;
+        use diesel::prelude::*;
+        sched_outbox
+            .order(id.asc())
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .select(model::SchedOutbox::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete(conn: &mut PgConnection, ids: &[i64]) -> error::Result<usize> {
+        use super::schema::sched_outbox::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(sched_outbox.filter(id.eq_any(ids)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete_by_artifacts(conn: &mut PgConnection, art_ids: &[i32]) -> error::Result<usize> {
+        use super::schema::sched_outbox::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(sched_outbox.filter(artifact_id.eq_any(art_ids)))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+}
@@ -9,6 +9,8 @@ diesel::table! {
         data -> Text,
         owner -> Nullable<Int4>,
         desp -> Nullable<Text>,
+        fields -> Nullable<Json>,
+        scrub -> Nullable<Json>,
     }
 }
 
@@ -20,6 +22,45 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    acnt_unit (id) {
+        id -> Int4,
+        account_id -> Int4,
+        data -> Text,
+        stat -> Text,
+        quarantined_at -> Nullable<Timestamp>,
+        reason -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    acnt_usage (id) {
+        id -> Int4,
+        unit_id -> Int4,
+        account_id -> Int4,
+        artifact_id -> Int4,
+        inst_id -> Text,
+        checked_out_at -> Timestamp,
+        checked_in_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    api_token (id) {
+        id -> Int4,
+        team_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        token_prefix -> Text,
+        salt -> Text,
+        scopes -> Json,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     artifact (id) {
         id -> Int4,
@@ -29,6 +70,68 @@ diesel::table! {
         team_id -> Int4,
         build -> Json,
         clean -> Nullable<Json>,
+        stat -> Text,
+        blocked_by -> Nullable<Text>,
+        failures -> Int4,
+        fence -> Int8,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Int8,
+        actor -> Text,
+        actor_kind -> Text,
+        team -> Nullable<Text>,
+        action -> Text,
+        resource -> Text,
+        before_digest -> Nullable<Text>,
+        after_digest -> Nullable<Text>,
+        request_id -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    inst_cred (id) {
+        id -> Int4,
+        artifact_id -> Int4,
+        inst_id -> Text,
+        name -> Text,
+        data -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    membership (id) {
+        id -> Int4,
+        user_id -> Int4,
+        team_id -> Int4,
+        role -> Text,
+        token_hash -> Text,
+        token_prefix -> Text,
+        salt -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    quota (team_id) {
+        team_id -> Int4,
+        max_artifacts -> Nullable<Int4>,
+        max_total -> Nullable<Int4>,
+        max_running -> Nullable<Int4>,
+        max_borrowed -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    sched_outbox (id) {
+        id -> Int8,
+        artifact_id -> Int4,
+        reason -> Text,
+        created_at -> Timestamp,
     }
 }
 
@@ -40,6 +143,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sec_usage (id) {
+        id -> Int4,
+        artifact_id -> Int4,
+        inst_id -> Text,
+        secret_id -> Int4,
+        version -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sec_version (id) {
+        id -> Int4,
+        secret_id -> Int4,
+        version -> Int4,
+        data -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     secret (id) {
         id -> Int4,
@@ -47,6 +171,9 @@ diesel::table! {
         data -> Text,
         owner -> Nullable<Int4>,
         desp -> Nullable<Text>,
+        version -> Int4,
+        backend -> Text,
+        path -> Nullable<Text>,
     }
 }
 
@@ -54,24 +181,63 @@ diesel::table! {
     team (id) {
         id -> Int4,
         name -> Text,
-        token -> Text,
+        token_hash -> Text,
         desp -> Nullable<Text>,
+        token_prefix -> Text,
+        salt -> Text,
+        prev_token_hash -> Nullable<Text>,
+        prev_token_prefix -> Nullable<Text>,
+        prev_salt -> Nullable<Text>,
+        prev_expires_at -> Nullable<Timestamp>,
+        tier -> Int4,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Int4,
+        name -> Text,
+        admin -> Bool,
+        created_at -> Timestamp,
     }
 }
 
 diesel::joinable!(account -> team (owner));
 diesel::joinable!(acnt_ctl -> account (account_id));
 diesel::joinable!(acnt_ctl -> team (team_id));
+diesel::joinable!(acnt_unit -> account (account_id));
+diesel::joinable!(acnt_usage -> account (account_id));
+diesel::joinable!(acnt_usage -> acnt_unit (unit_id));
+diesel::joinable!(acnt_usage -> artifact (artifact_id));
+diesel::joinable!(api_token -> team (team_id));
 diesel::joinable!(artifact -> team (team_id));
+diesel::joinable!(inst_cred -> artifact (artifact_id));
+diesel::joinable!(membership -> team (team_id));
+diesel::joinable!(membership -> users (user_id));
+diesel::joinable!(quota -> team (team_id));
 diesel::joinable!(sec_ctl -> secret (secret_id));
 diesel::joinable!(sec_ctl -> team (team_id));
+diesel::joinable!(sec_usage -> artifact (artifact_id));
+diesel::joinable!(sec_usage -> secret (secret_id));
+diesel::joinable!(sec_version -> secret (secret_id));
 diesel::joinable!(secret -> team (owner));
 
 diesel::allow_tables_to_appear_in_same_query!(
     account,
     acnt_ctl,
+    acnt_unit,
+    acnt_usage,
+    api_token,
     artifact,
+    audit_log,
+    inst_cred,
+    membership,
+    quota,
+    sched_outbox,
     sec_ctl,
+    sec_usage,
+    sec_version,
     secret,
     team,
+    users,
 );
@@ -1,12 +1,180 @@
 use crate::error;
 use diesel::pg::PgConnection;
+use super::model;
 
 pub struct SecretDao;
 
 impl SecretDao {
+    pub fn create(conn: &mut PgConnection, sec: model::Secret) -> error::Result<i32> {
+        use super::schema::secret::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(secret)
+            .values(&sec)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_name(conn: &mut PgConnection, sec_name: &str) -> error::Result<model::Secret> {
+        use super::schema::secret::dsl::*;
+        use diesel::prelude::*;
+        secret.filter(name.eq(sec_name))
+            .select(model::Secret::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_name_for_update(conn: &mut PgConnection, sec_name: &str) -> error::Result<model::Secret> {
+        use super::schema::secret::dsl::*;
+        use diesel::prelude::*;
+        secret.filter(name.eq(sec_name))
+            .select(model::Secret::as_select())
+            .for_update()
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
     pub fn exist_name(conn: &mut PgConnection, sec_name: &str) -> error::Result<bool> {
         use super::schema::secret::dsl::*;
         use diesel::prelude::*;
         diesel::dsl::select(diesel::dsl::exists(secret.filter(name.eq(sec_name)))).get_result(conn).map_err(|err| err.into())
     }
+
+    /// Save the data as the next version of the secret, and return the new version number.
+    /// The row should be locked by `load_by_name_for_update` in the same transaction.
+    pub fn bump_version(conn: &mut PgConnection, sec_id: i32, sec_data: &str) -> error::Result<i32> {
+        use super::schema::secret::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(secret.filter(id.eq(sec_id)))
+            .set((version.eq(version + 1), data.eq(sec_data)))
+            .returning(version)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn create_version(conn: &mut PgConnection, ver: model::SecretVersion) -> error::Result<i32> {
+        use super::schema::sec_version::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(sec_version)
+            .values(&ver)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_version(conn: &mut PgConnection, sec_id: i32, ver: i32) -> error::Result<model::SecretVersion> {
+        use super::schema::sec_version::dsl::*;
+        use diesel::prelude::*;
+        sec_version.filter(secret_id.eq(sec_id).and(version.eq(ver)))
+            .select(model::SecretVersion::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_versions(conn: &mut PgConnection, sec_id: i32) -> error::Result<Vec<model::SecretVersion>> {
+        use super::schema::sec_version::dsl::*;
+        use diesel::prelude::*;
+        sec_version.filter(secret_id.eq(sec_id))
+            .order(version.asc())
+            .select(model::SecretVersion::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn create_usage(conn: &mut PgConnection, usage: model::SecretUsage) -> error::Result<i32> {
+        use super::schema::sec_usage::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(sec_usage)
+            .values(&usage)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn list_usage(conn: &mut PgConnection, sec_id: i32) -> error::Result<Vec<model::SecretUsage>> {
+        use super::schema::sec_usage::dsl::*;
+        use diesel::prelude::*;
+        sec_usage.filter(secret_id.eq(sec_id))
+            .order(id.asc())
+            .select(model::SecretUsage::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn count_by_owner(conn: &mut PgConnection, team_id: i32) -> error::Result<i64> {
+        use super::schema::secret::dsl::*;
+        use diesel::prelude::*;
+        secret.filter(owner.eq(team_id)).count().get_result(conn).map_err(|err| err.into())
+    }
+
+    /// Delete the secrets owned by the team, along with the grants of them to the other teams.
+    pub fn delete_by_owner(conn: &mut PgConnection, team_id: i32) -> error::Result<usize> {
+        use super::schema::{secret, sec_ctl};
+        use diesel::prelude::*;
+        let owned = secret::table.filter(secret::owner.eq(team_id)).select(secret::id.nullable());
+        diesel::delete(sec_ctl::table.filter(sec_ctl::secret_id.eq_any(owned))).execute(conn)?;
+        diesel::delete(secret::table.filter(secret::owner.eq(team_id))).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
+        use diesel::prelude::*;
+        use super::schema::secret::dsl::*;
+        diesel::delete(secret).execute(conn).map_err(|err|err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bo::dao::TeamDao;
+
+    fn create_secret(conn: &mut PgConnection) -> error::Result<i32> {
+        let team = model::Team::new("Team S".to_owned(), "345678", None);
+        let team_id = TeamDao::create(conn, team)?;
+        let sec = model::Secret {
+            id: None,
+            name: "test-dao-secret".to_owned(),
+            data: "{\"token\":\"v1\"}".to_owned(),
+            owner: Some(team_id),
+            desp: None,
+            version: 1,
+            backend: "postgres".to_owned(),
+            path: None
+        };
+        let sec_id = SecretDao::create(conn, sec)?;
+        SecretDao::create_version(conn, model::SecretVersion {
+            id: None,
+            secret_id: sec_id,
+            version: 1,
+            data: "{\"token\":\"v1\"}".to_owned(),
+            created_at: None
+        })?;
+        Ok(sec_id)
+    }
+
+    #[test]
+    fn test_dao_bump_version() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            let sec_id = create_secret(conn)?;
+            let sec = SecretDao::load_by_name_for_update(conn, "test-dao-secret")?;
+            let new_version = SecretDao::bump_version(conn, sec_id, "{\"token\":\"v2\"}")?;
+            assert_eq!(new_version, sec.version + 1);
+            SecretDao::create_version(conn, model::SecretVersion {
+                id: None,
+                secret_id: sec_id,
+                version: new_version,
+                data: "{\"token\":\"v2\"}".to_owned(),
+                created_at: None
+            })?;
+
+            let sec = SecretDao::load_by_name(conn, "test-dao-secret")?;
+            assert_eq!(sec.version, 2);
+            assert_eq!(sec.data, "{\"token\":\"v2\"}");
+            let versions = SecretDao::list_versions(conn, sec_id)?;
+            assert_eq!(versions.len(), 2);
+            let first = SecretDao::load_version(conn, sec_id, 1)?;
+            assert_eq!(first.data, "{\"token\":\"v1\"}");
+            Ok(())
+        }).unwrap();
+    }
 }
@@ -1,6 +1,8 @@
+use crate::{crypto, oidc};
 use crate::error;
 use diesel::pg::PgConnection;
 use super::model;
+use super::{ApiTokenDao, UserDao};
 
 pub struct TeamDao;
 
@@ -15,10 +17,54 @@ impl TeamDao {
             .map_err(|err| err.into())
     }
 
+    /// Find the team by the lookup prefix of the token, then verify the token against the hash.
+    /// The previous token is accepted in its grace period. The token is either the team token, one
+    /// of the api tokens of the team, the token of a member of the team or the JWT of the user. The scopes of the api
+    /// tokens and the roles of the members are checked by `TokenOps::authorize`.
     pub fn find_team_by_token(conn: &mut PgConnection, team_token: &str) -> error::Result<model::Team> {
         use super::schema::team::dsl::*;
         use diesel::prelude::*;
-        team.filter(token.eq(team_token))
+        if oidc::is_jwt(team_token) {
+            return Self::find_team_by_identity(conn, &Self::verify_jwt(team_token)?);
+        }
+        let prefix = crypto::token_prefix(team_token);
+        let candidates = team.filter(token_prefix.eq(prefix).or(prev_token_prefix.eq(prefix)))
+            .select(model::Team::as_select())
+            .load(conn)?;
+        match Self::authenticate(candidates, team_token) {
+            Err(err) => match ApiTokenDao::find_by_token(conn, team_token)? {
+                Some(api_token) => Self::find_team_by_id(conn, api_token.team_id),
+                None => match UserDao::find_membership_by_token(conn, team_token)? {
+                    Some(member) => Self::find_team_by_id(conn, member.team_id),
+                    None => Err(err)
+                }
+            },
+            found => found
+        }
+    }
+
+    /// The team of the JWT user, which is the first of the teams in the claims known here.
+    pub fn find_team_by_identity(conn: &mut PgConnection, identity: &oidc::Identity) -> error::Result<model::Team> {
+        use super::schema::team::dsl::*;
+        use diesel::prelude::*;
+        let mut candidates = team.filter(name.eq_any(&identity.teams))
+            .select(model::Team::as_select())
+            .load(conn)?;
+        let position = identity.teams.iter()
+            .find_map(|team_name| candidates.iter().position(|candidate| &candidate.name == team_name))
+            .ok_or_else(|| error::GeneralError::Forbidden(format!("The user {} is in no team", identity.user)))?;
+        Ok(candidates.swap_remove(position))
+    }
+
+    /// The JWTs are refused if the OIDC is not configured.
+    fn verify_jwt(token: &str) -> error::Result<oidc::Identity> {
+        oidc::verifier().ok_or(error::GeneralError::Unauthorized)?.verify(token)
+    }
+
+    pub fn find_team_by_id(conn: &mut PgConnection, team_id: i32) -> error::Result<model::Team> {
+        use super::schema::team::dsl::*;
+        use diesel::prelude::*;
+        team.filter(id.eq(team_id))
             .select(model::Team::as_select())
             .first(conn)
             .map_err(|err| err.into())
@@ -36,11 +82,36 @@ impl TeamDao {
     pub fn find_team_by_token_for_update(conn: &mut PgConnection, team_token: &str) -> error::Result<model::Team> {
         use super::schema::team::dsl::*;
         use diesel::prelude::*;
-        team.filter(token.eq(team_token))
+        if oidc::is_jwt(team_token) {
+            let found = Self::find_team_by_identity(conn, &Self::verify_jwt(team_token)?)?;
+            return team.filter(id.eq(found.id.ok_or_else(|| error::error("Null team id"))?))
+                .select(model::Team::as_select())
+                .for_update()
+                .first(conn)
+                .map_err(|err| err.into());
+        }
+        let prefix = crypto::token_prefix(team_token);
+        let candidates = team.filter(token_prefix.eq(prefix).or(prev_token_prefix.eq(prefix)))
             .select(model::Team::as_select())
             .for_update()
-            .first(conn)
-            .map_err(|err| err.into())
+            .load(conn)?;
+        match Self::authenticate(candidates, team_token) {
+            Err(err) => {
+                let owner_id = match ApiTokenDao::find_by_token(conn, team_token)? {
+                    Some(api_token) => api_token.team_id,
+                    None => match UserDao::find_membership_by_token(conn, team_token)? {
+                        Some(member) => member.team_id,
+                        None => return Err(err)
+                    }
+                };
+                team.filter(id.eq(owner_id))
+                    .select(model::Team::as_select())
+                    .for_update()
+                    .first(conn)
+                    .map_err(|err| err.into())
+            },
+            found => found
+        }
     }
 
     pub fn find_team_by_name_for_update(conn: &mut PgConnection, team_name: &str) -> error::Result<model::Team> {
@@ -53,14 +124,54 @@ impl TeamDao {
             .map_err(|err| err.into())
     }
 
-    pub fn rotate_token(_conn: &mut PgConnection, _team_name: String, _token: String) -> error::Result<()> {
-        Err(error::error("not implemented yet"))
+    pub fn update(conn: &mut PgConnection, team_model: &model::Team) -> error::Result<usize> {
+        use super::schema::team::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(team)
+            .filter(id.eq(team_model.id.ok_or_else(|| error::error("Null team id"))?))
+            .set(team_model)
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// The quota of the team, or None if the team has no limits.
+    pub fn load_quota(conn: &mut PgConnection, owner_id: i32) -> error::Result<Option<model::Quota>> {
+        use super::schema::quota::dsl::*;
+        use diesel::prelude::*;
+        quota.filter(team_id.eq(owner_id))
+            .select(model::Quota::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|err| err.into())
+    }
+
+    pub fn save_quota(conn: &mut PgConnection, quota_model: &model::Quota) -> error::Result<usize> {
+        use super::schema::quota::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(quota)
+            .values(quota_model)
+            .on_conflict(team_id)
+            .do_update()
+            .set(quota_model)
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// An unknown token is reported as not found, the same as the unknown team.
+    fn authenticate(candidates: Vec<model::Team>, team_token: &str) -> error::Result<model::Team> {
+        candidates.into_iter()
+            .find(|candidate| candidate.verify(team_token))
+            .ok_or(diesel::result::Error::NotFound.into())
     }
 
+    /// Delete the team and the grants of the secrets and accounts to it. The api tokens are
+    /// deleted by the DB.
     pub fn delete(conn: &mut PgConnection, team_id: i32) -> error::Result<usize> {
         use diesel::prelude::*;
-        use super::schema::team::dsl::*;
-        diesel::delete(team.filter(id.eq(team_id))).execute(conn).map_err(|err|err.into())
+        use super::schema::{acnt_ctl, sec_ctl, team};
+        diesel::delete(sec_ctl::table.filter(sec_ctl::team_id.eq(team_id))).execute(conn)?;
+        diesel::delete(acnt_ctl::table.filter(acnt_ctl::team_id.eq(team_id))).execute(conn)?;
+        diesel::delete(team::table.filter(team::id.eq(team_id))).execute(conn).map_err(|err|err.into())
     }
 
     pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
@@ -78,12 +189,7 @@ mod tests {
     #[test]
     fn test_create_team() {
         let mut conn = get_connection();
-        let team = model::Team {
-            id: None,
-            name: "Team C".to_owned(),
-            token: "random-generated".to_owned(),
-            desp: None
-        };
+        let team = model::Team::new("Team C".to_owned(), "random-generated", None);
         match TeamDao::create(&mut conn, team) {
             Ok(team_id) => {
                 TeamDao::delete(&mut conn, team_id).expect("Failed to remove the test record");
@@ -98,13 +204,8 @@ mod tests {
     #[test]
     fn test_find_team_by_token() {
         let mut conn = get_connection();
-        let token = "random-generated-token".to_owned();
-        let team = model::Team {
-            id: None,
-            name: "Team J".to_owned(),
-            token: token.clone(),
-            desp: None
-        };
+        let token = crypto::generate_token();
+        let team = model::Team::new("Team J".to_owned(), &token, None);
         let team_id = TeamDao::create(&mut conn, team).expect("Failed to create team: Team J");
         match TeamDao::find_team_by_token(&mut conn, &token) {
             Ok(_) => {
@@ -119,6 +220,23 @@ mod tests {
 
     #[test]
     fn test_rotate_token() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            let old_token = crypto::generate_token();
+            TeamDao::create(conn, model::Team::new("Team R".to_owned(), &old_token, None))?;
+            let mut team = TeamDao::find_team_by_token(conn, &old_token)?;
+            let new_token = crypto::generate_token();
+            team.rotate(&new_token, Some(600));
+            TeamDao::update(conn, &team)?;
+            assert!(TeamDao::find_team_by_token(conn, &new_token).is_ok());
+            assert!(TeamDao::find_team_by_token(conn, &old_token).is_ok());
 
+            let newest_token = crypto::generate_token();
+            team.rotate(&newest_token, None);
+            TeamDao::update(conn, &team)?;
+            assert!(TeamDao::find_team_by_token(conn, &newest_token).is_ok());
+            assert!(TeamDao::find_team_by_token(conn, &new_token).is_err());
+            assert!(TeamDao::find_team_by_token(conn, &old_token).is_err());
+            Ok(())
+        }).unwrap();
     }
 }
@@ -0,0 +1,65 @@
+use crate::crypto;
+use crate::error;
+use diesel::pg::PgConnection;
+use super::model;
+
+pub struct ApiTokenDao;
+
+impl ApiTokenDao {
+    pub fn create(conn: &mut PgConnection, token_model: model::ApiToken) -> error::Result<i32> {
+        use super::schema::api_token::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(api_token)
+            .values(&token_model)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Find the live token by its lookup prefix, then verify it against the hash. The revoked and
+    /// expired tokens are not returned.
+    pub fn find_by_token(conn: &mut PgConnection, token: &str) -> error::Result<Option<model::ApiToken>> {
+        use super::schema::api_token::dsl::*;
+        use diesel::prelude::*;
+        let candidates = api_token.filter(token_prefix.eq(crypto::token_prefix(token)))
+            .filter(revoked_at.is_null())
+            .select(model::ApiToken::as_select())
+            .load(conn)?;
+        Ok(candidates.into_iter().find(|candidate| candidate.verify(token)))
+    }
+
+    pub fn list_by_team(conn: &mut PgConnection, owner_id: i32) -> error::Result<Vec<model::ApiToken>> {
+        use super::schema::api_token::dsl::*;
+        use diesel::prelude::*;
+        api_token.filter(team_id.eq(owner_id))
+            .order(created_at.asc())
+            .select(model::ApiToken::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn touch(conn: &mut PgConnection, token_id: i32) -> error::Result<usize> {
+        use super::schema::api_token::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(api_token.filter(id.eq(token_id)))
+            .set(last_used_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Revoke the live token of the team by the name. The revoked token is kept for the record.
+    pub fn revoke(conn: &mut PgConnection, owner_id: i32, token_name: &str) -> error::Result<usize> {
+        use super::schema::api_token::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(api_token.filter(team_id.eq(owner_id).and(name.eq(token_name)).and(revoked_at.is_null())))
+            .set(revoked_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
+        use diesel::prelude::*;
+        use super::schema::api_token::dsl::*;
+        diesel::delete(api_token).execute(conn).map_err(|err|err.into())
+    }
+}
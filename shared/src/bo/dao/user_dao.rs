@@ -0,0 +1,93 @@
+use crate::crypto;
+use crate::error;
+use diesel::pg::PgConnection;
+use super::model;
+
+pub struct UserDao;
+
+impl UserDao {
+    pub fn create(conn: &mut PgConnection, user_model: model::User) -> error::Result<i32> {
+        use super::schema::users::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(users)
+            .values(&user_model)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_id(conn: &mut PgConnection, user_id: i32) -> error::Result<model::User> {
+        use super::schema::users::dsl::*;
+        use diesel::prelude::*;
+        users.filter(id.eq(user_id))
+            .select(model::User::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn load_by_name(conn: &mut PgConnection, user_name: &str) -> error::Result<model::User> {
+        use super::schema::users::dsl::*;
+        use diesel::prelude::*;
+        users.filter(name.eq(user_name))
+            .select(model::User::as_select())
+            .first(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete(conn: &mut PgConnection, user_id: i32) -> error::Result<usize> {
+        use super::schema::users::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(users.filter(id.eq(user_id))).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn delete_all(conn: &mut PgConnection) -> error::Result<usize> {
+        use super::schema::users::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(users).execute(conn).map_err(|err|err.into())
+    }
+
+    pub fn create_membership(conn: &mut PgConnection, member: model::Membership) -> error::Result<i32> {
+        use super::schema::membership::dsl::*;
+        use diesel::prelude::*;
+        diesel::insert_into(membership)
+            .values(&member)
+            .returning(id)
+            .get_result(conn)
+            .map_err(|err| err.into())
+    }
+
+    /// Find the membership by the lookup prefix of the token, then verify it against the hash.
+    pub fn find_membership_by_token(conn: &mut PgConnection, token: &str) -> error::Result<Option<model::Membership>> {
+        use super::schema::membership::dsl::*;
+        use diesel::prelude::*;
+        let candidates = membership.filter(token_prefix.eq(crypto::token_prefix(token)))
+            .select(model::Membership::as_select())
+            .load(conn)?;
+        Ok(candidates.into_iter().find(|candidate| candidate.verify(token)))
+    }
+
+    pub fn list_memberships_by_team(conn: &mut PgConnection, owner_id: i32) -> error::Result<Vec<model::Membership>> {
+        use super::schema::membership::dsl::*;
+        use diesel::prelude::*;
+        membership.filter(team_id.eq(owner_id))
+            .order(id.asc())
+            .select(model::Membership::as_select())
+            .load(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn update_role(conn: &mut PgConnection, member_id: i32, owner_id: i32, member_role: &str) -> error::Result<usize> {
+        use super::schema::membership::dsl::*;
+        use diesel::prelude::*;
+        diesel::update(membership.filter(user_id.eq(member_id).and(team_id.eq(owner_id))))
+            .set(role.eq(member_role))
+            .execute(conn)
+            .map_err(|err| err.into())
+    }
+
+    pub fn delete_membership(conn: &mut PgConnection, member_id: i32, owner_id: i32) -> error::Result<usize> {
+        use super::schema::membership::dsl::*;
+        use diesel::prelude::*;
+        diesel::delete(membership.filter(user_id.eq(member_id).and(team_id.eq(owner_id)))).execute(conn).map_err(|err|err.into())
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use serde::Serialize;
 
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct Instance {
@@ -20,6 +21,7 @@ pub enum InstanceStatus {
     Succeeded
 }
 
+#[derive(Debug, PartialEq, Serialize)]
 pub struct InstanceNumbers {
     pub running: u32,
     pub fail: u32,
@@ -27,6 +29,24 @@ pub struct InstanceNumbers {
     pub done_dirt: u32
 }
 
+impl Instance {
+    /// The instance is built and waits to be borrowed.
+    pub fn is_ready(&self) -> bool {
+        !self.dirt && self.stat == InstanceStatus::Succeeded
+    }
+
+    /// The instance is built and it is used by a borrower.
+    pub fn is_borrowed(&self) -> bool {
+        self.dirt && self.stat == InstanceStatus::Succeeded && !self.is_clean_run()
+    }
+
+    /// The last run of the instance is the clean run, the runs are named after their pipelines
+    /// `build-{artifact}` and `clean-{artifact}`.
+    pub fn is_clean_run(&self) -> bool {
+        self.run_name.starts_with("clean-")
+    }
+}
+
 impl InstanceStatus {
     pub fn is_failed(&self) -> bool {
         if let Self::Failed(_) = self {
@@ -48,7 +68,8 @@ impl <S:AsRef<str>>From<S> for InstanceStatus {
             "Running" => InstanceStatus::Running,
             "Succeeded" => InstanceStatus::Succeeded,
             "" => InstanceStatus::Unknown,
-            _ => InstanceStatus::Failed(value.as_ref().to_owned()),
+            // The failed status is saved as `Fail: {reason}`, see `to_string`.
+            other => InstanceStatus::Failed(other.strip_prefix("Fail: ").unwrap_or(other).to_owned()),
         }
     }
 }
@@ -80,16 +80,16 @@ pub struct ConfigMapRef {
 }
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct HostPath {
-    path: String,
+    pub path: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "type", deserialize = "type"))]
-    tpe: Option<String>
+    pub tpe: Option<String>
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub struct SecretRef {
     #[serde(rename(serialize = "secretName", deserialize = "secretName"))]
-    secret_name: String
+    pub secret_name: String
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
@@ -151,10 +151,30 @@ pub struct TaskStepEnvKV {
 pub enum EnvValue {
     #[serde(rename(serialize = "value", deserialize = "value"))]
     Value(String),
-    #[serde(rename(serialize = "sercretKeyRef", deserialize = "secretKeyRef"))]
+    /// It is `valueFrom: {secretKeyRef: {name, key}}` in the manifest.
+    #[serde(rename(serialize = "valueFrom", deserialize = "valueFrom"), with = "value_from")]
     SecretKeyRef(SecretKeyRef)
 }
 
+mod value_from {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::SecretKeyRef;
+
+    #[derive(Serialize, Deserialize)]
+    struct ValueFrom {
+        #[serde(rename(serialize = "secretKeyRef", deserialize = "secretKeyRef"))]
+        secret_key_ref: SecretKeyRef
+    }
+
+    pub fn serialize<S: Serializer>(key_ref: &SecretKeyRef, serializer: S) -> Result<S::Ok, S::Error> {
+        ValueFrom { secret_key_ref: key_ref.clone() }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SecretKeyRef, D::Error> {
+        Ok(ValueFrom::deserialize(deserializer)?.secret_key_ref)
+    }
+}
+
 impl <'a>Default for EnvValue {
     fn default() -> Self {
         EnvValue::Value(String::new())
@@ -306,4 +326,18 @@ impl <'a> Secret<'a> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_secret_key_ref() {
+        let env = TaskStepEnvKV {
+            name: "GCP_KEY".to_owned(),
+            value: EnvValue::SecretKeyRef(SecretKeyRef { name: "acnt-opsman-cold-1234-gcp".to_owned(), key: "key".to_owned() })
+        };
+        let json = serde_json::to_string(&env).unwrap();
+        assert_eq!(json, r#"{"name":"GCP_KEY","valueFrom":{"secretKeyRef":{"name":"acnt-opsman-cold-1234-gcp","key":"key"}}}"#);
+        assert_eq!(serde_json::from_str::<TaskStepEnvKV>(&json).unwrap(), env);
+        let plain: TaskStepEnvKV = serde_json::from_str(r#"{"name":"REGION","value":"us-west1"}"#).unwrap();
+        assert_eq!(plain.value, EnvValue::Value("us-west1".to_owned()));
+    }
 }
@@ -1,7 +1,9 @@
 use std::io::{Result, ErrorKind, Error};
 use crate::command::{self, command_with_args};
+use crate::redact::Redactor;
 
-pub fn apply<S: AsRef<str>, N: AsRef<str>>(src: S, namespace: N) -> Result<()> {
+/// The output of the commands are masked by the redactor before they are logged or returned.
+pub fn apply<S: AsRef<str>, N: AsRef<str>>(src: S, namespace: N, redactor: &Redactor) -> Result<()> {
     let source_echo = command::command_with_args("echo", [src.as_ref()]);
     let kubectl = command::command_with_args("kubectl", ["apply", "-n", namespace.as_ref(), "-f", "-"]);
     let output = command::pipe_run(&mut[source_echo, kubectl])?;
@@ -9,8 +11,8 @@ pub fn apply<S: AsRef<str>, N: AsRef<str>>(src: S, namespace: N) -> Result<()> {
     let stdout = String::from_utf8_lossy(&output.stdout[..]);
     let stderr = String::from_utf8_lossy(&output.stderr[..]);
     log::info!("apply status: {}", output.status);
-    log::info!("apply output: {}", stdout);
-    log::info!("apply stderr: {}", stderr);
+    log::info!("apply output: {}", redactor.redact(stdout));
+    log::info!("apply stderr: {}", redactor.redact(stderr));
 
     Ok(())
 }
@@ -41,13 +43,29 @@ pub fn delete_run<S: AsRef<str>, N: AsRef<str>>(name: S, namespace: N) -> Result
     log::info!("delete stderr: {}", output_str);
 
     //Are you sure you want to delete all PipelineRuns in namespace "train" (y/n): All PipelineRuns(Completed) deleted in namespace "train"
-    if output_str.contains("All PipelineRuns(Completed) deleted in namespace ") {
+    //Are you sure you want to delete PipelineRun(s) "build-sample-run-8lvfx" (y/n): PipelineRuns deleted: "build-sample-run-8lvfx"
+    if output_str.contains("All PipelineRuns(Completed) deleted in namespace ") || output_str.contains("PipelineRuns deleted: ") {
         Ok(())
     } else {
         Err(Error::new(ErrorKind::InvalidData, format!("failed to delete pipeline: {}", name.as_ref())))
     }
 }
 
+pub fn cancel_run<S: AsRef<str>, N: AsRef<str>>(name: S, namespace: N) -> Result<()> {
+    let mut tkn_cancel = command::command_with_args("tkn", ["pipelinerun", "cancel", name.as_ref(), "-n", namespace.as_ref()]);
+    let output = tkn_cancel.output()?;
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    log::info!("cancel output: {}", output_str);
+    log::info!("cancel stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    // PipelineRun cancelled: build-sample-run-8lvfx
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidData, format!("failed to cancel pipeline run: {}", name.as_ref())))
+    }
+}
+
 pub fn list(namespace: &str) -> Result<Vec<String>> {
     let pipelines = command::command_with_args("tkn", ["pipeline", "list", "-o", "json", "-n", namespace]); 
     let jq = command::command_with_args("jq", ["-r", ".items[].metadata.name"]);
@@ -61,7 +79,7 @@ pub fn list(namespace: &str) -> Result<Vec<String>> {
     Ok(stdout.trim().split("\n").map(|v|String::from(v)).collect())
 }
 
-pub fn run(id: &str, namespace: &str, params: &[&str]) -> Result<String> {
+pub fn run(id: &str, namespace: &str, params: &[&str], redactor: &Redactor) -> Result<String> {
     let mut tkn_args = vec!["-n", namespace, "pipeline", "start", id];
     for p in params {
         tkn_args.push("-p");
@@ -70,8 +88,8 @@ pub fn run(id: &str, namespace: &str, params: &[&str]) -> Result<String> {
     let mut tkn = command_with_args("tkn", tkn_args);
     // example of output: PipelineRun started: env-deployment-run-8lvfx
     let output = tkn.output()?;
-    let stdout_str = command::stringfy(&output.stdout);
-    let stderr_str = command::stringfy(&output.stderr);
+    let stdout_str = redactor.redact(command::stringfy(&output.stdout));
+    let stderr_str = redactor.redact(command::stringfy(&output.stderr));
     log::info!("### stdout of run pipeline:\n{}", stdout_str);
     log::info!("### stderr of run pipeline:\n{}", stderr_str);
     let ok_msg = "PipelineRun started: ";
@@ -86,17 +104,27 @@ pub fn run(id: &str, namespace: &str, params: &[&str]) -> Result<String> {
     }
 }
 
-pub fn logs(run_name: &str, namespace: &str) -> Result<String> {
+pub fn logs(run_name: &str, namespace: &str, redactor: &Redactor) -> Result<String> {
     log::info!("### logs command: tkn pipelinerun logs {} -n {}", run_name, namespace);
     let mut logs = command::command_with_args("tkn", ["pipelinerun", "logs", run_name, "-n", namespace]); 
     let output = logs.output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout[..]);
-    let stderr = String::from_utf8_lossy(&output.stderr[..]);
+    let stdout = redactor.redact(String::from_utf8_lossy(&output.stdout[..]));
+    let stderr = redactor.redact(String::from_utf8_lossy(&output.stderr[..]));
     log::info!("pipeline logs status: {}", output.status);
     log::info!("pipeline logs output: {}", stdout);
     log::info!("pipeline logs stderr: {}", stderr);
 
-    Ok(stdout.to_string())
+    Ok(stdout)
+}
+
+/// The pipeline run in json, with its status conditions and results.
+pub fn describe_run(run_name: &str, namespace: &str) -> Result<String> {
+    let mut describe = command::command_with_args("tkn", ["pipelinerun", "describe", run_name, "-o", "json", "-n", namespace]);
+    let output = describe.output()?;
+    if !output.status.success() {
+        return Err(Error::new(ErrorKind::NotFound, format!("failed to describe pipeline run: {} with error: {}", run_name, command::stringfy(&output.stderr))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
 pub fn pipeline_run_stats(run_name: &str, namespace: &str) -> Result<String> {
@@ -110,13 +138,13 @@ pub fn pipeline_run_stats(run_name: &str, namespace: &str) -> Result<String> {
     Ok(output_text.trim_end_matches("\n").to_string())
 }
 
-pub fn pipeline_run_results(run_name: &str, namespace: &str) -> Result<String> {
+pub fn pipeline_run_results(run_name: &str, namespace: &str, redactor: &Redactor) -> Result<String> {
 
     //results: tkn pipelinerun describe test-result-run-j4h9t -n train -o json | jq '.status.results|.[]'
     let pipeline_run_describe = command::command_with_args("tkn", ["pipelinerun", "describe", run_name,  "-o", "json", "-n", namespace]);
     let jq_status = command::command_with_args("jq", ["-r", ".status.results"]);
     let output = command::pipe_run(&mut [pipeline_run_describe, jq_status])?;
-    let output_text = String::from_utf8_lossy(&output.stdout);
+    let output_text = redactor.redact(String::from_utf8_lossy(&output.stdout));
     Ok(output_text.trim_end_matches("\n").to_string())
 }
 
@@ -149,7 +177,7 @@ spec:
       taskRef:
         name: sample-init
 "#, ret_code, name);
-        apply(pipeline_yaml, namespace)
+        apply(pipeline_yaml, namespace, &Redactor::default())
     }
 
 
@@ -171,7 +199,7 @@ spec:
     fn test_pipeline_run_stats_running() {
         let pipeline_name = "pipeline-run-stats-sample-running";
         create_a_pipeline(pipeline_name, "train", 0).unwrap();
-        let run_name = run(pipeline_name, "train", &[]).unwrap();
+        let run_name = run(pipeline_name, "train", &[], &Redactor::default()).unwrap();
         std::thread::sleep(std::time::Duration::from_secs(1));
         let stat = pipeline_run_stats(&run_name, "train").unwrap();
         let result = delete("pipeline-run-stats-sample-running", "train");
@@ -183,7 +211,7 @@ spec:
     fn test_pipeline_run_stats_succeeded() {
         let pipeline_name = "pipeline-run-stats-sample-succeeded";
         create_a_pipeline(pipeline_name, "train", 0).unwrap();
-        let run_name = run(pipeline_name, "train", &[]).unwrap();
+        let run_name = run(pipeline_name, "train", &[], &Redactor::default()).unwrap();
         std::thread::sleep(std::time::Duration::from_secs(30));
         let stat = pipeline_run_stats(&run_name, "train").unwrap();
         let result = delete("pipeline-run-stats-sample-succeeded", "train");
@@ -195,7 +223,7 @@ spec:
     fn test_pipeline_run_stats_fail() {
         let pipeline_name = "pipeline-run-stats-sample-fail";
         create_a_pipeline(pipeline_name, "train", -1).unwrap();
-        let run_name = run(pipeline_name, "train", &[]).unwrap();
+        let run_name = run(pipeline_name, "train", &[], &Redactor::default()).unwrap();
         std::thread::sleep(std::time::Duration::from_secs(40));
         let stat = pipeline_run_stats(&run_name, "train").unwrap();
         let result = delete("pipeline-run-stats-sample-fail", "train");
@@ -0,0 +1,68 @@
+use serde::{Serialize, Deserialize};
+use crate::error;
+
+/// The limits of a team set by the admins. The absent limits are unlimited.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct QuotaRequest {
+    /// The number of the artifacts.
+    #[serde(default, rename(serialize = "maxArtifacts", deserialize = "maxArtifacts"), skip_serializing_if = "Option::is_none")]
+    pub max_artifacts: Option<i32>,
+    /// The summed `total` of the artifacts.
+    #[serde(default, rename(serialize = "maxTotal", deserialize = "maxTotal"), skip_serializing_if = "Option::is_none")]
+    pub max_total: Option<i32>,
+    /// The pipeline runs running at the same time.
+    #[serde(default, rename(serialize = "maxRunning", deserialize = "maxRunning"), skip_serializing_if = "Option::is_none")]
+    pub max_running: Option<i32>,
+    /// The instances borrowed at the same time.
+    #[serde(default, rename(serialize = "maxBorrowed", deserialize = "maxBorrowed"), skip_serializing_if = "Option::is_none")]
+    pub max_borrowed: Option<i32>
+}
+
+/// The usage of the team kept in the database. The running and borrowed instances are counted by
+/// the scheduler, see `RunUsage`.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub artifacts: i64,
+    pub total: i64
+}
+
+/// The quota of the team along with its usage.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct QuotaInfo {
+    pub limits: QuotaRequest,
+    pub usage: QuotaUsage
+}
+
+/// The instances of the team across all its artifacts, counted by the scheduler before a rollout.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RunUsage {
+    pub running: i64,
+    pub borrowed: i64
+}
+
+/// Refuse the request if the usage plus the requested amount goes over the limit. The error tells
+/// the usage against the limit.
+pub(crate) fn check(team: &str, quota: &str, usage: i64, requested: i64, limit: Option<i32>) -> error::Result<()> {
+    match limit {
+        Some(limit) if usage + requested > limit as i64 => Err(error::GeneralError::QuotaExceeded(
+            format!("{} of the team {} is {}, {} in use and {} requested", quota, team, limit, usage, requested))),
+        _ => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert!(check("Team C", "maxTotal", 12, 3, Some(15)).is_ok());
+        assert!(check("Team C", "maxTotal", 12, 300, None).is_ok());
+        let err = check("Team C", "maxTotal", 12, 5, Some(15)).unwrap_err();
+        assert!(matches!(err, error::GeneralError::QuotaExceeded(_)));
+        assert_eq!(err.to_string(), "QuotaExceeded: maxTotal of the team Team C is 15, 12 in use and 5 requested");
+
+        let limits: QuotaRequest = serde_json::from_str(r#"{"maxArtifacts":10,"maxRunning":4}"#).unwrap();
+        assert_eq!(limits, QuotaRequest { max_artifacts: Some(10), max_running: Some(4), ..Default::default() });
+    }
+}
@@ -19,6 +19,3 @@ pub enum ResourceScope<'a> {
     Art(&'a str),
 }
 
-pub fn generate_secret<'a>(_user_id: &'a str, _secret_name: &'a str, _art_id: &'a str) -> HashMap<String, String>{
-    HashMap::from([("service_account_key".to_owned(), "mock key".to_owned()), ("pivnet_token".to_owned(), "token123456".to_owned())])
-}
@@ -0,0 +1,65 @@
+pub mod backend;
+
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+/// The payload to create a secret. The data is saved as the version 1 of the secret.
+/// A secret kept by an external backend has no data, it is resolved from the `path` of the
+/// `backend` instead.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SecretRequest {
+    pub name: String,
+    #[serde(default)]
+    pub data: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>
+}
+
+/// The payload to write a new version of an existing secret.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SecretVersionRequest {
+    pub data: HashMap<String, String>
+}
+
+/// The secret as it is shown to the users. The values are never returned.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SecretInfo {
+    pub name: String,
+    pub version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    pub backend: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub versions: Vec<SecretVersionInfo>
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SecretVersionInfo {
+    pub version: i32,
+    pub keys: Vec<String>,
+    pub created_at: Option<NaiveDateTime>
+}
+
+/// Records which version of a secret an instance was built with.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SecretUsageInfo {
+    pub artifact: String,
+    pub inst_id: String,
+    pub version: i32,
+    pub created_at: Option<NaiveDateTime>
+}
+
+/// The resolved value of a `SecretRef`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SecretValue {
+    pub secret_id: i32,
+    pub name: String,
+    pub version: i32,
+    pub data: HashMap<String, String>
+}
@@ -0,0 +1,265 @@
+//! The backends that the secrets resolve from.
+//! - `postgres`: the secret and its versions are saved in the train DB.
+//! - `vault`: the secret is read from a Vault KV v2 engine at `VAULT_ADDR` with the token
+//!   `VAULT_TOKEN`. The engine is mounted at `VAULT_MOUNT`, default is `secret`.
+//! - `file`: the secret is read from a directory under `TRAIN_SECRET_DIR`, each file is a key of
+//!   the secret. The versions are the numbered sub-directories, e.g. `pivnet/1/token`. A directory
+//!   without numbered sub-directories is the version 1.
+//!
+//! The paths of the `vault` and `file` secrets are relative, and they are under the directory of
+//! the team owning the secret, see `SecretOps::resolve`.
+use diesel::PgConnection;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use crate::bo::dao;
+use crate::error;
+
+pub const POSTGRES: &str = "postgres";
+pub const VAULT: &str = "vault";
+pub const FILE: &str = "file";
+
+pub trait SecretBackend {
+    /// Read the given version of the secret at the path, or the latest version if it is absent.
+    /// Return the version that is read and the data of the secret.
+    fn read(&mut self, path: &str, version: Option<i32>) -> error::Result<(i32, HashMap<String, String>)>;
+}
+
+/// Open the backend of the kind. The `postgres` backend reads with the connection.
+pub fn open<'a>(conn: &'a mut PgConnection, kind: &str) -> error::Result<Box<dyn SecretBackend + 'a>> {
+    match kind {
+        POSTGRES => Ok(Box::new(PostgresBackend { conn })),
+        VAULT => Ok(Box::new(VaultBackend::from_env()?)),
+        FILE => Ok(Box::new(FileBackend::from_env()?)),
+        _ => Err(error::bad_request(&format!("Unknown secret backend: {}", kind)))
+    }
+}
+
+pub fn is_known(kind: &str) -> bool {
+    [POSTGRES, VAULT, FILE].contains(&kind)
+}
+
+/// Check the path of the secret is relative and made of plain names only, so it can not step out
+/// of the directory of the team.
+pub fn check_path(path: &str) -> error::Result<()> {
+    let plain = !path.is_empty() && Path::new(path).components().all(|comp| matches!(comp, Component::Normal(_)));
+    if plain && !path.contains(['\\', '?', '#', '%']) {
+        Ok(())
+    } else {
+        Err(error::bad_request(&format!("Invalid secret path: {}", path)))
+    }
+}
+
+pub struct PostgresBackend<'a> {
+    pub conn: &'a mut PgConnection
+}
+
+impl <'a>SecretBackend for PostgresBackend<'a> {
+    fn read(&mut self, path: &str, version: Option<i32>) -> error::Result<(i32, HashMap<String, String>)> {
+        let sec = dao::SecretDao::load_by_name(self.conn, path)?;
+        let sec_id = sec.id.ok_or_else(|| error::error("Null secret id"))?;
+        let (version, data) = match version {
+            Some(version) => (version, dao::SecretDao::load_version(self.conn, sec_id, version)?.data),
+            None => (sec.version, sec.data)
+        };
+        Ok((version, serde_json::from_str(&data)?))
+    }
+}
+
+pub struct VaultBackend {
+    pub addr: String,
+    pub token: String,
+    pub mount: String
+}
+
+impl VaultBackend {
+    pub fn from_env() -> error::Result<Self> {
+        let addr = std::env::var("VAULT_ADDR").map_err(|_| error::error("VAULT_ADDR must be set for the vault secret backend"))?;
+        let token = std::env::var("VAULT_TOKEN").map_err(|_| error::error("VAULT_TOKEN must be set for the vault secret backend"))?;
+        let mount = std::env::var("VAULT_MOUNT").unwrap_or_else(|_| String::from("secret"));
+        Ok(VaultBackend { addr, token, mount })
+    }
+
+    fn parse(body: &str) -> error::Result<(i32, HashMap<String, String>)> {
+        let value: serde_json::Value = serde_json::from_str(body)?;
+        if let Some(errors) = value.get("errors") {
+            return Err(error::error(&format!("Vault returns errors: {}", errors)));
+        }
+        let version = value["data"]["metadata"]["version"].as_i64()
+            .ok_or_else(|| error::error("Unable to find the version in the vault response"))?;
+        let fields = value["data"]["data"].as_object()
+            .ok_or_else(|| error::error("Unable to find the data in the vault response"))?;
+        let data = fields.iter().map(|(k, v)| {
+            let v = match v {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string()
+            };
+            (k.clone(), v)
+        }).collect();
+        Ok((version as i32, data))
+    }
+}
+
+impl SecretBackend for VaultBackend {
+    fn read(&mut self, path: &str, version: Option<i32>) -> error::Result<(i32, HashMap<String, String>)> {
+        check_path(path)?;
+        let url = format!("{}/v1/{}/data/{}", self.addr.trim_end_matches('/'), self.mount, path);
+        let mut request = ureq::get(&url).set("X-Vault-Token", &self.token);
+        if let Some(version) = version {
+            request = request.query("version", &version.to_string());
+        }
+        match request.call() {
+            Ok(response) => Self::parse(&response.into_string()?),
+            // The errors of vault are in the body, e.g. the secret is not found.
+            Err(ureq::Error::Status(_, response)) => Self::parse(&response.into_string()?),
+            Err(err) => Err(error::error(&format!("Failed to read the secret {} from vault: {}", path, err)))
+        }
+    }
+}
+
+pub struct FileBackend {
+    pub root: PathBuf
+}
+
+impl FileBackend {
+    pub fn from_env() -> error::Result<Self> {
+        let root = std::env::var("TRAIN_SECRET_DIR").map_err(|_| error::error("TRAIN_SECRET_DIR must be set for the file secret backend"))?;
+        Ok(FileBackend { root: PathBuf::from(root) })
+    }
+
+    fn versions(dir: &PathBuf) -> error::Result<Vec<i32>> {
+        let mut versions = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if let Ok(version) = entry.file_name().to_string_lossy().parse::<i32>() {
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+}
+
+impl SecretBackend for FileBackend {
+    fn read(&mut self, path: &str, version: Option<i32>) -> error::Result<(i32, HashMap<String, String>)> {
+        check_path(path)?;
+        // The links are resolved too, the secret must stay under the root.
+        let root = self.root.canonicalize()?;
+        let dir = root.join(path).canonicalize()?;
+        if !dir.starts_with(&root) {
+            return Err(error::bad_request(&format!("Invalid secret path: {}", path)));
+        }
+        let versions = Self::versions(&dir)?;
+        let (version, dir) = match (version, versions.last()) {
+            (Some(version), _) if versions.contains(&version) => (version, dir.join(version.to_string())),
+            (Some(1), None) => (1, dir),
+            (Some(version), _) => return Err(error::error(&format!("Unable to find the version {} of the secret: {}", version, path))),
+            (None, Some(latest)) => (*latest, dir.join(latest.to_string())),
+            (None, None) => (1, dir)
+        };
+        let mut data = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                let value = std::fs::read_to_string(entry.path())?;
+                data.insert(entry.file_name().to_string_lossy().to_string(), value);
+            }
+        }
+        Ok((version, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare_dir(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("train-secret-backend-{}", name));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_file_backend_unversioned() {
+        let root = prepare_dir("unversioned");
+        std::fs::create_dir_all(root.join("pivnet")).unwrap();
+        std::fs::write(root.join("pivnet/token"), "token123456").unwrap();
+        let mut backend = FileBackend { root };
+        let (version, data) = backend.read("pivnet", None).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(data["token"], "token123456");
+        assert!(backend.read("pivnet", Some(2)).is_err());
+    }
+
+    #[test]
+    fn test_file_backend_versioned() {
+        let root = prepare_dir("versioned");
+        for version in ["1", "2"] {
+            std::fs::create_dir_all(root.join("aws-route53").join(version)).unwrap();
+            std::fs::write(root.join("aws-route53").join(version).join("secret"), format!("secret-v{}", version)).unwrap();
+        }
+        let mut backend = FileBackend { root };
+        let (version, data) = backend.read("aws-route53", None).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(data["secret"], "secret-v2");
+        let (version, data) = backend.read("aws-route53", Some(1)).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(data["secret"], "secret-v1");
+    }
+
+    #[test]
+    fn test_file_backend_outside_root() {
+        let root = prepare_dir("outside");
+        std::fs::create_dir_all(root.join("team-a/pivnet")).unwrap();
+        std::fs::write(root.join("team-a/pivnet/token"), "token123456").unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("team-a/etc")).unwrap();
+        let mut backend = FileBackend { root: root.join("team-a") };
+        assert!(backend.read("pivnet", None).is_ok());
+        assert!(backend.read("../team-a/pivnet", None).is_err());
+        assert!(backend.read("/etc", None).is_err());
+        assert!(backend.read("etc", None).is_err());
+    }
+
+    #[test]
+    fn test_check_path() {
+        assert!(check_path("pivnet").is_ok());
+        assert!(check_path("aws/route53").is_ok());
+        for path in ["", "/etc/passwd", "../team-b/pivnet", "aws/../../team-b", "./pivnet", "pivnet?version=1"] {
+            assert!(check_path(path).is_err(), "{} is accepted", path);
+        }
+    }
+
+    #[test]
+    fn test_vault_read() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buff = [0u8; 4096];
+            let len = stream.read(&mut buff).unwrap();
+            let body = r#"{"data":{"data":{"token":"token123456"},"metadata":{"version":2}}}"#;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+            String::from_utf8_lossy(&buff[..len]).to_string()
+        });
+        let mut backend = VaultBackend { addr, token: "vault-token".to_owned(), mount: "secret".to_owned() };
+        let (version, data) = backend.read("team-a/pivnet", Some(2)).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(data["token"], "token123456");
+        let request = server.join().unwrap().to_lowercase();
+        assert!(request.starts_with("get /v1/secret/data/team-a/pivnet?version=2 "));
+        assert!(request.contains("x-vault-token: vault-token"));
+    }
+
+    #[test]
+    fn test_vault_parse() {
+        let body = r#"{"data":{"data":{"token":"token123456","port":5432},"metadata":{"version":3}}}"#;
+        let (version, data) = VaultBackend::parse(body).unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(data["token"], "token123456");
+        assert_eq!(data["port"], "5432");
+        assert!(VaultBackend::parse(r#"{"errors":["permission denied"]}"#).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Serialize, Deserialize};
+
+/// The highest tier of the teams, the teams are in the tier 0 by default.
+pub const MAX_TIER: i32 = 3;
+
+/// The payload to create a team. The token of the team is generated and returned only once.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TeamRequest {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>
+}
+
+/// The payload to update a team. The absent fields are not changed.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TeamUpdateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desp: Option<String>,
+    /// The tier from 0 to `MAX_TIER`, the artifacts of the higher tiers are scheduled first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<i32>
+}
+
+/// The resources owned by a team, which keep it from being deleted without the cascade.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TeamOwnership {
+    pub artifacts: i64,
+    pub secrets: i64,
+    pub accounts: i64
+}
+
+impl TeamOwnership {
+    pub fn is_empty(&self) -> bool {
+        self.artifacts == 0 && self.secrets == 0 && self.accounts == 0
+    }
+}
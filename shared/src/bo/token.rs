@@ -0,0 +1,152 @@
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+
+/// The operations allowed to an api token. The team token is allowed to do everything.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Show and list the artifacts.
+    #[serde(rename = "art:read")]
+    ArtRead,
+    /// Borrow and return the instances, and read their credentials. It is the scope for the CI.
+    #[serde(rename = "art:borrow")]
+    ArtBorrow,
+    /// Create, update and delete the artifacts.
+    #[serde(rename = "art:write")]
+    ArtWrite,
+    /// Show and list the secrets and the account pools, without their data.
+    #[serde(rename = "sec:read")]
+    SecRead,
+    /// Manage the secrets and the account pools.
+    #[serde(rename = "sec:admin")]
+    SecAdmin
+}
+
+impl Scope {
+    /// The scopes are nested: `art:write` grants `art:borrow`, which grants `art:read`, and
+    /// `sec:admin` grants `sec:read`.
+    pub fn grants(&self, required: Scope) -> bool {
+        match self {
+            Self::ArtWrite => matches!(required, Self::ArtWrite | Self::ArtBorrow | Self::ArtRead),
+            Self::ArtBorrow => matches!(required, Self::ArtBorrow | Self::ArtRead),
+            Self::SecAdmin => matches!(required, Self::SecAdmin | Self::SecRead),
+            _ => *self == required
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ArtRead => "art:read",
+            Self::ArtBorrow => "art:borrow",
+            Self::ArtWrite => "art:write",
+            Self::SecRead => "sec:read",
+            Self::SecAdmin => "sec:admin"
+        })
+    }
+}
+
+/// Who acted with the token, named in the audit records.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Actor {
+    /// The team token, which is not tied to anyone.
+    Team(String),
+    /// The api token of the team.
+    ApiToken { team: String, name: String },
+    /// The user acting in the team.
+    User { team: String, name: String },
+    /// The admin token of the admin service.
+    Admin,
+    /// Train itself, such as the scheduler resolving the secrets of the artifacts it deploys.
+    System
+}
+
+impl Actor {
+    pub fn kind(&self) -> &str {
+        match self {
+            Self::Team(_) => "team",
+            Self::ApiToken { .. } => "token",
+            Self::User { .. } => "user",
+            Self::Admin => "admin",
+            Self::System => "system"
+        }
+    }
+
+    /// The team the actor acts in. The admin token and the system are not in any team.
+    pub fn team(&self) -> Option<&str> {
+        match self {
+            Self::Team(team) | Self::ApiToken { team, .. } | Self::User { team, .. } => Some(team),
+            Self::Admin | Self::System => None
+        }
+    }
+
+    /// The name of the user or the token.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Team(team) => team,
+            Self::ApiToken { name, .. } | Self::User { name, .. } => name,
+            Self::Admin => "admin",
+            Self::System => "system"
+        }
+    }
+}
+
+impl std::fmt::Display for Actor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Team(team) => write!(f, "team {}", team),
+            Self::ApiToken { team, name } => write!(f, "token {} of team {}", name, team),
+            Self::User { team, name } => write!(f, "user {} of team {}", name, team),
+            Self::Admin => f.write_str("admin"),
+            Self::System => f.write_str("system")
+        }
+    }
+}
+
+/// The payload to issue an api token. The token never expires if `expires_at` is absent.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<NaiveDateTime>
+}
+
+/// The token just issued. It is the only time the token is returned.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct IssuedToken {
+    pub name: String,
+    pub token: String
+}
+
+/// The api token as it is listed to the team. The prefix tells the tokens apart.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_grants() {
+        assert!(Scope::ArtWrite.grants(Scope::ArtBorrow));
+        assert!(Scope::ArtBorrow.grants(Scope::ArtRead));
+        assert!(!Scope::ArtBorrow.grants(Scope::ArtWrite));
+        assert!(!Scope::ArtRead.grants(Scope::ArtBorrow));
+        assert!(!Scope::ArtWrite.grants(Scope::SecAdmin));
+        assert!(Scope::SecAdmin.grants(Scope::SecAdmin));
+        assert!(Scope::SecAdmin.grants(Scope::SecRead));
+        assert!(!Scope::SecRead.grants(Scope::SecAdmin));
+        let scopes: Vec<Scope> = serde_json::from_str(r#"["art:borrow","sec:admin"]"#).unwrap();
+        assert_eq!(scopes, vec![Scope::ArtBorrow, Scope::SecAdmin]);
+        assert_eq!(Scope::ArtRead.to_string(), "art:read");
+    }
+}
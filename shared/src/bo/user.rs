@@ -0,0 +1,97 @@
+use serde::{Serialize, Deserialize};
+use super::token::Scope;
+
+/// The role of the user. The global admin is a flag of the user, the other roles are given to
+/// the user in each of the teams. The roles are ordered from the most powerful.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can do everything in all the teams.
+    Admin,
+    /// Can edit and delete the artifacts, secrets and account pools of the team.
+    Owner,
+    /// Can borrow and return the instances.
+    Member,
+    /// Can list and show.
+    Viewer
+}
+
+impl Role {
+    /// The scopes of the role, checked per route in the same way as the api tokens. The admin is
+    /// not held to the ownership of its team either, see `TokenOps::is_admin`.
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Self::Admin => vec![Scope::ArtWrite, Scope::ArtBorrow, Scope::ArtRead, Scope::SecAdmin, Scope::SecRead],
+            Self::Owner => vec![Scope::ArtWrite, Scope::SecAdmin],
+            Self::Member => vec![Scope::ArtBorrow, Scope::SecRead],
+            Self::Viewer => vec![Scope::ArtRead, Scope::SecRead]
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+            Self::Member => "member",
+            Self::Viewer => "viewer"
+        })
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "owner" => Ok(Self::Owner),
+            "member" => Ok(Self::Member),
+            "viewer" => Ok(Self::Viewer),
+            _ => Err(format!("Unknown role: {}", s))
+        }
+    }
+}
+
+/// The payload to create a user.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct UserRequest {
+    pub name: String,
+    #[serde(default)]
+    pub admin: bool
+}
+
+/// The payload to add the user to the team. The token of the user in the team is returned only
+/// once.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MemberRequest {
+    pub user: String,
+    pub role: Role
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MemberUpdateRequest {
+    pub role: Role
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MemberInfo {
+    pub user: String,
+    pub role: Role
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_scopes() {
+        assert!(Role::Viewer.scopes().iter().any(|scope| scope.grants(Scope::ArtRead)));
+        assert!(!Role::Viewer.scopes().iter().any(|scope| scope.grants(Scope::ArtBorrow)));
+        assert!(Role::Member.scopes().iter().any(|scope| scope.grants(Scope::ArtBorrow)));
+        assert!(!Role::Member.scopes().iter().any(|scope| scope.grants(Scope::ArtWrite)));
+        assert!(Role::Owner.scopes().iter().any(|scope| scope.grants(Scope::SecAdmin)));
+        assert_eq!("member".parse::<Role>(), Ok(Role::Member));
+        assert!("guest".parse::<Role>().is_err());
+    }
+}
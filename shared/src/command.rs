@@ -10,11 +10,6 @@ pub fn command_with_args<C, A>(cmd: C, args: A) -> Command where C: AsRef<OsStr>
     command
 }
 
-#[inline]
-pub fn piped(cmd_ref: &mut Command) -> &mut Command {
-    cmd_ref.stdout(Stdio::piped())
-}
-
 pub fn pipe_run(commands: &mut [Command]) -> Result<Output>{
     let mut previous: Option<Child> = None;
     for c in commands {
@@ -0,0 +1,136 @@
+//! Encrypt the sensitive data before it is saved to the DB.
+//! The key is the 32 bytes in hex from the env `TRAIN_ENCRYPTION_KEY`. The cipher text is the hex
+//! of the nonce followed by the encrypted data.
+//! The team tokens are saved as the salted hashes instead, since they only need to be verified.
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use crate::error;
+
+const NONCE_LEN: usize = 12;
+
+const TOKEN_HEAD: &str = "trn_";
+/// The length of the lookup prefix of the tokens, which is the head of the token followed by 8
+/// random chars. It is saved in plaintext to find the team without checking every hash.
+pub const TOKEN_PREFIX_LEN: usize = 12;
+
+/// Generate the random token `trn_` followed by 32 bytes in hex.
+pub fn generate_token() -> String {
+    format!("{}{}", TOKEN_HEAD, random_hex(32))
+}
+
+pub fn generate_salt() -> String {
+    random_hex(16)
+}
+
+/// The lookup prefix of the token. The short tokens issued before are their own prefixes.
+pub fn token_prefix(token: &str) -> &str {
+    token.get(..TOKEN_PREFIX_LEN).unwrap_or(token)
+}
+
+/// The sha256 of the salt followed by the token, in hex.
+pub fn hash_token(salt: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn verify_token(salt: &str, token: &str, hash: &str) -> bool {
+    hash_token(salt, token).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+/// Compare the secrets in constant time, such as the admin token.
+pub fn verify_secret(expected: &str, given: &str) -> bool {
+    expected.as_bytes().ct_eq(given.as_bytes()).into()
+}
+
+/// The sha256 of the data in hex, such as the digest of the state of a resource in the audit log.
+pub fn digest(data: &str) -> String {
+    hex::encode(Sha256::digest(data.as_bytes()))
+}
+
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn encrypt(plain: &str) -> error::Result<String> {
+    encrypt_with(&key()?, plain)
+}
+
+pub fn decrypt(cipher_text: &str) -> error::Result<String> {
+    decrypt_with(&key()?, cipher_text)
+}
+
+/// Check the encryption key is set and valid, so the services fail at startup rather than on the
+/// first secret.
+pub fn check_key() -> error::Result<()> {
+    key().map(|_| ())
+}
+
+fn key() -> error::Result<Vec<u8>> {
+    dotenvy::dotenv().ok();
+    let key = std::env::var("TRAIN_ENCRYPTION_KEY").map_err(|_| error::error("TRAIN_ENCRYPTION_KEY must be set"))?;
+    let key = hex::decode(key.trim()).map_err(|err| error::error(&format!("TRAIN_ENCRYPTION_KEY is not in hex: {}", err)))?;
+    if key.len() != 32 {
+        return Err(error::error("TRAIN_ENCRYPTION_KEY must be 32 bytes"));
+    }
+    Ok(key)
+}
+
+fn encrypt_with(key: &[u8], plain: &str) -> error::Result<String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted = cipher.encrypt(&nonce, plain.as_bytes()).map_err(|_| error::error("Failed to encrypt the data"))?;
+    let mut buff = nonce.to_vec();
+    buff.extend(encrypted);
+    Ok(hex::encode(buff))
+}
+
+fn decrypt_with(key: &[u8], cipher_text: &str) -> error::Result<String> {
+    let buff = hex::decode(cipher_text).map_err(|err| error::error(&format!("The cipher text is not in hex: {}", err)))?;
+    if buff.len() < NONCE_LEN {
+        return Err(error::error("The cipher text is too short"));
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let (nonce, encrypted) = buff.split_at(NONCE_LEN);
+    let plain = cipher.decrypt(Nonce::from_slice(nonce), encrypted).map_err(|_| error::error("Failed to decrypt the data"))?;
+    String::from_utf8(plain).map_err(|err| error::error(&format!("The decrypted data is not utf8: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let key = [7u8; 32];
+        let cipher_text = encrypt_with(&key, "tjax21").unwrap();
+        assert!(!cipher_text.contains("tjax21"));
+        assert_ne!(cipher_text, encrypt_with(&key, "tjax21").unwrap());
+        assert_eq!(decrypt_with(&key, &cipher_text).unwrap(), "tjax21");
+        assert!(decrypt_with(&[8u8; 32], &cipher_text).is_err());
+    }
+
+    #[test]
+    fn test_hash_token() {
+        let token = generate_token();
+        assert_eq!(token.len(), TOKEN_HEAD.len() + 64);
+        assert_ne!(token, generate_token());
+        assert_eq!(token_prefix(&token), &token[..TOKEN_PREFIX_LEN]);
+        assert_eq!(token_prefix("123456"), "123456");
+        let salt = generate_salt();
+        let hash = hash_token(&salt, &token);
+        assert!(verify_token(&salt, &token, &hash));
+        assert!(!verify_token(&salt, "trn_guess", &hash));
+        assert!(!verify_token(&generate_salt(), &token, &hash));
+        // The same as the migration hashing the plaintext tokens by postgres.
+        assert_eq!(hash_token("salt", "123456"), "9898410d7f5045bc673db80c1a49b74f088fd7440037d8ce25c7d272a505bce5");
+        assert!(verify_secret("admin-token", "admin-token"));
+        assert!(!verify_secret("admin-token", "admin-toke"));
+    }
+}
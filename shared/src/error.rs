@@ -6,12 +6,28 @@ pub type Result<T> = std::result::Result<T, GeneralError>;
 
 #[derive(Debug)]
 pub enum GeneralError {
+    /// The internal failures, their details are kept out of the responses.
     Error(String),
+    /// The request is invalid, it tells what to fix.
+    BadRequest(String),
+    /// The resource asked for is not found.
+    NotFound(String),
+    /// The state of the resources refuses the request, such as a team still owning artifacts.
+    Conflict(String),
     IoError(std::io::Error),
     ProcessError(String),
     PendingArtRef,
-    PendingAccount,
+    /// The account pool is exhausted.
+    PendingAccount(String),
     PipelineError(String),
+    /// The token is unknown, revoked or expired.
+    Unauthorized,
+    /// The token is valid but it is not allowed to do the operation.
+    Forbidden(String),
+    /// The team would go over its quota, it tells the usage against the limit.
+    QuotaExceeded(String),
+    /// The lease on the resource expired and another scheduler may have taken it over.
+    LeaseLost(String),
     RedisError(redis::RedisError),
     SerdeJsonError(serde_json::Error),
     SerdeYamlError(serde_yaml::Error),
@@ -22,6 +38,22 @@ pub enum GeneralError {
 pub fn error(msg: &str) -> GeneralError {
     GeneralError::Error(msg.to_owned())
 }
+
+#[inline]
+pub fn bad_request(msg: &str) -> GeneralError {
+    GeneralError::BadRequest(msg.to_owned())
+}
+
+#[inline]
+pub fn not_found(msg: &str) -> GeneralError {
+    GeneralError::NotFound(msg.to_owned())
+}
+
+#[inline]
+pub fn conflict(msg: &str) -> GeneralError {
+    GeneralError::Conflict(msg.to_owned())
+}
+
 pub fn new_process_error<E: AsRef<str>>(err: E) -> GeneralError {
     let error_info: String = err.as_ref().to_string();
     GeneralError::ProcessError(error_info)
@@ -78,12 +110,27 @@ impl From<diesel::result::Error> for GeneralError {
 
 impl actix_web::error::ResponseError for GeneralError {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::from_u16(500).unwrap()
+        match self {
+            Self::BadRequest(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            Self::Unauthorized => actix_web::http::StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) | Self::QuotaExceeded(_) => actix_web::http::StatusCode::FORBIDDEN,
+            Self::NotFound(_) | Self::DBError(diesel::result::Error::NotFound) => actix_web::http::StatusCode::NOT_FOUND,
+            Self::Conflict(_) => actix_web::http::StatusCode::CONFLICT,
+            Self::DBError(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => actix_web::http::StatusCode::CONFLICT,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
 
+    /// The details of the internal errors may quote the output of the commands and the backends,
+    /// they are logged and the client gets a generic message. The other errors are told to the
+    /// client as they are.
     fn error_response(&self) -> HttpResponse<actix_http::body::BoxBody> {
-        //TODO: return true response
-        HttpResponse::build(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR).body(format!("{}", self)) 
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("the request failed: {}", self);
+            return HttpResponse::build(status).body("Internal error, see the logs of train");
+        }
+        HttpResponse::build(status).body(format!("{}", self))
     }
 }
 
@@ -91,11 +138,18 @@ impl Display for GeneralError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Self::Error(err) => f.write_fmt(format_args!("Error: {}", err))?,
+            Self::BadRequest(desc) => f.write_fmt(format_args!("BadRequest: {}", desc))?,
+            Self::NotFound(desc) => f.write_fmt(format_args!("NotFound: {}", desc))?,
+            Self::Conflict(desc) => f.write_fmt(format_args!("Conflict: {}", desc))?,
             Self::IoError(err) => f.write_fmt(format_args!("IoError: {}", err))?,
             Self::ProcessError(desc) => f.write_fmt(format_args!("ProcessError: {}", desc))?,
             Self::PendingArtRef => f.write_fmt(format_args!("PendingArtRef"))?,
-            Self::PendingAccount => f.write_fmt(format_args!("PendingAccount"))?,
+            Self::PendingAccount(pool) => f.write_fmt(format_args!("PendingAccount: {}", pool))?,
             Self::PipelineError(desc) => f.write_fmt(format_args!("PipelineError: {}", desc))?,
+            Self::Unauthorized => f.write_fmt(format_args!("Unauthorized"))?,
+            Self::Forbidden(desc) => f.write_fmt(format_args!("Forbidden: {}", desc))?,
+            Self::QuotaExceeded(desc) => f.write_fmt(format_args!("QuotaExceeded: {}", desc))?,
+            Self::LeaseLost(desc) => f.write_fmt(format_args!("LeaseLost: {}", desc))?,
             Self::RedisError(desc) => f.write_fmt(format_args!("RedisError: {}", desc))?,
             Self::SerdeJsonError(err) => f.write_fmt(format_args!("SerdeJsonError: {}", err))?,
             Self::SerdeYamlError(err) => f.write_fmt(format_args!("SerdeYamlError: {}", err))?,
@@ -106,3 +160,31 @@ impl Display for GeneralError {
 }
 
 impl std::error::Error for GeneralError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::ResponseError;
+    use actix_web::body::MessageBody;
+
+    fn body(err: &GeneralError) -> String {
+        let bytes = err.error_response().into_body().try_into_bytes().unwrap();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    #[test]
+    fn test_error_response() {
+        let err = error("Failed to read the secret: token123456");
+        assert_eq!(err.status_code(), actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body(&err).contains("token123456"));
+        let err = GeneralError::Forbidden("The token ci has no scope art:write".to_owned());
+        assert_eq!(err.status_code(), actix_web::http::StatusCode::FORBIDDEN);
+        assert!(body(&err).contains("art:write"));
+        let err = bad_request("Invalid secret path: ../other");
+        assert_eq!(err.status_code(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert_eq!(body(&err), "BadRequest: Invalid secret path: ../other");
+        let err = conflict("The team Team C still owns 1 artifacts");
+        assert_eq!(err.status_code(), actix_web::http::StatusCode::CONFLICT);
+        assert!(body(&err).contains("still owns"));
+    }
+}
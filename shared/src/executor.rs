@@ -0,0 +1,166 @@
+//! The executors that run the pipelines, see `scheduler::Executable`. The executor is selected by
+//! the env `TRAIN_EXECUTOR`:
+//! - `tekton`: the pipelines are applied to the Tekton of the cluster and run by `tkn`, in the
+//!   namespace `train`. It is the default.
+//! - `local`: the tasks run as local processes, or in the containers of a local runtime, see
+//!   `local::LocalExecutor`.
+pub mod local;
+
+use std::collections::HashMap;
+use crate::bo::{artifact, pipeline};
+use crate::bo::manifest::Secret;
+use crate::error;
+use crate::redact::Redactor;
+use crate::scheduler::{Executable, RunStatus};
+
+pub const TEKTON: &str = "tekton";
+pub const LOCAL: &str = "local";
+
+/// Open the executor of the kind.
+pub fn open(kind: &str) -> error::Result<Box<dyn Executable>> {
+    match kind {
+        TEKTON => Ok(Box::new(TektonExecutor::default())),
+        LOCAL => Ok(Box::new(local::LocalExecutor::from_env())),
+        _ => Err(error::error(&format!("Unknown executor: {}", kind)))
+    }
+}
+
+/// Open the executor configured by the env `TRAIN_EXECUTOR`, or the Tekton executor if it is not
+/// set.
+pub fn from_env() -> error::Result<Box<dyn Executable>> {
+    let kind = std::env::var("TRAIN_EXECUTOR").unwrap_or_else(|_| TEKTON.to_owned());
+    open(&kind)
+}
+
+pub struct TektonExecutor {
+    pub namespace: String
+}
+
+impl Default for TektonExecutor {
+    fn default() -> Self {
+        TektonExecutor { namespace: artifact::DEFAULT_NAMESPACE.to_owned() }
+    }
+}
+
+impl TektonExecutor {
+    /// The status of the run from its condition `Succeeded`, which is `Unknown` until the run is
+    /// done.
+    fn parse_status(run: &str) -> error::Result<RunStatus> {
+        let value: serde_json::Value = serde_json::from_str(run)?;
+        let condition = value["status"]["conditions"].as_array()
+            .and_then(|conditions| conditions.iter().find(|cond| cond["type"] == "Succeeded"));
+        let condition = match condition {
+            Some(condition) => condition,
+            None => return Ok(RunStatus::Pending)
+        };
+        let reason = condition["reason"].as_str().unwrap_or_default();
+        let stat = match (condition["status"].as_str().unwrap_or_default(), reason) {
+            ("True", _) => RunStatus::Succeeded,
+            ("False", "Cancelled") | ("False", "PipelineRunCancelled") => RunStatus::Cancelled,
+            ("False", _) => RunStatus::Failed(condition["message"].as_str().filter(|msg| !msg.is_empty()).unwrap_or(reason).to_owned()),
+            (_, "PipelineRunPending") => RunStatus::Pending,
+            _ => RunStatus::Running
+        };
+        Ok(stat)
+    }
+
+    /// The results of the run, they are under `pipelineResults` before `tekton.dev/v1`. The values
+    /// other than strings are kept in json.
+    fn parse_results(run: &str) -> error::Result<HashMap<String, String>> {
+        let value: serde_json::Value = serde_json::from_str(run)?;
+        let results = value["status"]["results"].as_array()
+            .or_else(|| value["status"]["pipelineResults"].as_array());
+        let mut parsed = HashMap::new();
+        for result in results.into_iter().flatten() {
+            if let Some(name) = result["name"].as_str() {
+                let value = match &result["value"] {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string()
+                };
+                parsed.insert(name.to_owned(), value);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl Executable for TektonExecutor {
+    fn apply(&self, _pipeline: &str, manifest: &str, redactor: &Redactor) -> error::Result<()> {
+        pipeline::apply(manifest, &self.namespace, redactor)?;
+        Ok(())
+    }
+
+    fn apply_secrets(&self, secrets: &[Secret], redactor: &Redactor) -> error::Result<()> {
+        let mut buff = String::new();
+        for sec in secrets {
+            buff.push_str("---\n");
+            buff.push_str(&serde_yaml::to_string(sec)?);
+        }
+        pipeline::apply(buff, &self.namespace, redactor)?;
+        Ok(())
+    }
+
+    fn start(&self, pipeline: &str, params: &[(String, String)], redactor: &Redactor) -> error::Result<String> {
+        let args: Vec<String> = params.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Ok(pipeline::run(pipeline, &self.namespace, &args, redactor)?)
+    }
+
+    fn status(&self, run_name: &str) -> error::Result<RunStatus> {
+        Self::parse_status(&pipeline::describe_run(run_name, &self.namespace)?)
+    }
+
+    fn results(&self, run_name: &str, redactor: &Redactor) -> error::Result<HashMap<String, String>> {
+        let run = pipeline::describe_run(run_name, &self.namespace)
+            .map_err(|err| error::error(&redactor.redact(err.to_string())))?;
+        let results = Self::parse_results(&run)?;
+        Ok(results.into_iter().map(|(name, value)| (name, redactor.redact(value))).collect())
+    }
+
+    fn logs(&self, run_name: &str, redactor: &Redactor) -> error::Result<String> {
+        Ok(pipeline::logs(run_name, &self.namespace, redactor)?)
+    }
+
+    fn cancel(&self, run_name: &str) -> error::Result<()> {
+        Ok(pipeline::cancel_run(run_name, &self.namespace)?)
+    }
+
+    fn delete(&self, run_name: &str) -> error::Result<()> {
+        Ok(pipeline::delete_run(run_name, &self.namespace)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tekton_status() {
+        let run = |status: &str, reason: &str, message: &str| format!(r#"{{"status":{{"conditions":[{{"type":"Succeeded","status":"{}","reason":"{}","message":"{}"}}]}}}}"#, status, reason, message);
+        assert_eq!(TektonExecutor::parse_status(r#"{"status":{}}"#).unwrap(), RunStatus::Pending);
+        assert_eq!(TektonExecutor::parse_status(&run("Unknown", "PipelineRunPending", "")).unwrap(), RunStatus::Pending);
+        assert_eq!(TektonExecutor::parse_status(&run("Unknown", "Running", "Tasks Completed: 0")).unwrap(), RunStatus::Running);
+        assert_eq!(TektonExecutor::parse_status(&run("True", "Succeeded", "Tasks Completed: 2")).unwrap(), RunStatus::Succeeded);
+        assert_eq!(TektonExecutor::parse_status(&run("False", "Cancelled", "")).unwrap(), RunStatus::Cancelled);
+        assert_eq!(TektonExecutor::parse_status(&run("False", "Failed", "Tasks Completed: 1 (Failed: 1)")).unwrap(), RunStatus::Failed("Tasks Completed: 1 (Failed: 1)".to_owned()));
+        assert_eq!(TektonExecutor::parse_status(&run("False", "PipelineRunTimeout", "")).unwrap(), RunStatus::Failed("PipelineRunTimeout".to_owned()));
+        assert!(TektonExecutor::parse_status("not json").is_err());
+    }
+
+    #[test]
+    fn test_tekton_results() {
+        let results = TektonExecutor::parse_results(r#"{"status":{"results":[{"name":"url","value":"https://opsman"},{"name":"ips","value":["10.0.0.1"]}]}}"#).unwrap();
+        assert_eq!(results.get("url").unwrap(), "https://opsman");
+        assert_eq!(results.get("ips").unwrap(), r#"["10.0.0.1"]"#);
+        let results = TektonExecutor::parse_results(r#"{"status":{"pipelineResults":[{"name":"url","value":"https://opsman"}]}}"#).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(TektonExecutor::parse_results(r#"{"status":{}}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open() {
+        assert!(open(TEKTON).is_ok());
+        assert!(open(LOCAL).is_ok());
+        assert!(open("argo").is_err());
+    }
+}
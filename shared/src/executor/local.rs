@@ -0,0 +1,763 @@
+//! The executor running the tasks of the pipelines as local processes, without Kubernetes. The
+//! tasks run one by one, each after the tasks in its `runAfter`. The steps run by `sh` on the host
+//! and their images are ignored, unless the container runtime is set by `TRAIN_LOCAL_RUNTIME`, such
+//! as `docker` or `podman`, which runs each step in its image.
+//!
+//! The pipelines, the secrets and the runs are kept under `TRAIN_LOCAL_DIR`, default is
+//! `train-local` under the temp directory, so the services on the same host share them:
+//! - `pipelines/{pipeline}.yaml`: the manifest of the pipeline as it is applied.
+//! - `secrets/{secret}/{key}`: a file for each key of the secret.
+//! - `runs/{run}/`: the status, the logs of the steps and the results of the run.
+//!
+//! A run is a thread of the service starting it, which records itself as the owner of the run. The
+//! run whose owner is gone, such as the service restarted, is failed with `executor restarted`.
+//! Only the services on the host and in the pid namespace of the owner can tell it is gone, for the
+//! others, such as the reconciller in another container, the run keeps its status.
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use serde::{Serialize, Deserialize};
+use crate::bo::manifest::{EnvValue, Metadata, PipelineSpec, Secret, TaskDef, TaskSpec, TaskStep, VolumeType};
+use crate::bo::naming;
+use crate::error;
+use crate::redact::Redactor;
+use crate::scheduler::{Executable, RunStatus};
+
+/// The milliseconds between two checks of the running step for its exit and the cancel of the run.
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// The marker file asking the run to stop.
+const CANCEL_FILE: &str = "cancel";
+const STATUS_FILE: &str = "status.json";
+const RESULTS_FILE: &str = "results.json";
+/// The pid, the start time and the namespace of the process running the run.
+const OWNER_FILE: &str = "owner";
+/// The reason of the runs failed since their owner is gone.
+const RESTARTED: &str = "executor restarted";
+
+pub struct LocalExecutor {
+    /// The directory of the pipelines, the secrets and the runs.
+    pub root: PathBuf,
+    /// The container runtime running the steps in their images, the steps run on the host if it
+    /// is absent.
+    pub runtime: Option<String>
+}
+
+impl LocalExecutor {
+    pub fn from_env() -> Self {
+        let root = std::env::var("TRAIN_LOCAL_DIR").map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("train-local"));
+        let runtime = std::env::var("TRAIN_LOCAL_RUNTIME").ok().filter(|runtime| !runtime.is_empty());
+        LocalExecutor { root, runtime }
+    }
+
+    fn pipeline_file(&self, pipeline: &str) -> PathBuf {
+        self.root.join("pipelines").join(format!("{}.yaml", pipeline))
+    }
+
+    /// The directory of the run, it fails if the run is not found.
+    fn run_dir(&self, run_name: &str) -> error::Result<PathBuf> {
+        let dir = self.root.join("runs").join(run_name);
+        if !dir.is_dir() {
+            return Err(error::error(&format!("The run {} is not found", run_name)));
+        }
+        Ok(dir)
+    }
+
+    /// Create the directory of a new run of the pipeline, named like the Tekton runs
+    /// `{pipeline}-run-{id}`.
+    fn create_run_dir(&self, pipeline: &str) -> error::Result<(String, PathBuf)> {
+        let runs = self.root.join("runs");
+        fs::create_dir_all(&runs)?;
+        loop {
+            let name = format!("{}-run-{}", pipeline, naming::random_id());
+            let dir = runs.join(&name);
+            match fs::create_dir(&dir) {
+                Ok(_) => return Ok((name, dir)),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into())
+            }
+        }
+    }
+}
+
+impl Executable for LocalExecutor {
+    fn apply(&self, pipeline: &str, manifest: &str, _redactor: &Redactor) -> error::Result<()> {
+        Definition::parse(manifest)?;
+        let file = self.pipeline_file(pipeline);
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&file, manifest)?;
+        log::info!("applied the pipeline {} to {}", pipeline, file.display());
+        Ok(())
+    }
+
+    fn apply_secrets(&self, secrets: &[Secret], _redactor: &Redactor) -> error::Result<()> {
+        for sec in secrets {
+            let dir = self.root.join("secrets").join(&sec.metadata.name);
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+            fs::create_dir_all(&dir)?;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+            for (key, value) in &sec.string_data {
+                fs::write(dir.join(key), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&self, pipeline: &str, params: &[(String, String)], redactor: &Redactor) -> error::Result<String> {
+        let manifest = fs::read_to_string(self.pipeline_file(pipeline))
+            .map_err(|err| error::error(&format!("The pipeline {} is not applied: {}", pipeline, err)))?;
+        let definition = Definition::parse(&manifest)?;
+        let mut values = definition.params(params)?;
+        let (name, dir) = self.create_run_dir(pipeline)?;
+        values.insert("context.pipelineRun.name".to_owned(), name.clone());
+        for sub_dir in ["logs", "results", "scripts", "workspace"] {
+            fs::create_dir(dir.join(sub_dir))?;
+        }
+        if let (Some(owner), Some(namespace)) = (process_owner(std::process::id()), pid_namespace()) {
+            fs::write(dir.join(OWNER_FILE), format!("{} {}", owner, namespace))?;
+        }
+        write_status(&dir, &RunStatus::Running)?;
+        let mut run = Run {
+            name: name.clone(),
+            dir,
+            secrets: self.root.join("secrets"),
+            runtime: self.runtime.clone(),
+            definition,
+            values
+        };
+        let args: Vec<String> = params.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        log::info!("started the run {} of {} with {}", name, pipeline, redactor.redact(args.join(" ")));
+        std::thread::spawn(move || run.execute());
+        Ok(name)
+    }
+
+    fn status(&self, run_name: &str) -> error::Result<RunStatus> {
+        let dir = self.run_dir(run_name)?;
+        let stat = read_status(&dir)?;
+        if !stat.is_done() && owner_gone(&dir)? {
+            log::warn!("the owner of the run {} is gone, fail it", run_name);
+            let stat = RunStatus::Failed(RESTARTED.to_owned());
+            write_status(&dir, &stat)?;
+            return Ok(stat);
+        }
+        Ok(stat)
+    }
+
+    fn results(&self, run_name: &str, redactor: &Redactor) -> error::Result<HashMap<String, String>> {
+        let file = self.run_dir(run_name)?.join(RESULTS_FILE);
+        if !file.exists() {
+            return Ok(HashMap::new());
+        }
+        let results: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(file)?)?;
+        Ok(redactor.redact_map(&results))
+    }
+
+    /// The logs of the steps in the order they run, each line is prefixed by `[task : step]` as
+    /// `tkn` does.
+    fn logs(&self, run_name: &str, redactor: &Redactor) -> error::Result<String> {
+        let mut files: Vec<PathBuf> = fs::read_dir(self.run_dir(run_name)?.join("logs"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        files.sort();
+        let mut logs = String::new();
+        for file in files {
+            // The logs are named `{seq}.{task}.{step}.log`, the names of the tasks and steps have no dots.
+            let file_name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let parts: Vec<&str> = file_name.split('.').collect();
+            let prefix = match parts.as_slice() {
+                [_, task, step, _] => format!("[{} : {}] ", task, step),
+                _ => String::new()
+            };
+            for line in String::from_utf8_lossy(&fs::read(&file)?).lines() {
+                logs.push_str(&prefix);
+                logs.push_str(line);
+                logs.push('\n');
+            }
+        }
+        Ok(redactor.redact(logs))
+    }
+
+    fn cancel(&self, run_name: &str) -> error::Result<()> {
+        let dir = self.run_dir(run_name)?;
+        if read_status(&dir)?.is_done() {
+            return Ok(());
+        }
+        // Nobody is left to see the marker of the run whose owner is gone.
+        if owner_gone(&dir)? {
+            write_status(&dir, &RunStatus::Cancelled)
+        } else {
+            fs::write(dir.join(CANCEL_FILE), "")?;
+            Ok(())
+        }
+    }
+
+    fn delete(&self, run_name: &str) -> error::Result<()> {
+        self.cancel(run_name)?;
+        fs::remove_dir_all(self.run_dir(run_name)?)?;
+        Ok(())
+    }
+}
+
+/// The status of the run as it is saved in `status.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RunState {
+    stat: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>
+}
+
+fn write_status(dir: &Path, stat: &RunStatus) -> error::Result<()> {
+    let state = match stat {
+        RunStatus::Pending => RunState { stat: "Pending".to_owned(), reason: None },
+        RunStatus::Running => RunState { stat: "Running".to_owned(), reason: None },
+        RunStatus::Succeeded => RunState { stat: "Succeeded".to_owned(), reason: None },
+        RunStatus::Failed(reason) => RunState { stat: "Failed".to_owned(), reason: Some(reason.clone()) },
+        RunStatus::Cancelled => RunState { stat: "Cancelled".to_owned(), reason: None }
+    };
+    // Replace the file at once, so it is never read half written.
+    let tmp = dir.join(format!("{}.tmp", STATUS_FILE));
+    fs::write(&tmp, serde_json::to_string(&state)?)?;
+    fs::rename(tmp, dir.join(STATUS_FILE))?;
+    Ok(())
+}
+
+fn read_status(dir: &Path) -> error::Result<RunStatus> {
+    let state: RunState = serde_json::from_str(&fs::read_to_string(dir.join(STATUS_FILE))?)?;
+    let stat = match state.stat.as_str() {
+        "Pending" => RunStatus::Pending,
+        "Running" => RunStatus::Running,
+        "Succeeded" => RunStatus::Succeeded,
+        "Cancelled" => RunStatus::Cancelled,
+        _ => RunStatus::Failed(state.reason.unwrap_or(state.stat))
+    };
+    Ok(stat)
+}
+
+/// The owner of the run as `{pid} {start time}`, the start time tells apart the process reusing
+/// the pid after a restart. It is absent if the process is gone or there is no `/proc`.
+fn process_owner(pid: u32) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command in the parentheses may hold spaces, the start time is the 20th field after it.
+    let start = stat.rsplit_once(')')?.1.split_whitespace().nth(19)?;
+    Some(format!("{} {}", pid, start))
+}
+
+/// The host and the pid namespace of this process as `{hostname}/{namespace}`, the pids are only
+/// meaningful in them. The inode of the namespace alone is the same for the initial namespace of
+/// every host. It is absent if there is no `/proc`.
+fn pid_namespace() -> Option<String> {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    let namespace = fs::read_link("/proc/self/ns/pid").ok()?;
+    Some(format!("{}/{}", hostname.trim(), namespace.to_string_lossy()))
+}
+
+/// Whether the process running the run is gone. The run with no owner recorded, or recorded on
+/// another host or in another pid namespace, is taken as alive since its pid can not be looked up.
+fn owner_gone(dir: &Path) -> error::Result<bool> {
+    let owner = match fs::read_to_string(dir.join(OWNER_FILE)) {
+        Ok(owner) => owner,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into())
+    };
+    let (process, namespace) = match owner.trim().rsplit_once(' ') {
+        Some(parts) => parts,
+        None => return Ok(false)
+    };
+    if pid_namespace().as_deref() != Some(namespace) {
+        return Ok(false);
+    }
+    let pid = process.split_whitespace().next().and_then(|pid| pid.parse().ok());
+    Ok(pid.and_then(process_owner).as_deref() != Some(process))
+}
+
+/// A document of the manifest, the `Task`s and the `Pipeline` rendered by `DeployUnit`.
+#[derive(Deserialize)]
+struct Document {
+    kind: String,
+    metadata: Metadata,
+    spec: serde_yaml::Value
+}
+
+/// The pipeline as it is applied, with its tasks by their names.
+struct Definition {
+    spec: PipelineSpec,
+    tasks: HashMap<String, TaskSpec>
+}
+
+impl Definition {
+    /// Parse the manifest, it fails if the tasks of the pipeline are not defined, or they can not
+    /// be ordered by their `runAfter`.
+    fn parse(manifest: &str) -> error::Result<Self> {
+        let mut spec = None;
+        let mut tasks = HashMap::new();
+        for document in serde_yaml::Deserializer::from_str(manifest) {
+            let document = Document::deserialize(document)?;
+            match document.kind.as_str() {
+                "Pipeline" => spec = Some(serde_yaml::from_value(document.spec)?),
+                "Task" => {
+                    tasks.insert(document.metadata.name, serde_yaml::from_value(document.spec)?);
+                },
+                kind => return Err(error::error(&format!("The kind {} is not supported by the local executor", kind)))
+            }
+        }
+        let spec = spec.ok_or_else(|| error::error("The manifest has no pipeline"))?;
+        let definition = Definition { spec, tasks };
+        definition.order()?;
+        Ok(definition)
+    }
+
+    /// The tasks of the pipeline in the order they run. A task runs after the tasks in its
+    /// `runAfter`, otherwise the tasks run in the order they are declared.
+    fn order(&self) -> error::Result<Vec<&TaskDef>> {
+        for task in &self.spec.tasks {
+            if !self.tasks.contains_key(&task.task_ref.name) {
+                return Err(error::error(&format!("The task {} is not defined", task.task_ref.name)));
+            }
+            if let Some(unknown) = task.run_after.iter().flatten().find(|name| !self.spec.tasks.iter().any(|t| &&t.name == name)) {
+                return Err(error::error(&format!("The task {} runs after the unknown task {}", task.name, unknown)));
+            }
+        }
+        let mut ordered: Vec<&TaskDef> = Vec::new();
+        while ordered.len() < self.spec.tasks.len() {
+            let is_done = |name: &String| ordered.iter().any(|done| &done.name == name);
+            let next = self.spec.tasks.iter()
+                .find(|task| !is_done(&task.name) && task.run_after.iter().flatten().all(is_done));
+            match next {
+                Some(task) => ordered.push(task),
+                None => return Err(error::error("The tasks of the pipeline run after each other in a cycle"))
+            }
+        }
+        Ok(ordered)
+    }
+
+    /// The values of the params of the pipeline as `params.{name}`, the given ones or their
+    /// defaults.
+    fn params(&self, given: &[(String, String)]) -> error::Result<HashMap<String, String>> {
+        let mut values: HashMap<String, String> = given.iter()
+            .map(|(name, value)| (format!("params.{}", name), value.clone()))
+            .collect();
+        for param in self.spec.params.iter().flatten() {
+            if let Entry::Vacant(entry) = values.entry(format!("params.{}", param.name)) {
+                let value = param.default.clone()
+                    .ok_or_else(|| error::error(&format!("The param {} of the pipeline has no value", param.name)))?;
+                entry.insert(value);
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// A run of a pipeline. It holds the values referred by the tasks, the params of the pipeline
+/// and the results of the tasks done.
+struct Run {
+    name: String,
+    dir: PathBuf,
+    secrets: PathBuf,
+    runtime: Option<String>,
+    definition: Definition,
+    values: HashMap<String, String>
+}
+
+impl Run {
+    /// Run the tasks and save the status and the results of the run.
+    fn execute(&mut self) {
+        let stat = self.run_tasks().unwrap_or_else(|err| RunStatus::Failed(err.to_string()));
+        let results: HashMap<String, String> = self.definition.spec.results.iter().flatten()
+            .map(|result| (result.name.clone(), substitute(&result.value, &self.values)))
+            .filter(|(_, value)| !value.contains("$("))
+            .collect();
+        match self.save(&stat, &results) {
+            Ok(_) => log::info!("the run {} is done: {:?}", self.name, stat),
+            Err(err) => log::warn!("failed to save the status of the run {}: {}", self.name, err)
+        }
+    }
+
+    fn save(&self, stat: &RunStatus, results: &HashMap<String, String>) -> error::Result<()> {
+        fs::write(self.dir.join(RESULTS_FILE), serde_json::to_string(results)?)?;
+        write_status(&self.dir, stat)
+    }
+
+    fn run_tasks(&mut self) -> error::Result<RunStatus> {
+        let tasks: Vec<TaskDef> = self.definition.order()?.into_iter().cloned().collect();
+        let mut seq = 0;
+        for task in &tasks {
+            let stat = self.run_task(task, &mut seq)?;
+            if stat != RunStatus::Succeeded {
+                return Ok(stat);
+            }
+        }
+        Ok(RunStatus::Succeeded)
+    }
+
+    /// Run the steps of the task one by one, and keep its results as `tasks.{task}.results.{name}`
+    /// for the tasks after it.
+    fn run_task(&mut self, task: &TaskDef, seq: &mut usize) -> error::Result<RunStatus> {
+        let spec = self.definition.tasks.get(&task.task_ref.name)
+            .ok_or_else(|| error::error(&format!("The task {} is not defined", task.task_ref.name)))?
+            .clone();
+        // The params of the pipeline are propagated to the steps, those of the task override them.
+        let mut values = self.values.clone();
+        values.insert("context.pipelineTask.name".to_owned(), task.name.clone());
+        for param in spec.params.iter().flatten() {
+            let value = task.params.iter().flatten().find(|value| value.name == param.name)
+                .map(|value| substitute(&value.value, &self.values))
+                .or_else(|| param.default.clone())
+                .ok_or_else(|| error::error(&format!("The param {} of the task {} has no value", param.name, task.name)))?;
+            values.insert(format!("params.{}", param.name), value);
+        }
+        let results_dir = self.dir.join("results").join(&task.name);
+        fs::create_dir(&results_dir)?;
+        for result in spec.results.iter().flatten() {
+            values.insert(format!("results.{}.path", result.name), results_dir.join(&result.name).to_string_lossy().into_owned());
+        }
+        for step in &spec.steps {
+            *seq += 1;
+            match self.run_step(task, &spec, step, &values, *seq)? {
+                RunStatus::Succeeded => {},
+                RunStatus::Failed(reason) if step.on_error.as_deref() == Some("continue") => {
+                    log::info!("the step {} of the run {} failed, continue: {}", step.name, self.name, reason);
+                },
+                stat => return Ok(stat)
+            }
+        }
+        for result in spec.results.iter().flatten() {
+            if let Ok(value) = fs::read_to_string(results_dir.join(&result.name)) {
+                self.values.insert(format!("tasks.{}.results.{}", task.name, result.name), value.trim_end_matches('\n').to_owned());
+            }
+        }
+        Ok(RunStatus::Succeeded)
+    }
+
+    /// Run the step, its output goes to `logs/{seq}.{task}.{step}.log`. The step is killed if the
+    /// run is cancelled meanwhile.
+    fn run_step(&self, task: &TaskDef, spec: &TaskSpec, step: &TaskStep, values: &HashMap<String, String>, seq: usize) -> error::Result<RunStatus> {
+        let container = format!("{}-{}-{}", self.name, task.name, step.name);
+        let mut command = self.command(&container, task, spec, step, values)?;
+        let log = fs::File::create(self.dir.join("logs").join(format!("{:03}.{}.{}.log", seq, task.name, step.name)))?;
+        command.current_dir(self.dir.join("workspace"))
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+        let mut child = command.spawn()?;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if status.success() {
+                    return Ok(RunStatus::Succeeded);
+                }
+                return Ok(RunStatus::Failed(format!("The step {} of the task {} exits with {}", step.name, task.name, status)));
+            }
+            if self.dir.join(CANCEL_FILE).exists() {
+                child.kill()?;
+                child.wait()?;
+                if let Some(runtime) = &self.runtime {
+                    // Killing the client of the runtime leaves the container running.
+                    let _ = Command::new(runtime).args(["rm", "-f", &container]).output();
+                }
+                return Ok(RunStatus::Cancelled);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+
+    /// The command of the step. The script is saved under `scripts` and run by `sh`, unless it
+    /// starts with a shebang. With the container runtime, the step runs in its image with the
+    /// directory of the run mounted at the same path, so the paths of the results hold.
+    fn command(&self, container: &str, task: &TaskDef, spec: &TaskSpec, step: &TaskStep, values: &HashMap<String, String>) -> error::Result<Command> {
+        let mut program: Vec<String> = Vec::new();
+        if let Some(script) = &step.script {
+            let file = self.dir.join("scripts").join(format!("{}.{}", task.name, step.name));
+            let script = substitute(script, values);
+            fs::write(&file, &script)?;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o755))?;
+            if !script.starts_with("#!") {
+                program.push("sh".to_owned());
+            }
+            program.push(file.to_string_lossy().into_owned());
+        } else if let Some(command) = &step.command {
+            program.push(substitute(command, values));
+        }
+        let args: Vec<String> = step.args.iter().flatten().map(|arg| substitute(arg, values)).collect();
+        let envs = self.envs(spec, step, values)?;
+
+        let runtime = match &self.runtime {
+            Some(runtime) => runtime,
+            None => {
+                if program.is_empty() {
+                    return Err(error::error(&format!("The step {} of the task {} has no script or command to run without a container runtime", step.name, task.name)));
+                }
+                for mount in step.volume_mounts.iter().flatten() {
+                    log::warn!("the volume {} of the step {} is not mounted without a container runtime", mount.name, step.name);
+                }
+                let mut command = Command::new(&program[0]);
+                command.args(&program[1..]).args(&args).envs(envs);
+                return Ok(command);
+            }
+        };
+        let run_dir = self.dir.to_string_lossy().into_owned();
+        let mut command = Command::new(runtime);
+        command.args(["run", "--rm", "--name", container])
+            .args(["-v", &format!("{}:{}", run_dir, run_dir)])
+            .args(["-w", &self.dir.join("workspace").to_string_lossy()]);
+        for (name, value) in envs {
+            // The values are passed by the env of the runtime, so they do not show in the process list.
+            command.args(["-e", &name]).env(name, value);
+        }
+        for volume in self.volumes(task, spec, step, values)? {
+            command.args(["-v", &volume]);
+        }
+        if let Some((entrypoint, rest)) = program.split_first() {
+            command.args(["--entrypoint", entrypoint]).arg(&step.image).args(rest);
+        } else {
+            command.arg(&step.image);
+        }
+        command.args(&args);
+        Ok(command)
+    }
+
+    /// The env of the step, those of the step template first. The values from the secrets are read
+    /// from the secrets applied.
+    fn envs(&self, spec: &TaskSpec, step: &TaskStep, values: &HashMap<String, String>) -> error::Result<Vec<(String, String)>> {
+        let template = spec.step_template.iter().flat_map(|template| template.env.iter());
+        let mut envs: Vec<(String, String)> = Vec::new();
+        for env in template.chain(step.env.iter().flatten()) {
+            let value = match &env.value {
+                EnvValue::Value(value) => substitute(value, values),
+                EnvValue::SecretKeyRef(key_ref) => {
+                    let name = substitute(&key_ref.name, values);
+                    fs::read_to_string(self.secrets.join(&name).join(&key_ref.key))
+                        .map_err(|_| error::error(&format!("The key {} of the secret {} is not found", key_ref.key, name)))?
+                }
+            };
+            envs.retain(|(name, _)| name != &env.name);
+            envs.push((env.name.clone(), value));
+        }
+        Ok(envs)
+    }
+
+    /// The volumes mounted by the step as the `-v` options of the container runtime. The config
+    /// maps are not supported.
+    fn volumes(&self, task: &TaskDef, spec: &TaskSpec, step: &TaskStep, values: &HashMap<String, String>) -> error::Result<Vec<String>> {
+        let mut volumes = Vec::new();
+        for mount in step.volume_mounts.iter().flatten() {
+            let volume = spec.volumes.iter().flatten().find(|volume| volume.name == mount.name)
+                .ok_or_else(|| error::error(&format!("The volume {} of the step {} is not defined", mount.name, step.name)))?;
+            let host = match &volume.volume_type {
+                VolumeType::Secret(secret) => format!("{}:{}:ro", self.secrets.join(substitute(&secret.secret_name, values)).display(), mount.mount_path),
+                VolumeType::HostPath(host_path) => format!("{}:{}", host_path.path, mount.mount_path),
+                VolumeType::EmptyDir(_) => {
+                    let dir = self.dir.join("volumes").join(&task.name).join(&volume.name);
+                    fs::create_dir_all(&dir)?;
+                    format!("{}:{}", dir.display(), mount.mount_path)
+                },
+                VolumeType::ConfigMap(_) => {
+                    log::warn!("the config map volume {} of the step {} is not supported by the local executor", volume.name, step.name);
+                    continue;
+                }
+            };
+            volumes.push(host);
+        }
+        Ok(volumes)
+    }
+}
+
+/// Replace the references `$(name)` in the text by their values, the unknown ones are left as they
+/// are.
+fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("$(") {
+        result.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        match reference.find(')') {
+            Some(end) => {
+                match values.get(&reference[..end]) {
+                    Some(value) => result.push_str(value),
+                    None => result.push_str(&rest[start..start + end + 3])
+                }
+                rest = &reference[end + 1..];
+            },
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bo::artifact::DeployUnit;
+
+    fn prepare_executor(name: &str, runtime: Option<&str>) -> LocalExecutor {
+        let root = std::env::temp_dir().join(format!("train-local-executor-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        LocalExecutor { root, runtime: runtime.map(String::from) }
+    }
+
+    fn apply_unit(executor: &LocalExecutor, pipeline: &str, unit: &str) {
+        let unit: DeployUnit = serde_json::from_str(unit).unwrap();
+        executor.apply(pipeline, &unit.to_manifest_yaml(pipeline).unwrap(), &Redactor::default()).unwrap();
+    }
+
+    fn wait(executor: &LocalExecutor, run_name: &str) -> RunStatus {
+        for _ in 0..100 {
+            let stat = executor.status(run_name).unwrap();
+            if stat.is_done() {
+                return stat;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+        }
+        panic!("the run {} is not done in time", run_name);
+    }
+
+    fn params(art_id: &str) -> Vec<(String, String)> {
+        vec![("art_id".to_owned(), art_id.to_owned()), ("inst_id".to_owned(), "warm-1234".to_owned())]
+    }
+
+    const TWO_TASKS: &str = r#"{
+        "params": [{"name": "art_id", "description": "The artifact"}, {"name": "inst_id", "description": "The instance"}],
+        "tasks": [
+            {"name": "verify", "runAfter": ["init"],
+             "spec": {"params": [{"name": "url", "description": "The url"}],
+                      "steps": [{"name": "echo", "image": "ubuntu", "script": "echo got $(params.url)"}]},
+             "paramValues": [{"name": "url", "value": "$(tasks.init.results.url)"}]},
+            {"name": "init",
+             "spec": {"params": [{"name": "name", "description": "The name", "default": "nobody"}],
+                      "results": [{"name": "url", "description": "The url"}],
+                      "steps": [{"name": "write", "image": "ubuntu", "script": "echo $(params.name)-$(params.art_id)\necho -n https://$(params.name).$(params.art_id) > $(results.url.path)"}]},
+             "paramValues": [{"name": "name", "value": "$(params.inst_id)"}]}
+        ],
+        "results": [{"name": "url", "value": "$(tasks.init.results.url)"}]
+    }"#;
+
+    #[test]
+    fn test_substitute() {
+        let values = HashMap::from([("params.name".to_owned(), "John".to_owned())]);
+        assert_eq!(substitute("hi $(params.name), $(params.age) $(", &values), "hi John, $(params.age) $(");
+        assert_eq!(substitute("$(params.name)$(params.name)", &values), "JohnJohn");
+    }
+
+    #[test]
+    fn test_run_after_and_results() {
+        let executor = prepare_executor("run-after", None);
+        apply_unit(&executor, "build-opsman", TWO_TASKS);
+        let run_name = executor.start("build-opsman", &params("opsman"), &Redactor::default()).unwrap();
+        assert!(run_name.starts_with("build-opsman-run-"));
+        assert_eq!(wait(&executor, &run_name), RunStatus::Succeeded);
+        let results = executor.results(&run_name, &Redactor::default()).unwrap();
+        assert_eq!(results.get("url").unwrap(), "https://warm-1234.opsman");
+        let logs = executor.logs(&run_name, &Redactor::new(["opsman"])).unwrap();
+        assert_eq!(logs, "[init : write] warm-1234-******\n[verify : echo] got https://warm-1234.******\n");
+        executor.delete(&run_name).unwrap();
+        assert!(executor.status(&run_name).is_err());
+    }
+
+    #[test]
+    fn test_failed_and_cancelled() {
+        let executor = prepare_executor("failed", None);
+        apply_unit(&executor, "build-fail", r#"{"tasks": [{"name": "init", "spec": {"steps": [
+            {"name": "ignored", "image": "ubuntu", "script": "exit 1", "onError": "continue"},
+            {"name": "fail", "image": "ubuntu", "command": "sh", "args": ["-c", "exit 3"]},
+            {"name": "skipped", "image": "ubuntu", "script": "echo skipped"}]}}]}"#);
+        let run_name = executor.start("build-fail", &[], &Redactor::default()).unwrap();
+        match wait(&executor, &run_name) {
+            RunStatus::Failed(reason) => assert!(reason.contains("fail"), "{}", reason),
+            stat => panic!("unexpected status {:?}", stat)
+        }
+        assert!(!executor.logs(&run_name, &Redactor::default()).unwrap().contains("skipped"));
+
+        apply_unit(&executor, "build-slow", r#"{"tasks": [{"name": "init", "spec": {"steps": [{"name": "sleep", "image": "ubuntu", "script": "sleep 30"}]}}]}"#);
+        let run_name = executor.start("build-slow", &[], &Redactor::default()).unwrap();
+        assert_eq!(executor.status(&run_name).unwrap(), RunStatus::Running);
+        executor.cancel(&run_name).unwrap();
+        assert_eq!(wait(&executor, &run_name), RunStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_owner_gone() {
+        let executor = prepare_executor("owner", None);
+        apply_unit(&executor, "build-slow", r#"{"tasks": [{"name": "init", "spec": {"steps": [{"name": "sleep", "image": "ubuntu", "script": "sleep 30"}]}}]}"#);
+        let run_name = executor.start("build-slow", &[], &Redactor::default()).unwrap();
+        let dir = executor.run_dir(&run_name).unwrap();
+        assert_eq!(executor.status(&run_name).unwrap(), RunStatus::Running);
+        // The pid of another namespace can not be looked up, the run keeps its status.
+        fs::write(dir.join(OWNER_FILE), format!("{} 0 other-host/pid:[1]", u32::MAX)).unwrap();
+        assert_eq!(executor.status(&run_name).unwrap(), RunStatus::Running);
+        // The pid is reused by another process after a restart.
+        let namespace = pid_namespace().unwrap();
+        fs::write(dir.join(OWNER_FILE), format!("{} 0 {}", std::process::id(), namespace)).unwrap();
+        assert_eq!(executor.status(&run_name).unwrap(), RunStatus::Failed(RESTARTED.to_owned()));
+
+        let run_name = executor.start("build-slow", &[], &Redactor::default()).unwrap();
+        let dir = executor.run_dir(&run_name).unwrap();
+        fs::write(dir.join(OWNER_FILE), format!("{} 0 {}", u32::MAX, namespace)).unwrap();
+        executor.cancel(&run_name).unwrap();
+        assert!(!dir.join(CANCEL_FILE).exists());
+        assert_eq!(read_status(&dir).unwrap(), RunStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_invalid_pipelines() {
+        let executor = prepare_executor("invalid", None);
+        let cycle: DeployUnit = serde_json::from_str(r#"{"tasks": [
+            {"name": "a", "runAfter": ["b"], "spec": {"steps": [{"name": "s", "image": "ubuntu", "script": "true"}]}},
+            {"name": "b", "runAfter": ["a"], "spec": {"steps": [{"name": "s", "image": "ubuntu", "script": "true"}]}}]}"#).unwrap();
+        let err = executor.apply("build-cycle", &cycle.to_manifest_yaml("build-cycle").unwrap(), &Redactor::default()).unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+        assert!(executor.start("build-cycle", &[], &Redactor::default()).is_err());
+
+        apply_unit(&executor, "build-params", TWO_TASKS);
+        let err = executor.start("build-params", &[], &Redactor::default()).unwrap_err();
+        assert!(err.to_string().contains("art_id"));
+    }
+
+    #[test]
+    fn test_secret_env() {
+        let executor = prepare_executor("secret", None);
+        let secret = Secret::new("acnt-opsman-warm-1234-gcp".to_owned(), "train", HashMap::from([("key", "s3cr3t-key")]));
+        executor.apply_secrets(&[secret], &Redactor::default()).unwrap();
+        apply_unit(&executor, "build-secret", r#"{
+            "params": [{"name": "art_id", "description": "The artifact"}, {"name": "inst_id", "description": "The instance"}],
+            "tasks": [{"name": "init", "spec": {"steps": [{"name": "show", "image": "ubuntu", "script": "echo $GCP_KEY $REGION",
+                "env": [{"name": "GCP_KEY", "valueFrom": {"secretKeyRef": {"name": "acnt-$(params.art_id)-$(params.inst_id)-gcp", "key": "key"}}},
+                        {"name": "REGION", "value": "us-west1"}]}]}}]}"#);
+        let run_name = executor.start("build-secret", &params("opsman"), &Redactor::default()).unwrap();
+        assert_eq!(wait(&executor, &run_name), RunStatus::Succeeded);
+        assert_eq!(executor.logs(&run_name, &Redactor::default()).unwrap(), "[init : show] s3cr3t-key us-west1\n");
+        assert_eq!(executor.logs(&run_name, &Redactor::new(["s3cr3t-key"])).unwrap(), "[init : show] ****** us-west1\n");
+
+        let run_name = executor.start("build-secret", &params("unknown"), &Redactor::default()).unwrap();
+        match wait(&executor, &run_name) {
+            RunStatus::Failed(reason) => assert!(reason.contains("acnt-unknown-warm-1234-gcp")),
+            stat => panic!("unexpected status {:?}", stat)
+        }
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        // The runtime `echo` prints the command it is given instead of running it.
+        let executor = prepare_executor("runtime", Some("echo"));
+        apply_unit(&executor, "build-image", r#"{"tasks": [{"name": "init", "spec": {"steps": [
+            {"name": "script", "image": "ubuntu", "script": "echo hi", "env": [{"name": "REGION", "value": "us-west1"}]},
+            {"name": "command", "image": "alpine", "command": "ls", "args": ["-l"]}]}}]}"#);
+        let run_name = executor.start("build-image", &[], &Redactor::default()).unwrap();
+        assert_eq!(wait(&executor, &run_name), RunStatus::Succeeded);
+        let logs = executor.logs(&run_name, &Redactor::default()).unwrap();
+        let run_dir = executor.root.join("runs").join(&run_name).to_string_lossy().into_owned();
+        assert!(logs.contains(&format!("[init : script] run --rm --name {}-init-script -v {}:{} -w {}/workspace -e REGION --entrypoint sh ubuntu {}/scripts/init.script",
+            run_name, run_dir, run_dir, run_dir, run_dir)), "{}", logs);
+        assert!(logs.contains(&format!("[init : command] run --rm --name {}-init-command -v {}:{} -w {}/workspace --entrypoint ls alpine -l",
+            run_name, run_dir, run_dir, run_dir)), "{}", logs);
+    }
+}
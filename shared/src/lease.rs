@@ -0,0 +1,122 @@
+//! The leases on the resources shared by the scheduler replicas, kept in redis. A lease is held by
+//! one replica until it is released or it expires, and each lease taken gets a fencing token
+//! higher than the previous ones on the resource. The writes guarded by the lease pass the token
+//! along, so a replica resuming after its lease expired is refused once another replica took over.
+use crate::error;
+use redis::ConnectionLike;
+
+/// The milliseconds a lease lasts unless it is renewed.
+pub const DEFAULT_LEASE_MS: u64 = 15_000;
+
+const KEY_PREFIX: &str = "train-lease";
+
+/// Take the lease if it is free, and bump the fencing token of the resource.
+const ACQUIRE_SCRIPT: &str = r"
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+  local token = redis.call('INCR', KEYS[2])
+  redis.call('SET', KEYS[1], ARGV[1] .. '/' .. token, 'PX', ARGV[2])
+  return token
+end
+return false
+";
+
+const RENEW_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+";
+
+const RELEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+#[derive(Debug)]
+pub struct Lease {
+    resource: String,
+    holder: String,
+    token: i64,
+    ttl_ms: u64
+}
+
+impl Lease {
+    /// Take the lease on the resource for the holder, or return None if another holder has it.
+    pub fn acquire(resource: &str, holder: &str, ttl_ms: u64, conn: &mut dyn ConnectionLike) -> error::Result<Option<Self>> {
+        let token: Option<i64> = redis::Script::new(ACQUIRE_SCRIPT)
+            .key(Self::key(resource))
+            .key(format!("{}:fence", Self::key(resource)))
+            .arg(holder)
+            .arg(ttl_ms)
+            .invoke(conn)?;
+        Ok(token.map(|token| Self {
+            resource: resource.to_owned(),
+            holder: holder.to_owned(),
+            token,
+            ttl_ms
+        }))
+    }
+
+    /// Extend the lease by its time to live. It returns false if the lease is lost.
+    pub fn renew(&self, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let renewed: i64 = redis::Script::new(RENEW_SCRIPT)
+            .key(Self::key(&self.resource))
+            .arg(self.value())
+            .arg(self.ttl_ms)
+            .invoke(conn)?;
+        Ok(renewed == 1)
+    }
+
+    /// Give up the lease. It returns false if the lease was lost already.
+    pub fn release(self, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let released: i64 = redis::Script::new(RELEASE_SCRIPT)
+            .key(Self::key(&self.resource))
+            .arg(self.value())
+            .invoke(conn)?;
+        Ok(released == 1)
+    }
+
+    /// The fencing token, higher than those of the leases taken on the resource before.
+    pub fn token(&self) -> i64 {
+        self.token
+    }
+
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    fn value(&self) -> String {
+        format!("{}/{}", self.holder, self.token)
+    }
+
+    fn key(resource: &str) -> String {
+        format!("{}:{}", KEY_PREFIX, resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_renew_and_release() {
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        let _: usize = redis::Cmd::del(&["train-lease:unit-test-01", "train-lease:unit-test-01:fence"]).query(&mut conn).unwrap();
+        let lease = Lease::acquire("unit-test-01", "worker-1", 60_000, &mut conn).unwrap().unwrap();
+        assert_eq!(lease.token(), 1);
+        assert!(Lease::acquire("unit-test-01", "worker-2", 60_000, &mut conn).unwrap().is_none());
+        assert!(lease.renew(&mut conn).unwrap());
+        assert!(lease.release(&mut conn).unwrap());
+
+        // The lease expired is taken over with a higher token, and the former holder loses it.
+        let stale = Lease::acquire("unit-test-01", "worker-1", 1, &mut conn).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let lease = Lease::acquire("unit-test-01", "worker-2", 60_000, &mut conn).unwrap().unwrap();
+        assert_eq!(lease.token(), 3);
+        assert!(!stale.renew(&mut conn).unwrap());
+        assert!(!stale.release(&mut conn).unwrap());
+        assert!(lease.release(&mut conn).unwrap());
+    }
+}
@@ -1,25 +1,38 @@
 pub mod error;
+pub mod crypto;
+pub mod executor;
+pub mod oidc;
+pub mod lease;
 pub mod queue;
+pub mod redact;
 pub mod scheduler;
 pub mod bo;
 mod command;
 
 #[cfg(test)]
 mod tests {
-    use crate::bo::dao::{TeamDao, ArtifactDao};
-    use crate::bo::artifact::ArtifactRequest;
+    use crate::bo::dao::{ApiTokenDao, TeamDao, ArtifactDao, InstanceDao};
+    use crate::bo::instance::{Instance, InstanceStatus};
+    use crate::bo::account::{AccountRequest, AccountUpdateRequest, UnitStatus};
+    use crate::bo::artifact::{AccountEnv, AccountRef, ArtifactRequest, ArtifactStatus, SecretRef};
+    use crate::bo::secret::SecretRequest;
+    use crate::bo::token::{Scope, TokenRequest};
+    use crate::bo::team::TeamUpdateRequest;
+    use crate::bo::token::Actor;
+    use crate::bo::user::{MemberRequest, MemberUpdateRequest, Role, UserRequest};
+    use crate::bo::{AccountOps, ArtifactOps, AuditOps, CredentialOps, QuotaOps, SecretOps, TeamOps, TokenOps, UserOps};
+    use crate::bo::tests::{sample_artifact, sample_request};
+    use crate::executor::TektonExecutor;
+    use crate::bo::quota::{QuotaRequest, QuotaUsage, RunUsage};
+    use crate::bo::audit::{AuditQuery, Resource};
+    use std::collections::HashMap;
     use diesel::pg::PgConnection;
     use crate::bo::dao::model;
     use crate::error;
     // POST   /api/v1/art with body in json as request
 
     fn run_case(conn: &mut PgConnection, case: impl FnOnce(&mut PgConnection) -> error::Result<()>)-> error::Result<()>  {
-        let team = model::Team {
-            id: None,
-            name: "Team C".to_owned(),
-            token: "234567".to_owned(),
-            desp: None
-        };
+        let team = model::Team::new("Team C".to_owned(), "234567", None);
         match TeamDao::create(conn, team) {
             Ok(team_id) => {
                 case(conn).expect("Fail to run the test case");
@@ -96,9 +109,89 @@ mod tests {
 
     }
 
+    fn secret_request(name: &str, value: &str) -> SecretRequest {
+        SecretRequest {
+            name: name.to_owned(),
+            data: HashMap::from([("token".to_owned(), value.to_owned())]),
+            desp: None,
+            backend: None,
+            path: None
+        }
+    }
+
     #[test]
     fn test_secret_creation() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let sec_id = SecretOps::create(conn, "234567", secret_request("test-lib-secret-creation", "v1"))?;
+                assert!(sec_id.is_positive());
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let sec_ref = SecretRef { name: "test-lib-secret-creation".to_owned(), version: None };
+                let value = SecretOps::resolve(conn, team_id, &sec_ref)?;
+                assert_eq!(value.version, 1);
+                assert_eq!(value.data["token"], "v1");
+
+                // Another team can neither resolve the secret nor point a secret of its own at it.
+                let other_id = TeamDao::create(conn, model::Team::new("Team D".to_owned(), "345678", None))?;
+                assert!(SecretOps::resolve(conn, other_id, &sec_ref).is_err());
+                let mut request = secret_request("test-lib-secret-alias", "v1");
+                request.path = Some("test-lib-secret-creation".to_owned());
+                assert!(SecretOps::create(conn, "345678", request).is_err());
+                for path in ["../Team C/pivnet", "/etc"] {
+                    let mut request = secret_request("test-lib-secret-file", "v1");
+                    request.data.clear();
+                    request.backend = Some("file".to_owned());
+                    request.path = Some(path.to_owned());
+                    assert!(SecretOps::create(conn, "345678", request).is_err());
+                }
+                Ok(())
+            })
+        }).unwrap();
+    }
 
+    #[test]
+    fn test_artifact_redactor() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                SecretOps::create(conn, "234567", secret_request("test-lib-redactor", "token123456"))?;
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let mut build = sample_request("test-lib-redactor").build;
+                build.secrets = Some(vec![SecretRef { name: "test-lib-redactor".to_owned(), version: None }]);
+                let art_id = ArtifactDao::create(conn, model::Artifact { build: serde_json::to_value(build)?, ..sample_artifact(team_id, "test-lib-redactor") })?;
+                let redactor = ArtifactOps::redactor(conn, art_id)?;
+                assert_eq!(redactor.redact("pivnet token: token123456"), "pivnet token: ******");
+                let records = AuditOps::query(conn, AuditQuery { action: Some("sec.resolve".to_owned()), resource: Some("secret/test-lib-redactor".to_owned()), ..Default::default() })?;
+                assert_eq!((records.len(), records[0].actor_kind.as_str()), (1, "system"));
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_credential_issue() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let mut build = sample_request("test-lib-credential").build;
+                build.generated = Some(serde_json::from_str(r#"[{"name":"admin","type":"password","length":20}]"#)?);
+                let art_id = ArtifactDao::create(conn, model::Artifact {
+                    total: 2,
+                    target: 2,
+                    build: serde_json::to_value(build)?,
+                    ..sample_artifact(team_id, "test-lib-credential")
+                })?;
+                let first = CredentialOps::issue(conn, art_id, "cold-1234")?;
+                let second = CredentialOps::issue(conn, art_id, "warm-5678")?;
+                assert_eq!(first["admin"]["password"].len(), 20);
+                assert_ne!(first["admin"]["password"], second["admin"]["password"]);
+
+                let shown = CredentialOps::show(conn, "234567", "test-lib-credential".to_owned(), "cold-1234")?;
+                assert_eq!(shown, first);
+                assert_eq!(CredentialOps::revoke(conn, art_id, "cold-1234")?, 1);
+                assert!(CredentialOps::load(conn, art_id, "cold-1234")?.is_empty());
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
@@ -108,12 +201,50 @@ mod tests {
 
     #[test]
     fn test_secret_show() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                SecretOps::create(conn, "234567", secret_request("test-lib-secret-show", "v1"))?;
+                let info = SecretOps::show(conn, "234567", "test-lib-secret-show")?;
+                assert_eq!(info.version, 1);
+                assert_eq!(info.versions.len(), 1);
+                assert_eq!(info.versions[0].keys, vec!["token".to_owned()]);
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
     fn test_secret_update() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                SecretOps::create(conn, "234567", secret_request("test-lib-secret-update", "v1"))?;
+                let team = TeamDao::find_team_by_token(conn, "234567")?;
+                let mut art_ids = Vec::new();
+                for (name, version) in [("test-lib-follow-latest", None), ("test-lib-pinned", Some(1))] {
+                    let mut build = sample_request(name).build;
+                    build.secrets = Some(vec![SecretRef { name: "test-lib-secret-update".to_owned(), version }]);
+                    art_ids.push(ArtifactDao::create(conn, model::Artifact {
+                        build: serde_json::to_value(build)?,
+                        ..sample_artifact(team.id.expect("Null team Id"), name)
+                    })?);
+                }
+
+                let affected = SecretOps::rotate(conn, "234567", "test-lib-secret-update", HashMap::from([("token".to_owned(), "v2".to_owned())]))?;
+                assert_eq!(affected, vec![art_ids[0]]);
+                let value = SecretOps::resolve(conn, team.id.expect("Null team Id"), &SecretRef { name: "test-lib-secret-update".to_owned(), version: None })?;
+                assert_eq!(value.version, 2);
+                assert_eq!(value.data["token"], "v2");
+                let value = SecretOps::resolve(conn, team.id.expect("Null team Id"), &SecretRef { name: "test-lib-secret-update".to_owned(), version: Some(1) })?;
+                assert_eq!(value.data["token"], "v1");
+
+                SecretOps::record_usage(conn, art_ids[0], "cold-1234", &[value])?;
+                let usage = SecretOps::usage(conn, "234567", "test-lib-secret-update")?;
+                assert_eq!(usage.len(), 1);
+                assert_eq!(usage[0].artifact, "test-lib-follow-latest");
+                assert_eq!(usage[0].version, 1);
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
@@ -121,29 +252,530 @@ mod tests {
 
     }
 
+    fn account_request(name: &str, units: &[&str]) -> AccountRequest {
+        AccountRequest {
+            name: name.to_owned(),
+            data: "{\"region\":\"us-west1\"}".to_owned(),
+            desp: None,
+            fields: None,
+            scrub: None,
+            units: units.iter().map(|unit| unit.to_string()).collect()
+        }
+    }
+
     #[test]
     fn test_account_creation() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let acnt_id = AccountOps::create(conn, "234567", account_request("test-lib-account-creation", &["unit-1", "unit-2"]))?;
+                assert!(acnt_id.is_positive());
+                let info = AccountOps::show(conn, "234567", "test-lib-account-creation")?;
+                assert_eq!(info.total, 2);
+                assert_eq!(info.in_stock, 2);
+                assert!(info.units.iter().all(|unit| unit.stat == UnitStatus::Available.to_string()));
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
     fn test_account_list() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-list-b", &["unit-1"]))?;
+                AccountOps::create(conn, "234567", account_request("test-lib-account-list-a", &[]))?;
+                let names: Vec<String> = AccountOps::list(conn, "234567")?.into_iter().map(|info| info.name).collect();
+                assert_eq!(names, vec!["test-lib-account-list-a", "test-lib-account-list-b"]);
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
     fn test_account_show() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-show", &["unit-1"]))?;
+                let info = AccountOps::show(conn, "234567", "test-lib-account-show")?;
+                assert_eq!(info.units.len(), 1);
+                assert!(!serde_json::to_string(&info)?.contains("unit-1"));
+                assert!(AccountOps::show(conn, "unknown-token", "test-lib-account-show").is_err());
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
     fn test_account_update() {
-
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-update", &["unit-1"]))?;
+                let info = AccountOps::show(conn, "234567", "test-lib-account-update")?;
+                AccountOps::update(conn, "234567", "test-lib-account-update", AccountUpdateRequest {
+                    desp: Some("gcp projects".to_owned()),
+                    add_units: vec!["unit-2".to_owned(), "unit-3".to_owned()],
+                    remove_units: vec![info.units[0].id],
+                    ..Default::default()
+                })?;
+                let info = AccountOps::show(conn, "234567", "test-lib-account-update")?;
+                assert_eq!(info.desp.as_deref(), Some("gcp projects"));
+                assert_eq!(info.total, 2);
+                assert_eq!(info.in_stock, 2);
+                Ok(())
+            })
+        }).unwrap();
     }
 
     #[test]
     fn test_account_destroy() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-destroy", &["unit-1"]))?;
+                AccountOps::delete(conn, "234567", "test-lib-account-destroy")?;
+                assert!(AccountOps::show(conn, "234567", "test-lib-account-destroy").is_err());
+                Ok(())
+            })
+        }).unwrap();
+    }
 
+    #[test]
+    fn test_account_check_out_and_check_in() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-gcp", &["project-1"]))?;
+                AccountOps::create(conn, "234567", account_request("test-lib-account-aws", &["account-1", "account-2"]))?;
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let art_id = ArtifactDao::create(conn, sample_artifact(team_id, "test-lib-account-check-out"))?;
+                let refs = vec![AccountRef { name: "test-lib-account-gcp".to_owned(), env: None }, AccountRef { name: "test-lib-account-aws".to_owned(), env: None }];
+
+                let units = AccountOps::check_out(conn, art_id, "cold-1234", &refs)?;
+                assert_eq!(units.len(), 2);
+                assert!(units.iter().any(|unit| unit.data == "project-1"));
+                // The gcp pool is exhausted, nothing is checked out from the aws pool either.
+                assert!(matches!(AccountOps::check_out(conn, art_id, "warm-5678", &refs), Err(error::GeneralError::PendingAccount(pool)) if pool == "test-lib-account-gcp"));
+                assert_eq!(AccountOps::show(conn, "234567", "test-lib-account-aws")?.in_stock, 1);
+                assert!(AccountOps::delete(conn, "234567", "test-lib-account-gcp").is_err());
+
+                assert!(AccountOps::check_in(conn, art_id, "cold-1234")?.is_empty());
+                assert!(AccountOps::check_in(conn, art_id, "cold-1234")?.is_empty());
+                assert_eq!(AccountOps::show(conn, "234567", "test-lib-account-gcp")?.in_stock, 1);
+                let usage = AccountOps::usage(conn, "234567", "test-lib-account-gcp")?;
+                assert_eq!(usage.len(), 1);
+                assert!(usage[0].checked_in_at.is_some());
+                Ok(())
+            })
+        }).unwrap();
     }
 
+
+    #[test]
+    fn test_artifact_pending_account() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-pending", &["project-1"]))?;
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let mut build = sample_request("test-lib-artifact-pending").build;
+                build.accounts = Some(vec![AccountRef { name: "test-lib-account-pending".to_owned(), env: None }]);
+                let art_id = ArtifactDao::create(conn, model::Artifact {
+                    total: 2,
+                    target: 2,
+                    build: serde_json::to_value(build)?,
+                    ..sample_artifact(team_id, "test-lib-artifact-pending")
+                })?;
+
+                ArtifactOps::check_out_accounts(conn, art_id, "cold-1234")?;
+                assert!(matches!(ArtifactOps::check_out_accounts(conn, art_id, "warm-5678"), Err(error::GeneralError::PendingAccount(_))));
+                let info = ArtifactOps::show(conn, "234567", "test-lib-artifact-pending".to_owned())?;
+                assert_eq!(info.stat, "PendingAccount");
+                assert_eq!(info.blocked_by.as_deref(), Some("test-lib-account-pending"));
+
+                assert_eq!(AccountOps::check_in(conn, art_id, "cold-1234")?, vec![art_id]);
+                let info = ArtifactOps::show(conn, "234567", "test-lib-artifact-pending".to_owned())?;
+                assert_eq!(info.stat, "NotScheduled");
+                assert!(info.blocked_by.is_none());
+                assert_eq!(ArtifactOps::check_out_accounts(conn, art_id, "warm-5678")?.len(), 1);
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_account_fields() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let mut req = account_request("test-lib-account-fields", &[r#"{"project_id":"p-1"}"#]);
+                req.fields = Some(serde_json::from_str(r#"[{"name":"project_id","type":"string"},{"name":"key","type":"json","sensitive":true}]"#)?);
+                // The service account without the json key is refused.
+                assert!(AccountOps::create(conn, "234567", req.clone()).is_err());
+                assert!(AccountOps::show(conn, "234567", "test-lib-account-fields").is_err());
+                req.units = vec![r#"{"project_id":"p-1","key":{"type":"service_account","private_key":"pk-123456"}}"#.to_owned()];
+                AccountOps::create(conn, "234567", req)?;
+
+                let mut artifact_request = sample_request("test-lib-account-fields");
+                let env = vec![AccountEnv { name: "GCP_KEY".to_owned(), field: "key".to_owned() }];
+                artifact_request.build.accounts = Some(vec![AccountRef { name: "test-lib-account-fields".to_owned(), env: Some(env) }]);
+                let mut invalid = artifact_request.clone();
+                invalid.build.accounts = Some(vec![AccountRef { name: "test-lib-account-fields".to_owned(), env: Some(vec![AccountEnv { name: "GCP_KEY".to_owned(), field: "json_key".to_owned() }]) }]);
+                assert!(ArtifactOps::create(conn, "234567", invalid).is_err());
+                let art_id = ArtifactOps::create(conn, "234567", artifact_request)?;
+                let build = ArtifactOps::load_by_id(conn, art_id)?.build.to_string();
+                assert!(build.contains(r#""valueFrom":{"secretKeyRef":{"key":"key","name":"acnt-$(params.art_id)-$(params.inst_id)-test-lib-account-fields"}}"#));
+
+                let units = ArtifactOps::check_out_accounts(conn, art_id, "cold-1234")?;
+                assert_eq!(units[0].keys["project_id"], "p-1");
+                assert!(units[0].keys["key"].contains("service_account"));
+                let redactor = ArtifactOps::redactor(conn, art_id)?;
+                assert_eq!(redactor.redact("project p-1 with pk-123456"), "project p-1 with ******");
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_account_quarantine() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                AccountOps::create(conn, "234567", account_request("test-lib-account-quarantine", &["project-1"]))?;
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                let mut build = sample_request("test-lib-artifact-quarantine").build;
+                build.accounts = Some(vec![AccountRef { name: "test-lib-account-quarantine".to_owned(), env: None }]);
+                let art_id = ArtifactDao::create(conn, model::Artifact {
+                    total: 2,
+                    target: 2,
+                    build: serde_json::to_value(build)?,
+                    ..sample_artifact(team_id, "test-lib-artifact-quarantine")
+                })?;
+                let unit_id = ArtifactOps::check_out_accounts(conn, art_id, "cold-1234")?[0].unit_id;
+                assert!(ArtifactOps::check_out_accounts(conn, art_id, "warm-5678").is_err());
+
+                assert_eq!(AccountOps::quarantine(conn, art_id, "cold-1234", "clean run failed")?, 1);
+                assert!(AccountOps::check_in(conn, art_id, "cold-1234")?.is_empty());
+                let info = AccountOps::show(conn, "234567", "test-lib-account-quarantine")?;
+                assert_eq!(info.in_stock, 0);
+                assert_eq!(info.units[0].stat, UnitStatus::Quarantined.to_string());
+                let quarantined = AccountOps::list_quarantined(conn)?;
+                assert_eq!(quarantined.len(), 1);
+                assert_eq!(quarantined[0].artifact.as_deref(), Some("test-lib-artifact-quarantine"));
+                assert_eq!(quarantined[0].reason.as_deref(), Some("clean run failed"));
+                assert!(quarantined[0].data.is_none());
+                assert_eq!(AccountOps::inspect(conn, unit_id)?.data.as_deref(), Some("project-1"));
+                assert!(AccountOps::scrub(conn, &TektonExecutor::default(), unit_id).unwrap_err().to_string().contains("no scrub pipeline"));
+
+                assert_eq!(AccountOps::release(conn, unit_id)?, vec![art_id]);
+                assert!(AccountOps::release(conn, unit_id).is_err());
+                assert_eq!(AccountOps::show(conn, "234567", "test-lib-account-quarantine")?.in_stock, 1);
+                assert!(AccountOps::list_quarantined(conn)?.is_empty());
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_api_token() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let borrow = TokenOps::create(conn, "234567", TokenRequest {
+                    name: "ci".to_owned(),
+                    scopes: vec![Scope::ArtBorrow],
+                    expires_at: None
+                })?;
+                assert!(TokenOps::authorize(conn, &borrow.token, Scope::ArtRead).is_ok());
+                assert!(TokenOps::authorize(conn, &borrow.token, Scope::ArtBorrow).is_ok());
+                assert!(matches!(TokenOps::authorize(conn, &borrow.token, Scope::ArtWrite), Err(error::GeneralError::Forbidden(_))));
+                assert!(matches!(TokenOps::authorize(conn, &borrow.token, Scope::SecAdmin), Err(error::GeneralError::Forbidden(_))));
+                assert!(TokenOps::authorize(conn, "234567", Scope::SecAdmin).is_ok());
+                assert_eq!(TeamDao::find_team_by_token(conn, &borrow.token)?.name, "Team C");
+                // The api tokens cannot issue the tokens.
+                assert!(matches!(TokenOps::list(conn, &borrow.token), Err(error::GeneralError::Forbidden(_))));
+
+                let tokens = TokenOps::list(conn, "234567")?;
+                assert_eq!(tokens.len(), 1);
+                assert_eq!(tokens[0].scopes, vec![Scope::ArtBorrow]);
+                assert!(tokens[0].last_used_at.is_some());
+                assert!(borrow.token.starts_with(&tokens[0].prefix));
+
+                TokenOps::revoke(conn, "234567", "ci")?;
+                assert!(matches!(TokenOps::authorize(conn, &borrow.token, Scope::ArtRead), Err(error::GeneralError::Unauthorized)));
+                assert!(TeamDao::find_team_by_token(conn, &borrow.token).is_err());
+                assert!(TokenOps::revoke(conn, "234567", "ci").is_err());
+                assert!(TokenOps::list(conn, "234567")?[0].revoked_at.is_some());
+
+                let expired = chrono::Utc::now().naive_utc() - chrono::Duration::seconds(60);
+                assert!(TokenOps::create(conn, "234567", TokenRequest { name: "old".to_owned(), scopes: vec![Scope::ArtRead], expires_at: Some(expired) }).is_err());
+                let team_id = TeamDao::find_team_by_token(conn, "234567")?.id.expect("Null team Id");
+                ApiTokenDao::create(conn, model::ApiToken::new(team_id, "old".to_owned(), "trn_expired-token", serde_json::to_value([Scope::ArtRead])?, Some(expired)))?;
+                assert!(matches!(TokenOps::authorize(conn, "trn_expired-token", Scope::ArtRead), Err(error::GeneralError::Unauthorized)));
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_team_lifecycle() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            let (team_id, token) = TeamOps::create(conn, "Team L".to_owned(), None)?;
+            TeamOps::update(conn, "Team L".to_owned(), TeamUpdateRequest { name: Some("Team M".to_owned()), desp: Some("renamed".to_owned()), tier: Some(2) })?;
+            let team = TeamDao::find_team_by_token(conn, &token)?;
+            assert_eq!(team.name, "Team M");
+            assert_eq!(team.desp.as_deref(), Some("renamed"));
+            assert_eq!(team.tier, 2);
+            assert!(TeamOps::update(conn, "Team M".to_owned(), TeamUpdateRequest { tier: Some(4), ..Default::default() }).is_err());
+
+            AccountOps::create(conn, &token, account_request("test-lib-team-lifecycle", &["project-1"]))?;
+            let ownership = TeamOps::ownership(conn, team_id)?;
+            assert_eq!((ownership.artifacts, ownership.secrets, ownership.accounts), (0, 0, 1));
+            assert!(TeamOps::delete_by_name(conn, "Team M".to_owned(), false).unwrap_err().to_string().contains("still owns"));
+            TeamOps::delete_by_name(conn, "Team M".to_owned(), true)?;
+            assert!(TeamDao::find_team_by_token(conn, &token).is_err());
+            assert!(!crate::bo::dao::AccountDao::exist_name(conn, "test-lib-team-lifecycle")?);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_team_delete_with_instance() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            let (team_id, _) = TeamOps::create(conn, "Team I".to_owned(), None)?;
+            let art_id = ArtifactDao::create(conn, sample_artifact(team_id, "test-lib-team-instance"))?;
+            let mut redis_conn = crate::queue::connection()?;
+            let instance = Instance {
+                id: "warm-team".to_owned(),
+                art_id: art_id.to_string(),
+                run_name: "build-test-lib-team-instance-run-1".to_owned(),
+                dirt: false,
+                stat: InstanceStatus::Succeeded,
+                results: None
+            };
+            InstanceDao::save(instance.clone(), &mut redis_conn)?;
+            let err = TeamOps::delete_by_name(conn, "Team I".to_owned(), true).unwrap_err();
+            assert_eq!(err.to_string(), format!("Conflict: The artifact {} still has 1 instances, set its target to 0 and wait for them to be cleaned", art_id));
+            assert!(ArtifactDao::exist_name(conn, "test-lib-team-instance")?);
+
+            InstanceDao::delete(&instance.id, &instance.art_id, &mut redis_conn)?;
+            TeamOps::delete_by_name(conn, "Team I".to_owned(), true)?;
+            assert!(!ArtifactDao::exist_name(conn, "test-lib-team-instance")?);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_user_roles() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                UserOps::create(conn, UserRequest { name: "alice".to_owned(), admin: false })?;
+                UserOps::create(conn, UserRequest { name: "root".to_owned(), admin: true })?;
+                let alice = UserOps::add_member(conn, "Team C", MemberRequest { user: "alice".to_owned(), role: Role::Viewer })?.token;
+                let root = UserOps::add_member(conn, "Team C", MemberRequest { user: "root".to_owned(), role: Role::Viewer })?.token;
+                assert!(UserOps::add_member(conn, "Team C", MemberRequest { user: "alice".to_owned(), role: Role::Admin }).is_err());
+
+                assert_eq!(TokenOps::authorize(conn, &alice, Scope::ArtRead)?, Actor::User { team: "Team C".to_owned(), name: "alice".to_owned() });
+                assert!(TokenOps::authorize(conn, &alice, Scope::SecRead).is_ok());
+                assert!(matches!(TokenOps::authorize(conn, &alice, Scope::ArtBorrow), Err(error::GeneralError::Forbidden(_))));
+                assert_eq!(TeamDao::find_team_by_token(conn, &alice)?.name, "Team C");
+
+                UserOps::update_member(conn, "Team C", "alice", MemberUpdateRequest { role: Role::Member })?;
+                assert!(TokenOps::authorize(conn, &alice, Scope::ArtBorrow).is_ok());
+                assert!(matches!(TokenOps::authorize(conn, &alice, Scope::ArtWrite), Err(error::GeneralError::Forbidden(_))));
+                UserOps::update_member(conn, "Team C", "alice", MemberUpdateRequest { role: Role::Owner })?;
+                assert!(TokenOps::authorize(conn, &alice, Scope::ArtWrite).is_ok());
+                assert!(TokenOps::authorize(conn, &alice, Scope::SecAdmin).is_ok());
+                assert!(matches!(TokenOps::authorize_admin(conn, &alice), Err(error::GeneralError::Forbidden(_))));
+
+                // The global admin can do everything whatever the role in the team.
+                assert!(TokenOps::authorize(conn, &root, Scope::SecAdmin).is_ok());
+                assert!(TokenOps::authorize_admin(conn, &root).is_ok());
+
+                let members = UserOps::list_members(conn, "Team C")?;
+                assert_eq!(members.len(), 2);
+                assert_eq!(members[0].role, Role::Owner);
+                UserOps::remove_member(conn, "Team C", "alice")?;
+                assert!(matches!(TokenOps::authorize(conn, &alice, Scope::ArtRead), Err(error::GeneralError::Unauthorized)));
+                UserOps::delete(conn, "root")?;
+                assert!(TeamDao::find_team_by_token(conn, &root).is_err());
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_admin_in_other_teams() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                TeamDao::create(conn, model::Team::new("Team D".to_owned(), "345678", None))?;
+                SecretOps::create(conn, "345678", secret_request("test-lib-admin-secret", "v1"))?;
+                let mut request = sample_request("test-lib-admin-artifact");
+                ArtifactOps::create(conn, "345678", request.clone())?;
+
+                // Neither the admin nor the owner are members of the Team D.
+                UserOps::create(conn, UserRequest { name: "alice".to_owned(), admin: false })?;
+                UserOps::create(conn, UserRequest { name: "root".to_owned(), admin: true })?;
+                let alice = UserOps::add_member(conn, "Team C", MemberRequest { user: "alice".to_owned(), role: Role::Owner })?.token;
+                let root = UserOps::add_member(conn, "Team C", MemberRequest { user: "root".to_owned(), role: Role::Viewer })?.token;
+                assert!(SecretOps::show(conn, &alice, "test-lib-admin-secret").is_err());
+                assert!(ArtifactOps::show(conn, &alice, "test-lib-admin-artifact".to_owned()).is_err());
+
+                assert!(TokenOps::authorize(conn, &root, Scope::ArtWrite).is_ok());
+                assert_eq!(SecretOps::show(conn, &root, "test-lib-admin-secret")?.version, 1);
+                SecretOps::rotate(conn, &root, "test-lib-admin-secret", HashMap::from([("token".to_owned(), "v2".to_owned())]))?;
+                assert_eq!(ArtifactOps::show(conn, &root, "test-lib-admin-artifact".to_owned())?.target, request.target);
+                request.target = 0;
+                ArtifactOps::update(conn, &root, request)?;
+                let art = ArtifactDao::load_by_name(conn, "test-lib-admin-artifact".to_owned())?;
+                assert_eq!(art.target, 0);
+                assert_eq!(art.team_id, TeamDao::find_team_by_token(conn, "345678")?.id.expect("Null team Id"));
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_jwt_auth() {
+        use crate::oidc;
+        oidc::install(oidc::Verifier::new(oidc::tests::test_config()).unwrap());
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let member = oidc::tests::sign(serde_json::json!({"sub": "bob", "iss": "https://sso.example.com", "exp": oidc::tests::exp(600), "groups": ["Team X", "Team C", "developers"]}));
+                assert_eq!(TeamDao::find_team_by_token(conn, &member)?.name, "Team C");
+                assert_eq!(TokenOps::authorize(conn, &member, Scope::ArtBorrow)?, Actor::User { team: "Team C".to_owned(), name: "bob".to_owned() });
+                assert!(matches!(TokenOps::authorize(conn, &member, Scope::ArtWrite), Err(error::GeneralError::Forbidden(_))));
+                assert!(matches!(TokenOps::authorize_admin(conn, &member), Err(error::GeneralError::Forbidden(_))));
+                AccountOps::list(conn, &member)?;
+
+                let admin = oidc::tests::sign(serde_json::json!({"sub": "root", "iss": "https://sso.example.com", "exp": oidc::tests::exp(600), "groups": ["Team C", "train-admins"]}));
+                assert!(TokenOps::authorize(conn, &admin, Scope::SecAdmin).is_ok());
+                assert!(TokenOps::authorize_admin(conn, &admin).is_ok());
+                // The leads are the owners in the Team C only, and no global admin.
+                let lead = oidc::tests::sign(serde_json::json!({"sub": "carol", "iss": "https://sso.example.com", "exp": oidc::tests::exp(600), "groups": ["Team C", "leads"]}));
+                assert!(TokenOps::authorize(conn, &lead, Scope::ArtWrite).is_ok());
+                assert!(matches!(TokenOps::authorize_admin(conn, &lead), Err(error::GeneralError::Forbidden(_))));
+
+                let stranger = oidc::tests::sign(serde_json::json!({"sub": "eve", "iss": "https://sso.example.com", "exp": oidc::tests::exp(600), "groups": ["Team X", "developers"]}));
+                assert!(matches!(TokenOps::authorize(conn, &stranger, Scope::ArtRead), Err(error::GeneralError::Forbidden(_))));
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_audit_log() {
+        use diesel::{Connection, RunQueryDsl};
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let actor = TokenOps::authorize(conn, "234567", Scope::SecAdmin)?;
+                let resource = Resource::Account("test-lib-audit".to_owned());
+                AuditOps::run(conn, &actor, "acnt.create", &resource, "req-1", |conn| {
+                    AccountOps::create(conn, "234567", account_request("test-lib-audit", &["project-1"]))
+                })?;
+                AuditOps::run(conn, &actor, "acnt.update", &resource, "req-2", |conn| {
+                    AccountOps::update(conn, "234567", "test-lib-audit", AccountUpdateRequest { desp: Some("audited".to_owned()), ..Default::default() })
+                })?;
+                // The failed operation leaves no record.
+                assert!(AuditOps::run(conn, &actor, "acnt.create", &resource, "req-3", |conn| {
+                    AccountOps::create(conn, "234567", account_request("test-lib-audit", &["project-1"]))
+                }).is_err());
+
+                let records = AuditOps::query(conn, AuditQuery { resource: Some(resource.to_string()), ..Default::default() })?;
+                assert_eq!(records.iter().map(|record| record.request_id.as_str()).collect::<Vec<_>>(), vec!["req-2", "req-1"]);
+                assert_eq!(records[1].before_digest, None);
+                assert_eq!(records[1].after_digest, records[0].before_digest);
+                assert_ne!(records[0].before_digest, records[0].after_digest);
+                assert_eq!((records[0].actor.as_str(), records[0].actor_kind.as_str(), records[0].team.as_deref()), ("Team C", "team", Some("Team C")));
+                assert_eq!(AuditOps::query(conn, AuditQuery { action: Some("acnt.update".to_owned()), limit: Some(1), ..Default::default() })?.len(), 1);
+                assert!(AuditOps::query(conn, AuditQuery { actor: Some("nobody".to_owned()), ..Default::default() })?.is_empty());
+
+                // The log is append only.
+                assert!(conn.transaction(|conn| diesel::sql_query("UPDATE audit_log SET actor = 'eve'").execute(conn)).is_err());
+                assert!(conn.transaction(|conn| diesel::sql_query("DELETE FROM audit_log").execute(conn)).is_err());
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_team_quota() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let artifact = |name: &str, total: i32| ArtifactRequest { total, target: 1, ..sample_request(name) };
+
+                assert!(QuotaOps::set(conn, "Team C", QuotaRequest { max_total: Some(-1), ..Default::default() }).is_err());
+                QuotaOps::set(conn, "Team C", QuotaRequest { max_artifacts: Some(2), max_total: Some(10), max_running: Some(3), max_borrowed: Some(2) })?;
+                let art_id = ArtifactOps::create(conn, "234567", artifact("test-lib-quota-1", 6))?;
+                let err = ArtifactOps::create(conn, "234567", artifact("test-lib-quota-2", 5)).unwrap_err();
+                assert_eq!(err.to_string(), "QuotaExceeded: maxTotal of the team Team C is 10, 6 in use and 5 requested");
+                ArtifactOps::create(conn, "234567", artifact("test-lib-quota-2", 4))?;
+                assert!(matches!(ArtifactOps::create(conn, "234567", artifact("test-lib-quota-3", 0)), Err(error::GeneralError::QuotaExceeded(_))));
+                // The artifact updated is left out of the usage.
+                ArtifactOps::update(conn, "234567", artifact("test-lib-quota-1", 5))?;
+                assert!(matches!(ArtifactOps::update(conn, "234567", artifact("test-lib-quota-1", 7)), Err(error::GeneralError::QuotaExceeded(_))));
+                assert_eq!(QuotaOps::show(conn, "Team C")?.usage, QuotaUsage { artifacts: 2, total: 9 });
+
+                assert_eq!(QuotaOps::check_run(conn, art_id, 2, &RunUsage { running: 1, borrowed: 1 })?, 2);
+                assert_eq!(QuotaOps::check_run(conn, art_id, 3, &RunUsage { running: 1, borrowed: 1 })?, 2);
+                assert_eq!(QuotaOps::check_run(conn, art_id, 1, &RunUsage { running: 0, borrowed: 2 })?, 1);
+                assert!(matches!(QuotaOps::check_run(conn, art_id, 1, &RunUsage { running: 3, borrowed: 0 }), Err(error::GeneralError::QuotaExceeded(_))));
+                assert_eq!(QuotaOps::check_run(conn, art_id, -2, &RunUsage { running: 3, borrowed: 2 })?, -2);
+
+                // The absent limits are unlimited.
+                QuotaOps::set(conn, "Team C", QuotaRequest::default())?;
+                ArtifactOps::create(conn, "234567", artifact("test-lib-quota-3", 100))?;
+                assert_eq!(QuotaOps::show(conn, "Team C")?.limits, QuotaRequest::default());
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_outbox_notify() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let request = sample_request("test-lib-outbox");
+                let art_id = ArtifactOps::create(conn, "234567", request.clone())?;
+                ArtifactOps::update(conn, "234567", request.clone())?;
+                ArtifactOps::update(conn, "234567", ArtifactRequest { target: request.target + 1, ..request.clone() })?;
+                ArtifactOps::notify(conn, "234567", request.name.clone(), "borrow")?;
+                assert!(ArtifactOps::notify(conn, "234567", "test-lib-outbox-missing".to_owned(), "borrow").is_err());
+
+                let pending: Vec<(i32, String)> = crate::bo::dao::OutboxDao::lock_oldest(conn, 100)?
+                    .into_iter()
+                    .filter(|record| record.artifact_id == art_id)
+                    .map(|record| (record.artifact_id, record.reason))
+                    .collect();
+                assert_eq!(pending, vec![
+                    (art_id, "create".to_owned()),
+                    (art_id, "update".to_owned()),
+                    (art_id, "target".to_owned()),
+                    (art_id, "borrow".to_owned())
+                ]);
+                Ok(())
+            })
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_acknowledge_maintenance() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            run_case(conn, |conn| {
+                let request = sample_request("test-lib-maintenance");
+                let art_id = ArtifactOps::create(conn, "234567", request.clone())?;
+                assert!(ArtifactOps::acknowledge(conn, "234567", request.name.clone()).is_err());
+
+                assert_eq!(ArtifactDao::add_failures(conn, art_id, 2)?, 2);
+                assert_eq!(ArtifactDao::add_failures(conn, art_id, 3)?, 5);
+                ArtifactDao::update_stat(conn, art_id, &ArtifactStatus::Maintenance.to_string(), None)?;
+                let info = ArtifactOps::show(conn, "234567", request.name.clone())?;
+                assert_eq!((info.stat.as_str(), info.failures), ("Maintenance", 5));
+
+                ArtifactOps::acknowledge(conn, "234567", request.name.clone())?;
+                let info = ArtifactOps::show(conn, "234567", request.name.clone())?;
+                assert_eq!((info.stat.as_str(), info.failures), ("NotScheduled", 0));
+
+                // The writes under a lease older than the latest one are refused.
+                assert!(ArtifactDao::fence(conn, art_id, 2)?);
+                assert!(ArtifactDao::fence(conn, art_id, 2)?);
+                assert!(!ArtifactDao::fence(conn, art_id, 1)?);
+                assert!(ArtifactDao::fence(conn, art_id, 3)?);
+                Ok(())
+            })
+        }).unwrap();
+    }
 }
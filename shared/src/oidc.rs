@@ -0,0 +1,378 @@
+//! Authenticate the users by the JWTs issued by the OIDC provider of the company, alongside the
+//! team tokens. The JWTs are verified against the JWKS of the provider, and the claims are mapped
+//! to the user, the team and the role:
+//! - `TRAIN_OIDC_JWKS`: the path or the `http(s)` URL of the JWKS. The JWTs are refused if it is
+//!   not set.
+//! - `TRAIN_OIDC_ISSUER`, `TRAIN_OIDC_AUDIENCE`: checked against `iss` and `aud` if they are set.
+//! - `TRAIN_OIDC_USER_CLAIM`, `TRAIN_OIDC_TEAM_CLAIM`, `TRAIN_OIDC_ROLE_CLAIM`: the claims of the
+//!   user, the team and the role, `sub`, `team` and `role` by default. The team and the role
+//!   claims are either a string or an array of strings, such as the groups.
+//! - `TRAIN_OIDC_ROLE_MAP`: maps the values of the role claim to the roles in the teams, such as
+//!   `developers=member,Team C:leads=owner`. The entries prefixed by a team apply in that team
+//!   only. The values not in the map grant nothing, and the map cannot grant the admin.
+//! - `TRAIN_OIDC_ADMIN_VALUES`: the values of the role claim granting the global admin, such as
+//!   `train-admins`. None of them do if it is not set.
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use crate::bo::user::Role;
+use crate::error;
+
+static VERIFIER: OnceLock<Verifier> = OnceLock::new();
+
+/// The seconds between two loads of the JWKS. The JWTs of the keys unknown meanwhile are refused,
+/// so the clients can not make the service fetch the JWKS on every request.
+const JWKS_RELOAD_COOLDOWN_SEC: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcConfig {
+    pub jwks: String,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub user_claim: String,
+    pub team_claim: String,
+    pub role_claim: String,
+    pub role_map: Vec<RoleMapping>,
+    pub admin_values: Vec<String>
+}
+
+/// An entry of the role map, the value of the role claim granting the role in the team, or in
+/// every team of the JWT if no team is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleMapping {
+    pub team: Option<String>,
+    pub value: String,
+    pub role: Role
+}
+
+impl OidcConfig {
+    pub fn new(jwks: &str) -> Self {
+        OidcConfig {
+            jwks: jwks.to_owned(),
+            issuer: None,
+            audience: None,
+            user_claim: "sub".to_owned(),
+            team_claim: "team".to_owned(),
+            role_claim: "role".to_owned(),
+            role_map: Vec::new(),
+            admin_values: Vec::new()
+        }
+    }
+
+    /// Load the config from the env, or None if `TRAIN_OIDC_JWKS` is not set.
+    pub fn from_env() -> error::Result<Option<Self>> {
+        dotenvy::dotenv().ok();
+        let jwks = match std::env::var("TRAIN_OIDC_JWKS") {
+            Ok(jwks) if !jwks.is_empty() => jwks,
+            _ => return Ok(None)
+        };
+        let mut config = Self::new(&jwks);
+        config.issuer = std::env::var("TRAIN_OIDC_ISSUER").ok();
+        config.audience = std::env::var("TRAIN_OIDC_AUDIENCE").ok();
+        if let Ok(claim) = std::env::var("TRAIN_OIDC_USER_CLAIM") {
+            config.user_claim = claim;
+        }
+        if let Ok(claim) = std::env::var("TRAIN_OIDC_TEAM_CLAIM") {
+            config.team_claim = claim;
+        }
+        if let Ok(claim) = std::env::var("TRAIN_OIDC_ROLE_CLAIM") {
+            config.role_claim = claim;
+        }
+        if let Ok(role_map) = std::env::var("TRAIN_OIDC_ROLE_MAP") {
+            config.role_map = parse_role_map(&role_map)?;
+        }
+        if let Ok(admin_values) = std::env::var("TRAIN_OIDC_ADMIN_VALUES") {
+            config.admin_values = admin_values.split(',')
+                .map(|value| value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+                .collect();
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Parse the role map such as `developers=member,Team C:leads=owner`. The admin is refused, it is
+/// granted by `TRAIN_OIDC_ADMIN_VALUES` only.
+pub fn parse_role_map(role_map: &str) -> error::Result<Vec<RoleMapping>> {
+    role_map.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (key, role) = entry.split_once('=')
+                .ok_or_else(|| error::error(&format!("The role map entry {} is not in the form [team:]value=role", entry)))?;
+            let role = role.trim().parse::<Role>()?;
+            if role == Role::Admin {
+                return Err(error::error(&format!("The role map entry {} grants the admin, use TRAIN_OIDC_ADMIN_VALUES instead", entry)));
+            }
+            let (team, value) = match key.split_once(':') {
+                Some((team, value)) => (Some(team.trim().to_owned()), value.trim().to_owned()),
+                None => (None, key.trim().to_owned())
+            };
+            Ok(RoleMapping { team, value, role })
+        })
+        .collect()
+}
+
+/// The user the JWT is issued to, with the teams, the most powerful role mapped in each of them,
+/// and whether the user is a global admin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub user: String,
+    pub teams: Vec<String>,
+    pub roles: HashMap<String, Role>,
+    pub admin: bool
+}
+
+impl Identity {
+    /// The role of the user in the team, the global admin is the admin in every team.
+    pub fn role(&self, team: &str) -> Option<Role> {
+        if self.admin {
+            return Some(Role::Admin);
+        }
+        self.roles.get(team).copied()
+    }
+}
+
+pub struct Verifier {
+    config: OidcConfig,
+    jwks: RwLock<JwkSet>,
+    loaded_at: Mutex<Instant>,
+    cooldown: Duration
+}
+
+impl Verifier {
+    /// Load the JWKS of the config.
+    pub fn new(config: OidcConfig) -> error::Result<Self> {
+        let jwks = load_jwks(&config.jwks)?;
+        Ok(Verifier {
+            config,
+            jwks: RwLock::new(jwks),
+            loaded_at: Mutex::new(Instant::now()),
+            cooldown: Duration::from_secs(JWKS_RELOAD_COOLDOWN_SEC)
+        })
+    }
+
+    pub fn from_env() -> error::Result<Option<Self>> {
+        OidcConfig::from_env()?.map(Self::new).transpose()
+    }
+
+    /// Verify the signature, the expiry, the issuer and the audience of the JWT, then map the
+    /// claims. The JWKS is reloaded if the key of the JWT is unknown, since the provider rotates
+    /// its keys, but not more than once per cooldown.
+    pub fn verify(&self, token: &str) -> error::Result<Identity> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| error::GeneralError::Unauthorized)?;
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(error::GeneralError::Unauthorized);
+        }
+        let kid = header.kid.ok_or(error::GeneralError::Unauthorized)?;
+        let key = match self.decoding_key(&kid)? {
+            Some(key) => key,
+            None if self.reload()? => self.decoding_key(&kid)?.ok_or(error::GeneralError::Unauthorized)?,
+            None => return Err(error::GeneralError::Unauthorized)
+        };
+        let mut validation = Validation::new(header.alg);
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false
+        }
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        let claims = jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|err| {
+                log::info!("The JWT is refused: {}", err);
+                error::GeneralError::Unauthorized
+            })?
+            .claims;
+        self.identity(&claims)
+    }
+
+    /// Reload the JWKS unless it is loaded within the cooldown. Return whether it is reloaded.
+    fn reload(&self) -> error::Result<bool> {
+        {
+            let mut loaded_at = self.loaded_at.lock().map_err(|_| error::error("The JWKS lock is poisoned"))?;
+            if loaded_at.elapsed() < self.cooldown {
+                return Ok(false);
+            }
+            // The failed loads count too, so an unreachable provider is not retried on every request.
+            *loaded_at = Instant::now();
+        }
+        let jwks = load_jwks(&self.config.jwks)?;
+        *self.jwks.write().map_err(|_| error::error("The JWKS lock is poisoned"))? = jwks;
+        Ok(true)
+    }
+
+    fn decoding_key(&self, kid: &str) -> error::Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().map_err(|_| error::error("The JWKS lock is poisoned"))?;
+        jwks.find(kid)
+            .map(|jwk| DecodingKey::from_jwk(jwk).map_err(|err| error::error(&format!("The key {} of the JWKS is invalid: {}", kid, err))))
+            .transpose()
+    }
+
+    fn identity(&self, claims: &serde_json::Value) -> error::Result<Identity> {
+        let user = claims.get(&self.config.user_claim)
+            .and_then(|user| user.as_str())
+            .ok_or_else(|| error::GeneralError::Forbidden(format!("The JWT has no claim {}", self.config.user_claim)))?;
+        let teams = strings(claims.get(&self.config.team_claim));
+        if teams.is_empty() {
+            return Err(error::GeneralError::Forbidden(format!("The JWT has no claim {}", self.config.team_claim)));
+        }
+        let values = strings(claims.get(&self.config.role_claim));
+        let admin = values.iter().any(|value| self.config.admin_values.contains(value));
+        let roles: HashMap<String, Role> = teams.iter()
+            .filter_map(|team| self.config.role_map.iter()
+                .filter(|mapping| mapping.team.as_ref().is_none_or(|scope| scope == team) && values.contains(&mapping.value))
+                .map(|mapping| mapping.role)
+                .min()
+                .map(|role| (team.clone(), role)))
+            .collect();
+        if !admin && roles.is_empty() {
+            return Err(error::GeneralError::Forbidden(format!("The JWT of {} maps to no role", user)));
+        }
+        Ok(Identity { user: user.to_owned(), teams, roles, admin })
+    }
+}
+
+/// The claim is a string or an array of strings.
+fn strings(claim: Option<&serde_json::Value>) -> Vec<String> {
+    match claim {
+        Some(serde_json::Value::String(s)) => vec![s.clone()],
+        Some(serde_json::Value::Array(values)) => values.iter().filter_map(|v| v.as_str().map(|s| s.to_owned())).collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Read the JWKS from the file, or fetch it from the URL.
+fn load_jwks(source: &str) -> error::Result<JwkSet> {
+    let body = if source.starts_with("http://") || source.starts_with("https://") {
+        ureq::get(source).call()
+            .map_err(|err| error::error(&format!("Failed to fetch the JWKS from {}: {}", source, err)))?
+            .into_string()?
+    } else {
+        std::fs::read_to_string(source)?
+    };
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Install the verifier of the process. It is done once at the start, the later ones are ignored.
+pub fn install(verifier: Verifier) {
+    if VERIFIER.set(verifier).is_err() {
+        log::warn!("The OIDC verifier is already installed");
+    }
+}
+
+/// The verifier of the process, or None if the OIDC is not configured.
+pub fn verifier() -> Option<&'static Verifier> {
+    VERIFIER.get()
+}
+
+/// The JWTs are three base64url segments joined by dots, while the train tokens have no dots.
+pub fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    pub(crate) fn test_config() -> OidcConfig {
+        let mut config = OidcConfig::new("../asset/test-oidc-jwks.json");
+        config.issuer = Some("https://sso.example.com".to_owned());
+        config.team_claim = "groups".to_owned();
+        config.role_claim = "groups".to_owned();
+        config.role_map = parse_role_map("developers=member, Team C:leads=owner").unwrap();
+        config.admin_values = vec!["train-admins".to_owned()];
+        config
+    }
+
+    /// Sign the claims by the local test key.
+    pub(crate) fn sign(claims: serde_json::Value) -> String {
+        sign_with_kid(claims, "train-test")
+    }
+
+    fn sign_with_kid(claims: serde_json::Value, kid: &str) -> String {
+        let pem = std::fs::read("../asset/test-oidc-key.pem").unwrap();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_owned());
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_rsa_pem(&pem).unwrap()).unwrap()
+    }
+
+    pub(crate) fn exp(sec: i64) -> i64 {
+        chrono::Utc::now().timestamp() + sec
+    }
+
+    #[test]
+    fn test_verify() {
+        let verifier = Verifier::new(test_config()).unwrap();
+        let token = sign(serde_json::json!({"sub": "alice", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "developers"]}));
+        assert!(is_jwt(&token));
+        assert!(!is_jwt("trn_0123456789abcdef"));
+        let identity = verifier.verify(&token).unwrap();
+        assert_eq!(identity.user, "alice");
+        assert_eq!(identity.role("Team C"), Some(Role::Member));
+        assert!(!identity.admin);
+        assert_eq!(identity.teams, vec!["Team C".to_owned(), "developers".to_owned()]);
+
+        let admin = sign(serde_json::json!({"sub": "root", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "developers", "train-admins"]}));
+        assert_eq!(verifier.verify(&admin).unwrap().role("Team C"), Some(Role::Admin));
+        // The values naming a role grant nothing unless they are mapped.
+        let named = sign(serde_json::json!({"sub": "eve", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "admin"]}));
+        assert!(matches!(verifier.verify(&named), Err(error::GeneralError::Forbidden(_))));
+        // The entries of a team grant the role in that team only.
+        let lead = sign(serde_json::json!({"sub": "carol", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "Team D", "leads", "developers"]}));
+        let identity = verifier.verify(&lead).unwrap();
+        assert_eq!(identity.role("Team C"), Some(Role::Owner));
+        assert_eq!(identity.role("Team D"), Some(Role::Member));
+        assert!(!identity.admin);
+
+        let expired = sign(serde_json::json!({"sub": "alice", "iss": "https://sso.example.com", "exp": exp(-600), "groups": ["Team C", "developers"]}));
+        assert!(matches!(verifier.verify(&expired), Err(error::GeneralError::Unauthorized)));
+        let other_issuer = sign(serde_json::json!({"sub": "alice", "iss": "https://evil.example.com", "exp": exp(600), "groups": ["Team C", "developers"]}));
+        assert!(matches!(verifier.verify(&other_issuer), Err(error::GeneralError::Unauthorized)));
+        let no_role = sign(serde_json::json!({"sub": "alice", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C"]}));
+        assert!(matches!(verifier.verify(&no_role), Err(error::GeneralError::Forbidden(_))));
+        let mut tampered = token.clone();
+        tampered.insert(tampered.rfind('.').unwrap() + 1, 'x');
+        assert!(matches!(verifier.verify(&tampered), Err(error::GeneralError::Unauthorized)));
+    }
+
+    #[test]
+    fn test_reload_jwks() {
+        use std::io::{Read, Write};
+        // The JWKS is served once, then the server is gone.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jwks", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buff = [0u8; 4096];
+            assert!(stream.read(&mut buff).unwrap() > 0);
+            let body = std::fs::read_to_string("../asset/test-oidc-jwks.json").unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        });
+        let mut config = test_config();
+        config.jwks = url;
+        let mut verifier = Verifier::new(config).unwrap();
+        server.join().unwrap();
+        let token = sign(serde_json::json!({"sub": "alice", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "developers"]}));
+        assert_eq!(verifier.verify(&token).unwrap().user, "alice");
+
+        // The unknown key is refused without a fetch within the cooldown.
+        let unknown = sign_with_kid(serde_json::json!({"sub": "alice", "iss": "https://sso.example.com", "exp": exp(600), "groups": ["Team C", "developers"]}), "rotated");
+        assert!(matches!(verifier.verify(&unknown), Err(error::GeneralError::Unauthorized)));
+        // After the cooldown the JWKS is fetched again, which fails as the server is gone.
+        verifier.cooldown = Duration::ZERO;
+        assert!(matches!(verifier.verify(&unknown), Err(error::GeneralError::Error(_))));
+        assert_eq!(verifier.verify(&token).unwrap().user, "alice");
+    }
+
+    #[test]
+    fn test_parse_role_map() {
+        let role_map = parse_role_map("developers=member,Team C:leads=owner").unwrap();
+        assert_eq!(role_map[0], RoleMapping { team: None, value: "developers".to_owned(), role: Role::Member });
+        assert_eq!(role_map[1], RoleMapping { team: Some("Team C".to_owned()), value: "leads".to_owned(), role: Role::Owner });
+        assert!(parse_role_map("developers").is_err());
+        assert!(parse_role_map("train-admins=admin").is_err());
+        assert!(parse_role_map("developers=guest").is_err());
+    }
+}
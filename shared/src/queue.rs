@@ -1,40 +1,466 @@
 use crate::error;
+use rand::Rng;
 use redis::ConnectionLike;
 
-pub const DEFAULT_QUEUE_NAME: &str = "train-artifact-01";
+/// The queue is a sorted set since the priorities, under a new name so the list of the earlier
+/// versions at `LEGACY_QUEUE_NAME` does not fail the commands with WRONGTYPE.
+pub const DEFAULT_QUEUE_NAME: &str = "train-artifact-02";
+/// The list the earlier versions queued the artifacts to, see `Queue::migrate`.
+pub const LEGACY_QUEUE_NAME: &str = "train-artifact-01";
+pub const DEFAULT_DELAYED_QUEUE_NAME: &str = "train-artifact-delayed-01";
+pub const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1";
+/// The seconds a reserved artifact stays invisible to the other workers before it is delivered
+/// again, unless it is acknowledged.
+pub const DEFAULT_VISIBILITY_TIMEOUT_SEC: i64 = 600;
+/// The deliveries of an artifact which are not acknowledged before it is dead-lettered.
+pub const DEFAULT_MAX_ATTEMPTS: i64 = 5;
+/// The milliseconds a heartbeat of the worker lasts, the other workers take over its artifacts
+/// once it is over, see `Queue::redeliver_orphaned`.
+pub const HEARTBEAT_TTL_MS: u64 = 6_000;
 
+/// Open the connection to the redis server at the env `REDIS_URL`.
+pub fn connection() -> error::Result<redis::Connection> {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("REDIS_URL").unwrap_or(DEFAULT_REDIS_URL.to_owned());
+    Ok(redis::Client::open(url)?.get_connection()?)
+}
+
+/// The milliseconds of waiting one level of the priority is worth. An artifact queued earlier by
+/// more than this is taken before the artifacts one level higher, so the routine work is never
+/// starved by the urgent one.
+pub const PRIORITY_STEP_MS: i64 = 60_000;
+/// The highest priority, the higher ones are capped to it.
+pub const MAX_PRIORITY: i64 = 10;
+
+/// Take the first pending artifact and reserve it for the worker in one step.
+const RESERVE_SCRIPT: &str = r"
+local first = redis.call('ZRANGE', KEYS[1], 0, 0)
+if #first == 0 then
+  return false
+end
+redis.call('ZREM', KEYS[1], first[1])
+redis.call('RPUSH', KEYS[2], first[1])
+redis.call('ZADD', KEYS[3], ARGV[1], ARGV[2] .. '/' .. first[1])
+return first[1]
+";
+
+/// Move the artifacts of the legacy list to the queue in their order, and drop the list.
+const MIGRATE_SCRIPT: &str = r"
+if redis.call('TYPE', KEYS[1]).ok ~= 'list' then
+  return 0
+end
+local items = redis.call('LRANGE', KEYS[1], 0, -1)
+for i, item in ipairs(items) do
+  redis.call('ZADD', KEYS[2], 'LT', tonumber(ARGV[1]) + i, item)
+end
+redis.call('DEL', KEYS[1])
+return #items
+";
+
+/// The queue of the artifacts to reconcile, a redis sorted set scored by the time the artifact is
+/// queued, less `PRIORITY_STEP_MS` for each level of its priority. An artifact is pending at most
+/// once, queuing it again keeps the earlier or the more urgent place.
+/// Besides the plain `dequeue`, a worker reserves an artifact with `reserve`, which moves it
+/// atomically to the processing list of the worker, and removes it with `ack` once it is done. The
+/// artifacts not acknowledged in time, or given back with `nack`, are delivered again up to
+/// `max_attempts` times, then moved to the dead-letter list until they are replayed.
 pub struct Queue {
-    name: String
+    name: String,
+    visibility_sec: i64,
+    max_attempts: i64
 }
 
 impl Queue {
     pub fn enqueue(&self, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<()> {
-        log::info!("Enqueue the artifact: {} to the queue: {}", art_id, &self.name);
-        redis::Cmd::rpush(&self.name, art_id).execute(conn);
+        self.enqueue_with_priority(art_id, 0, conn)
+    }
+
+    /// Queue the artifact with the priority from 0 to `MAX_PRIORITY`. If it is pending already, it
+    /// is moved ahead when the new place is earlier.
+    pub fn enqueue_with_priority(&self, art_id: &str, priority: i64, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        log::info!("Enqueue the artifact: {} to the queue: {} with the priority {}", art_id, &self.name, priority);
+        let score = chrono::Utc::now().timestamp_millis() - priority.clamp(0, MAX_PRIORITY) * PRIORITY_STEP_MS;
+        // Wake up one of the workers blocked in `reserve`, a single signal is enough.
+        let _: () = redis::pipe()
+            .atomic()
+            .cmd("ZADD").arg(&self.name).arg("LT").arg(score).arg(art_id).ignore()
+            .rpush(self.signal(), 1).ignore()
+            .ltrim(self.signal(), 0, 0).ignore()
+            .query(conn)?;
         Ok(())
     }
 
     pub fn dequeue(&self, conn: &mut dyn ConnectionLike) -> error::Result<Option<String>> {
-        let response: Option<String> = redis::Cmd::lpop(&self.name, None).query(conn)?;
+        let response: Vec<String> = redis::Cmd::zpopmin(&self.name, 1).query(conn)?;
+        Ok(response.into_iter().next())
+    }
+
+    /// Block until an artifact is queued, or return None after `timeout_sec` seconds. It blocks
+    /// forever if `timeout_sec` is 0.
+    pub fn block_dequeue(&self, timeout_sec: usize, conn: &mut dyn ConnectionLike) -> error::Result<Option<String>> {
+        let response: Option<(String, String, f64)> = redis::cmd("BZPOPMIN").arg(&self.name).arg(timeout_sec).query(conn)?;
+        Ok(response.map(|(_, art_id, _)| art_id))
+    }
+
+    /// The pending artifacts with their scores, the next first.
+    pub fn pending(&self, conn: &mut dyn ConnectionLike) -> error::Result<Vec<(String, f64)>> {
+        let response: Vec<(String, f64)> = redis::Cmd::zrange_withscores(&self.name, 0, -1).query(conn)?;
         Ok(response)
     }
 
-    pub fn block_dequeue(&self, timeout_sec: usize, conn: &mut dyn ConnectionLike) -> error::Result<String> {
-        let response: Option<(String,String)> = redis::Cmd::blpop(&self.name, timeout_sec).query(conn)?;
-        match response {
-            Some((_, art_id)) => Ok(art_id),
-            None => Err(error::error("Timeout"))
+    /// Block until an artifact is queued and move it to the processing list of the worker, or
+    /// return None after `timeout_sec` seconds. The artifact is delivered again if the worker does
+    /// not `ack` it within the visibility timeout. It may return None earlier if another worker
+    /// takes the artifact first. The heartbeat of the worker is refreshed before the artifact is
+    /// reserved, so it is never taken for orphaned while the worker holds it.
+    pub fn reserve(&self, worker: &str, timeout_sec: usize, conn: &mut dyn ConnectionLike) -> error::Result<Option<String>> {
+        if let Some(art_id) = self.try_reserve(worker, conn)? {
+            return Ok(Some(art_id));
+        }
+        let signal: Option<(String, String)> = redis::Cmd::blpop(self.signal(), timeout_sec).query(conn)?;
+        match signal {
+            Some(_) => self.try_reserve(worker, conn),
+            None => Ok(None)
         }
     }
 
+    fn try_reserve(&self, worker: &str, conn: &mut dyn ConnectionLike) -> error::Result<Option<String>> {
+        self.heartbeat(worker, HEARTBEAT_TTL_MS, conn)?;
+        let deadline = chrono::Utc::now().timestamp() + self.visibility_sec;
+        let response: Option<String> = redis::Script::new(RESERVE_SCRIPT)
+            .key(&self.name)
+            .key(self.processing(worker))
+            .key(self.inflight())
+            .arg(deadline)
+            .arg(worker)
+            .invoke(conn)?;
+        Ok(response)
+    }
+
+    /// The artifact reserved by the worker is done, remove it for good.
+    pub fn ack(&self, worker: &str, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        let _: () = redis::pipe()
+            .atomic()
+            .lrem(self.processing(worker), 1, art_id).ignore()
+            .zrem(self.inflight(), Self::member(worker, art_id)).ignore()
+            .hdel(self.attempts(), art_id).ignore()
+            .query(conn)?;
+        Ok(())
+    }
+
+    /// The worker fails to process the artifact, deliver it again or dead-letter it. It returns
+    /// true if the artifact is dead-lettered.
+    pub fn nack(&self, worker: &str, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let _: usize = redis::Cmd::zrem(self.inflight(), Self::member(worker, art_id)).query(conn)?;
+        self.retry(worker, art_id, conn)
+    }
+
+    /// Deliver again the artifacts reserved by any worker whose visibility timeout passed the unix
+    /// time `now`. The reconciliation of a worker still sending the heartbeats may just be long, so
+    /// its artifact is delivered again without counting an attempt, and it is left to the lease of
+    /// the artifact to keep the two deliveries apart. It returns the number of the artifacts
+    /// delivered again or dead-lettered.
+    pub fn redeliver_expired(&self, now: i64, conn: &mut dyn ConnectionLike) -> error::Result<usize> {
+        let expired: Vec<String> = redis::Cmd::zrangebyscore(self.inflight(), "-inf", now).query(conn)?;
+        let mut count = 0;
+        for member in expired {
+            // Only one of the workers running this at the same time claims the artifact.
+            let claimed: usize = redis::Cmd::zrem(self.inflight(), &member).query(conn)?;
+            if claimed == 0 {
+                continue;
+            }
+            if let Some((worker, art_id)) = member.rsplit_once('/') {
+                let alive: bool = redis::Cmd::exists(self.alive(worker)).query(conn)?;
+                if alive {
+                    log::info!("The artifact: {} reserved by the live worker: {} is not acknowledged in time, deliver it again", art_id, worker);
+                    let _: usize = redis::Cmd::lrem(self.processing(worker), 1, art_id).query(conn)?;
+                    self.enqueue(art_id, conn)?;
+                } else {
+                    log::warn!("The artifact: {} reserved by the worker: {} is not acknowledged in time", art_id, worker);
+                    self.retry(worker, art_id, conn)?;
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Tell the other workers the worker is alive for the next `ttl_ms` milliseconds, see
+    /// `redeliver_orphaned`.
+    pub fn heartbeat(&self, worker: &str, ttl_ms: u64, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        let _: () = redis::cmd("SET").arg(self.alive(worker)).arg(1).arg("PX").arg(ttl_ms).query(conn)?;
+        Ok(())
+    }
+
+    /// Deliver again the artifacts reserved by the workers whose heartbeat stopped, without waiting
+    /// for their visibility timeout. Only the workers sending the heartbeats should reserve the
+    /// artifacts of the queue this is called on. It returns the number of the artifacts delivered
+    /// again or dead-lettered.
+    pub fn redeliver_orphaned(&self, conn: &mut dyn ConnectionLike) -> error::Result<usize> {
+        let inflight: Vec<String> = redis::Cmd::zrange(self.inflight(), 0, -1).query(conn)?;
+        let mut count = 0;
+        for member in inflight {
+            let (worker, art_id) = match member.rsplit_once('/') {
+                Some(pair) => pair,
+                None => continue
+            };
+            let alive: bool = redis::Cmd::exists(self.alive(worker)).query(conn)?;
+            if alive {
+                continue;
+            }
+            let claimed: usize = redis::Cmd::zrem(self.inflight(), &member).query(conn)?;
+            if claimed > 0 {
+                log::warn!("The worker: {} holding the artifact: {} is gone", worker, art_id);
+                self.retry(worker, art_id, conn)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Give back the artifacts left in the processing list of the worker, such as after it
+    /// crashed and restarted. Each of them counts as a failed delivery.
+    pub fn recover(&self, worker: &str, conn: &mut dyn ConnectionLike) -> error::Result<usize> {
+        let left: Vec<String> = redis::Cmd::lrange(self.processing(worker), 0, -1).query(conn)?;
+        for art_id in &left {
+            self.nack(worker, art_id, conn)?;
+        }
+        Ok(left.len())
+    }
+
+    /// Move the artifacts queued by the earlier versions to the list `legacy` into the queue, and
+    /// drop the list. It is called by the scheduler loop, so the API replicas not upgraded yet are
+    /// drained as well. It returns the number of the artifacts moved.
+    pub fn migrate(&self, legacy: &str, conn: &mut dyn ConnectionLike) -> error::Result<usize> {
+        let moved: usize = redis::Script::new(MIGRATE_SCRIPT)
+            .key(legacy)
+            .key(&self.name)
+            .arg(chrono::Utc::now().timestamp_millis())
+            .invoke(conn)?;
+        if moved > 0 {
+            log::info!("Moved {} artifacts from the legacy queue: {} to the queue: {}", moved, legacy, &self.name);
+            let _: () = redis::pipe()
+                .atomic()
+                .rpush(self.signal(), 1).ignore()
+                .ltrim(self.signal(), 0, 0).ignore()
+                .query(conn)?;
+        }
+        Ok(moved)
+    }
+
+    /// The artifacts which ran out of the attempts, the oldest first.
+    pub fn dead_letters(&self, conn: &mut dyn ConnectionLike) -> error::Result<Vec<String>> {
+        let response: Vec<String> = redis::Cmd::lrange(self.dead(), 0, -1).query(conn)?;
+        Ok(response)
+    }
+
+    /// Move the dead-lettered artifact back to the queue with fresh attempts. It returns false if
+    /// the artifact is not dead-lettered.
+    pub fn replay(&self, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let removed = self.remove_dead(art_id, conn)?;
+        if removed {
+            log::info!("Replay the dead-lettered artifact: {} to the queue: {}", art_id, &self.name);
+            self.enqueue(art_id, conn)?;
+        }
+        Ok(removed)
+    }
+
+    /// Take the artifact off the dead-letter list, it returns false if it is not dead-lettered.
+    pub fn remove_dead(&self, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let removed: usize = redis::Cmd::lrem(self.dead(), 0, art_id).query(conn)?;
+        Ok(removed > 0)
+    }
+
+    /// Move all the dead-lettered artifacts back to the queue, it returns the artifacts replayed.
+    pub fn replay_all(&self, conn: &mut dyn ConnectionLike) -> error::Result<Vec<String>> {
+        let mut replayed = Vec::new();
+        loop {
+            let response: Option<String> = redis::Cmd::lpop(self.dead(), None).query(conn)?;
+            match response {
+                Some(art_id) => {
+                    self.enqueue(&art_id, conn)?;
+                    replayed.push(art_id);
+                },
+                None => return Ok(replayed)
+            }
+        }
+    }
+
+    /// Take the artifact off the processing list of the worker, and queue it again unless it ran
+    /// out of the attempts.
+    fn retry(&self, worker: &str, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let _: usize = redis::Cmd::lrem(self.processing(worker), 1, art_id).query(conn)?;
+        let attempts: i64 = redis::Cmd::hincr(self.attempts(), art_id, 1).query(conn)?;
+        if attempts >= self.max_attempts {
+            log::warn!("The artifact: {} failed {} times, move it to the dead-letter list of the queue: {}", art_id, attempts, &self.name);
+            let _: () = redis::pipe()
+                .atomic()
+                .hdel(self.attempts(), art_id).ignore()
+                .rpush(self.dead(), art_id).ignore()
+                .query(conn)?;
+            Ok(true)
+        } else {
+            self.enqueue(art_id, conn)?;
+            Ok(false)
+        }
+    }
+
+    fn processing(&self, worker: &str) -> String {
+        format!("{}:processing:{}", self.name, worker)
+    }
+
+    fn inflight(&self) -> String {
+        format!("{}:inflight", self.name)
+    }
+
+    fn attempts(&self) -> String {
+        format!("{}:attempts", self.name)
+    }
+
+    fn dead(&self) -> String {
+        format!("{}:dead", self.name)
+    }
+
+    fn signal(&self) -> String {
+        format!("{}:signal", self.name)
+    }
+
+    fn alive(&self, worker: &str) -> String {
+        format!("{}:alive:{}", self.name, worker)
+    }
+
+    fn member(worker: &str, art_id: &str) -> String {
+        format!("{}/{}", worker, art_id)
+    }
+
+    /// Remove the queue along with its processing lists, the retry counts, the dead letters and
+    /// the wake-up signal.
     pub fn reset(&self, conn: &mut dyn ConnectionLike) -> error::Result<()> {
-        redis::Cmd::del(&self.name).execute(conn);
+        let processing: Vec<String> = redis::Cmd::keys(format!("{}:processing:*", self.name)).query(conn)?;
+        let _: usize = redis::Cmd::del(&self.name).query(conn)?;
+        let _: usize = redis::Cmd::del(&[self.inflight(), self.attempts(), self.dead(), self.signal()]).query(conn)?;
+        for key in processing {
+            let _: usize = redis::Cmd::del(key).query(conn)?;
+        }
+        Ok(())
+    }
+
+    pub fn new(name: String) -> Self {
+        Self { name, visibility_sec: DEFAULT_VISIBILITY_TIMEOUT_SEC, max_attempts: DEFAULT_MAX_ATTEMPTS }
+    }
+
+    /// Set the visibility timeout and the attempts before the artifact is dead-lettered.
+    pub fn with_retry(mut self, visibility_sec: i64, max_attempts: i64) -> Self {
+        self.visibility_sec = visibility_sec;
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+/// The id of the worker taking the artifacts from the queue, from the env `TRAIN_WORKER_ID`, or
+/// the `HOSTNAME` such as the name of the pod. It should survive the restarts of the worker, so
+/// the artifacts it left behind are recovered.
+pub fn worker_id() -> String {
+    std::env::var("TRAIN_WORKER_ID")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or("scheduler".to_owned())
+}
+
+/// The seconds to wait before the retry `attempt`, counted from 0. It doubles with each attempt up
+/// to `max_sec`, and a random half of it is taken off, so the artifacts failed together are not
+/// retried all at once.
+pub fn backoff_sec(attempt: u32, base_sec: i64, max_sec: i64) -> i64 {
+    let ceiling = base_sec.saturating_mul(1_i64 << attempt.min(32)).min(max_sec);
+    let half = ceiling / 2;
+    half + rand::thread_rng().gen_range(0..=ceiling - half)
+}
+
+/// The artifacts parked until the time to retry them, saved in a sorted set scored by the unix
+/// time in seconds when they are due.
+pub struct DelayedQueue {
+    name: String
+}
+
+impl DelayedQueue {
+    /// Park the artifact for `delay_sec` seconds. An artifact parked already is re-scheduled.
+    pub fn park(&self, art_id: &str, delay_sec: i64, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        let due = chrono::Utc::now().timestamp() + delay_sec;
+        log::info!("Park the artifact: {} in the queue: {} until {}", art_id, &self.name, due);
+        let _: usize = redis::Cmd::zadd(&self.name, art_id, due).query(conn)?;
+        Ok(())
+    }
+
+    /// Park the artifact for `delay_sec` seconds unless it is parked already, whenever it is due.
+    pub fn park_if_absent(&self, art_id: &str, delay_sec: i64, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let due = chrono::Utc::now().timestamp() + delay_sec;
+        let added: usize = redis::cmd("ZADD").arg(&self.name).arg("NX").arg(due).arg(art_id).query(conn)?;
+        Ok(added > 0)
+    }
+
+    /// Park the artifact for the backoff of its next retry, see `backoff_sec`. The attempts are
+    /// counted until `clear_backoff`. It returns the seconds parked.
+    pub fn park_with_backoff(&self, art_id: &str, base_sec: i64, max_sec: i64, conn: &mut dyn ConnectionLike) -> error::Result<i64> {
+        let attempts: i64 = redis::Cmd::hincr(self.attempts(), art_id, 1).query(conn)?;
+        let delay_sec = backoff_sec((attempts - 1) as u32, base_sec, max_sec);
+        self.park(art_id, delay_sec, conn)?;
+        Ok(delay_sec)
+    }
+
+    /// Forget the retries of the artifact, the next backoff starts over from the base.
+    pub fn clear_backoff(&self, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        let _: usize = redis::Cmd::hdel(self.attempts(), art_id).query(conn)?;
+        Ok(())
+    }
+
+    fn attempts(&self) -> String {
+        format!("{}:attempts", self.name)
+    }
+
+    /// Remove the artifacts due at the unix time `now` and return them. The artifact is returned
+    /// to only one of the callers if they run concurrently.
+    pub fn take_due(&self, now: i64, conn: &mut dyn ConnectionLike) -> error::Result<Vec<String>> {
+        let candidates: Vec<String> = redis::Cmd::zrangebyscore(&self.name, "-inf", now).query(conn)?;
+        let mut result = Vec::new();
+        for art_id in candidates {
+            if self.remove(&art_id, conn)? {
+                result.push(art_id);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Move the parked artifact to the queue right away, it returns false if it is not parked.
+    pub fn resume(&self, art_id: &str, queue: &Queue, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        if self.remove(art_id, conn)? {
+            queue.enqueue(art_id, conn)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Move the artifacts due now to the queue.
+    pub fn promote_due(&self, queue: &Queue, conn: &mut dyn ConnectionLike) -> error::Result<usize> {
+        let due = self.take_due(chrono::Utc::now().timestamp(), conn)?;
+        for art_id in &due {
+            queue.enqueue(art_id, conn)?;
+        }
+        Ok(due.len())
+    }
+
+    pub fn remove(&self, art_id: &str, conn: &mut dyn ConnectionLike) -> error::Result<bool> {
+        let removed: usize = redis::Cmd::zrem(&self.name, art_id).query(conn)?;
+        Ok(removed > 0)
+    }
+
+    pub fn reset(&self, conn: &mut dyn ConnectionLike) -> error::Result<()> {
+        let _: usize = redis::Cmd::del(&[self.name.clone(), self.attempts()]).query(conn)?;
         Ok(())
     }
 
     pub fn new(name: String) -> Self {
         Self { name }
-    } 
+    }
 }
 
 #[cfg(test)]
@@ -43,18 +469,130 @@ mod tests {
 
     #[test]
     fn test_enqueue_and_dequeue() {
-        let queue = Queue { name: "unit-test-01".to_owned() };
+        let queue = Queue::new("unit-test-01".to_owned());
         let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
         queue.reset(&mut conn).unwrap();
         queue.enqueue("art-001", &mut conn).unwrap();
         queue.enqueue("art-002", &mut conn).unwrap();
-        let art_id = queue.block_dequeue(5, &mut conn).unwrap();
+        let art_id = queue.block_dequeue(5, &mut conn).unwrap().unwrap();
         assert_eq!(art_id, "art-001");
         let art_id = queue.dequeue(&mut conn).unwrap().unwrap();
         assert_eq!(art_id, "art-002");
         let art_id = queue.dequeue(&mut conn).unwrap();
         assert!(art_id.is_none());
-        let art_id = queue.block_dequeue(1, &mut conn);
-        assert!(art_id.is_err());
+        let art_id = queue.block_dequeue(1, &mut conn).unwrap();
+        assert!(art_id.is_none());
+    }
+
+    #[test]
+    fn test_park_and_resume() {
+        let queue = Queue::new("unit-test-02".to_owned());
+        let delayed = DelayedQueue { name: "unit-test-delayed-02".to_owned() };
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        queue.reset(&mut conn).unwrap();
+        delayed.reset(&mut conn).unwrap();
+        delayed.park("art-001", 0, &mut conn).unwrap();
+        delayed.park("art-002", 3600, &mut conn).unwrap();
+        assert_eq!(delayed.promote_due(&queue, &mut conn).unwrap(), 1);
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-001");
+        assert!(delayed.resume("art-002", &queue, &mut conn).unwrap());
+        assert!(!delayed.resume("art-002", &queue, &mut conn).unwrap());
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-002");
+    }
+
+    #[test]
+    fn test_reserve_ack_and_dead_letter() {
+        let queue = Queue::new("unit-test-03".to_owned()).with_retry(0, 2);
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        queue.reset(&mut conn).unwrap();
+        queue.enqueue("art-001", &mut conn).unwrap();
+        queue.enqueue("art-002", &mut conn).unwrap();
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-001");
+        queue.ack("worker-1", "art-001", &mut conn).unwrap();
+        assert_eq!(queue.redeliver_expired(chrono::Utc::now().timestamp(), &mut conn).unwrap(), 0);
+
+        // The artifact is delivered again after the visibility timeout, not counted while its
+        // worker is alive, then dead-lettered.
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-002");
+        assert_eq!(queue.redeliver_expired(chrono::Utc::now().timestamp(), &mut conn).unwrap(), 1);
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-002");
+        queue.heartbeat("worker-1", 1, &mut conn).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(queue.redeliver_expired(chrono::Utc::now().timestamp(), &mut conn).unwrap(), 1);
+        assert_eq!(queue.reserve("worker-2", 1, &mut conn).unwrap().unwrap(), "art-002");
+        assert!(queue.nack("worker-2", "art-002", &mut conn).unwrap());
+        assert!(queue.reserve("worker-2", 1, &mut conn).unwrap().is_none());
+        assert_eq!(queue.dead_letters(&mut conn).unwrap(), vec!["art-002".to_owned()]);
+
+        assert!(queue.replay("art-002", &mut conn).unwrap());
+        assert!(!queue.replay("art-002", &mut conn).unwrap());
+        assert_eq!(queue.reserve("worker-3", 1, &mut conn).unwrap().unwrap(), "art-002");
+        assert_eq!(queue.recover("worker-3", &mut conn).unwrap(), 1);
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-002");
+        assert!(queue.replay_all(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dedup_and_priority() {
+        let queue = Queue::new("unit-test-04".to_owned());
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        queue.reset(&mut conn).unwrap();
+        queue.enqueue("art-001", &mut conn).unwrap();
+        queue.enqueue("art-002", &mut conn).unwrap();
+        queue.enqueue("art-001", &mut conn).unwrap();
+        queue.enqueue_with_priority("art-003", 2, &mut conn).unwrap();
+        // Queuing again with a lower priority keeps the earlier place.
+        queue.enqueue("art-003", &mut conn).unwrap();
+        assert_eq!(queue.pending(&mut conn).unwrap().len(), 3);
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-003");
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-001");
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-002");
+        assert!(queue.dequeue(&mut conn).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let queue = Queue::new("unit-test-06".to_owned());
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        queue.reset(&mut conn).unwrap();
+        let _: usize = redis::Cmd::del("unit-test-06-legacy").query(&mut conn).unwrap();
+        let _: usize = redis::Cmd::rpush("unit-test-06-legacy", &["art-001", "art-002", "art-001"]).query(&mut conn).unwrap();
+        queue.enqueue("art-003", &mut conn).unwrap();
+        assert_eq!(queue.migrate("unit-test-06-legacy", &mut conn).unwrap(), 3);
+        assert_eq!(queue.migrate("unit-test-06-legacy", &mut conn).unwrap(), 0);
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-003");
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-001");
+        assert_eq!(queue.dequeue(&mut conn).unwrap().unwrap(), "art-002");
+        assert!(queue.dequeue(&mut conn).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_backoff_sec() {
+        for _ in 0..10 {
+            let delay = backoff_sec(0, 30, 600);
+            assert!((15..=30).contains(&delay));
+            let delay = backoff_sec(3, 30, 600);
+            assert!((120..=240).contains(&delay));
+            let delay = backoff_sec(10, 30, 600);
+            assert!((300..=600).contains(&delay));
+        }
+        assert!((1800..=3600).contains(&backoff_sec(100, 30, 3600)));
+    }
+
+    #[test]
+    fn test_redeliver_orphaned() {
+        let queue = Queue::new("unit-test-05".to_owned());
+        let mut conn = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        queue.reset(&mut conn).unwrap();
+        queue.enqueue("art-001", &mut conn).unwrap();
+        queue.enqueue("art-002", &mut conn).unwrap();
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-001");
+        assert_eq!(queue.reserve("worker-2", 1, &mut conn).unwrap().unwrap(), "art-002");
+        // The workers are alive from their reserve on.
+        assert_eq!(queue.redeliver_orphaned(&mut conn).unwrap(), 0);
+        queue.heartbeat("worker-2", 1, &mut conn).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(queue.redeliver_orphaned(&mut conn).unwrap(), 1);
+        assert_eq!(queue.reserve("worker-1", 1, &mut conn).unwrap().unwrap(), "art-002");
     }
 }
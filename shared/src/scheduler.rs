@@ -1,59 +1,454 @@
 use crate::queue;
 use crate::error;
+use crate::lease::{self, Lease};
+use crate::bo::{AccountOps, ArtifactOps, CredentialOps, QuotaOps, SecretOps, account::CheckedOutUnit, artifact::{Artifact, ArtifactStatus, Rollout}, instance::{Instance, InstanceNumbers, InstanceStatus}, quota::RunUsage, secret::SecretValue};
+use crate::bo::dao::{ArtifactDao, InstanceDao};
+use crate::bo::naming;
+use crate::bo::manifest::Secret;
+use crate::redact::Redactor;
+use diesel::pg::PgConnection;
+use redis::ConnectionLike;
+use std::collections::HashMap;
 
-pub trait Executable {
-    fn execute(&mut self, arts: &[i32]) -> error::Result<u32>;
+/// The seconds an artifact pending on an account or an artifact reference is first parked before
+/// it is retried, in case it is not resumed meanwhile. It doubles with each retry, see
+/// `queue::backoff_sec`.
+pub const PENDING_RETRY_BASE_SEC: i64 = 30;
+
+/// The seconds an artifact failed to roll out is first parked before it is retried.
+pub const FAILURE_RETRY_BASE_SEC: i64 = 60;
+
+/// The longest the artifacts are parked between two retries.
+pub const RETRY_MAX_SEC: i64 = 1800;
+
+/// The consecutive build failures that put the artifact into `Maintenance`.
+pub const MAX_BUILD_FAILURES: i32 = 5;
+
+/// The seconds an artifact reconciled by another scheduler is parked before it is reconciled
+/// again, since it may have changed meanwhile.
+pub const BUSY_RETRY_SEC: i64 = 5;
+
+/// The seconds an artifact of a team over its quota is parked before it is retried.
+pub const QUOTA_RETRY_SEC: i64 = 60;
+
+/// The engine running the pipelines of the build and clean rollouts, and the scrub of the accounts.
+/// The scheduler takes the engine from the configuration, see `executor::from_env`.
+pub trait Executable: Send + Sync {
+    /// Make the pipeline defined by the manifest available to the runs, along with its tasks.
+    fn apply(&self, pipeline: &str, manifest: &str, redactor: &Redactor) -> error::Result<()>;
+
+    /// Make the secrets available to the runs, which refer to them by their names.
+    fn apply_secrets(&self, secrets: &[Secret], redactor: &Redactor) -> error::Result<()>;
+
+    /// Start a run of the pipeline with the params, and return the name of the run.
+    fn start(&self, pipeline: &str, params: &[(String, String)], redactor: &Redactor) -> error::Result<String>;
+
+    fn status(&self, run_name: &str) -> error::Result<RunStatus>;
+
+    /// The results of the run by their names, none until the run is done.
+    fn results(&self, run_name: &str, redactor: &Redactor) -> error::Result<HashMap<String, String>>;
+
+    fn logs(&self, run_name: &str, redactor: &Redactor) -> error::Result<String>;
+
+    /// Stop the run, it ends up `Cancelled`.
+    fn cancel(&self, run_name: &str) -> error::Result<()>;
+
+    /// Remove the run along with its logs and results.
+    fn delete(&self, run_name: &str) -> error::Result<()>;
+}
+
+/// The status of a pipeline run as the executor reports it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RunStatus {
+    /// The run is accepted but not started yet.
+    Pending,
+    Running,
+    Succeeded,
+    /// The run failed, with the reason given by the executor.
+    Failed(String),
+    Cancelled
+}
+
+impl RunStatus {
+    pub fn is_done(&self) -> bool {
+        !matches!(self, Self::Pending | Self::Running)
+    }
+}
+
+/// The instance is running until its run is done, a cancelled run fails the instance.
+impl From<RunStatus> for InstanceStatus {
+    fn from(stat: RunStatus) -> Self {
+        match stat {
+            RunStatus::Pending | RunStatus::Running => InstanceStatus::Running,
+            RunStatus::Succeeded => InstanceStatus::Succeeded,
+            RunStatus::Failed(reason) => InstanceStatus::Failed(reason),
+            RunStatus::Cancelled => InstanceStatus::Failed("Cancelled".to_owned())
+        }
+    }
+}
+
+/// Sync the instances of all the artifacts, see `sync_leased`. It returns the number of the
+/// artifacts whose instances are changed. The failure of an artifact is logged and the others go
+/// on, only the failures of the redis server stop it.
+pub fn sync_all(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, queue: &queue::Queue, worker: &str) -> error::Result<usize> {
+    let mut changed = 0;
+    for art_id in ArtifactDao::list_ids(conn)? {
+        match sync_leased(conn, redis_conn, executor, queue, worker, art_id) {
+            Ok(Some(instances)) if !instances.is_empty() => changed += 1,
+            Ok(_) => {},
+            Err(err @ error::GeneralError::RedisError(_)) => return Err(err),
+            Err(err) => log::warn!("failed to sync the instances of the artifact {}: {}", art_id, err)
+        }
+    }
+    Ok(changed)
+}
+
+/// Sync the instances of the artifact under its lease, so a scheduler does not change them
+/// meanwhile, and queue the artifact to reconcile it if any of them is changed. It returns the
+/// instances changed, or None if the artifact is leased by a scheduler, it is synced next time.
+pub fn sync_leased(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, queue: &queue::Queue, worker: &str, art_id: i32) -> error::Result<Option<Vec<Instance>>> {
+    let lease = match Lease::acquire(&format!("artifact:{}", art_id), worker, lease::DEFAULT_LEASE_MS, redis_conn)? {
+        Some(lease) => lease,
+        None => return Ok(None)
+    };
+    let result = sync_instances(conn, redis_conn, executor, &lease, art_id);
+    lease.release(redis_conn)?;
+    let changed = result?;
+    if !changed.is_empty() {
+        let priority = ArtifactOps::priority(conn, redis_conn, art_id, 0)?;
+        queue.enqueue_with_priority(&art_id.to_string(), priority, redis_conn)?;
+    }
+    Ok(Some(changed))
+}
+
+/// Sync the instances of the artifact on demand, see `sync_leased`, and return the numbers of its
+/// instances by status. It returns None if the artifact is leased by a scheduler.
+pub fn poll(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, queue: &queue::Queue, worker: &str, art_id: i32) -> error::Result<Option<InstanceNumbers>> {
+    if sync_leased(conn, redis_conn, executor, queue, worker, art_id)?.is_none() {
+        return Ok(None);
+    }
+    statistic_instances(&InstanceDao::many(&art_id.to_string(), redis_conn)?).map(Some)
+}
+
+/// Refresh the statuses of the running instances of the artifact from their runs, and capture the
+/// results of the builds succeeded. The instances whose runs are not found are left as they are.
+/// It returns the instances changed.
+pub fn sync_instances(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, lease: &Lease, art_id: i32) -> error::Result<Vec<Instance>> {
+    let running: Vec<Instance> = InstanceDao::many(&art_id.to_string(), redis_conn)?.into_iter()
+        .filter(|inst| matches!(inst.stat, InstanceStatus::Running | InstanceStatus::Unknown))
+        .collect();
+    if running.is_empty() {
+        return Ok(Vec::new());
+    }
+    let redactor = ArtifactOps::redactor(conn, art_id)?;
+    let mut changed = Vec::new();
+    for inst in running {
+        let stat = match executor.status(&inst.run_name) {
+            // The reason is shown to the team, it may quote the output of the run.
+            Ok(RunStatus::Failed(reason)) => InstanceStatus::Failed(redactor.redact(reason)),
+            Ok(stat) => InstanceStatus::from(stat),
+            Err(err) => {
+                log::warn!("failed to get the status of the run {} of the instance {}: {}", inst.run_name, inst.id, err);
+                continue;
+            }
+        };
+        if stat == inst.stat {
+            continue;
+        }
+        let results = if stat == InstanceStatus::Succeeded && !inst.dirt {
+            // The status is recorded without the results rather than holding the other instances.
+            match executor.results(&inst.run_name, &redactor) {
+                Ok(results) => Some(results),
+                Err(err) => {
+                    log::warn!("failed to get the results of the run {} of the instance {}: {}", inst.run_name, inst.id, err);
+                    None
+                }
+            }
+        } else {
+            inst.results.clone()
+        };
+        if !lease.renew(redis_conn)? {
+            return Err(error::GeneralError::LeaseLost(format!("{} with the token {}", lease.resource(), lease.token())));
+        }
+        log::info!("the run {} of the instance {} of the artifact {} is {}", inst.run_name, inst.id, art_id, stat.to_string());
+        let synced = Instance { stat, results, ..inst };
+        InstanceDao::update(synced.clone(), redis_conn)?;
+        changed.push(synced);
+    }
+    Ok(changed)
 }
 
-pub struct DefaultExecutor;
-impl Executable for DefaultExecutor {
-    fn execute(&mut self, _arts: &[i32]) -> error::Result<u32> {
-        Ok(0)
+/// Check out the accounts for the instance. If a pool is exhausted, the artifact is parked in the
+/// delayed queue until a unit of the pool is checked in, or its backoff is over.
+pub fn check_out_accounts(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32, inst_id: &str) -> error::Result<Vec<CheckedOutUnit>> {
+    let result = ArtifactOps::check_out_accounts(conn, art_id, inst_id);
+    if let Err(error::GeneralError::PendingAccount(_)) = &result {
+        let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+        delayed.park_with_backoff(&art_id.to_string(), PENDING_RETRY_BASE_SEC, RETRY_MAX_SEC, redis_conn)?;
     }
+    result
 }
 
-pub fn process(_queue: &queue::Queue) -> error::Result<Vec<String>> {
-        // Block on reading the head of the list.
-/*
- * let mut conn = dao::connection(DEFAULT_REDIS_URL).expect(&format!("Failed to establish connection to redis server at: {}", DEFAULT_REDIS_URL));
-    let art_id = queue.block_dequeue(0, &mut conn)?;
+/// Check the quota of the team before the rollout of the runs of the artifact. If the team is over
+/// its quota, or the runs are capped by it, the artifact is parked in the delayed queue and the
+/// rest is retried later. It returns the number of the runs allowed.
+pub fn check_quota(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32, runs: i32, usage: &RunUsage) -> error::Result<i32> {
+    let result = QuotaOps::check_run(conn, art_id, runs, usage);
+    let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    match &result {
+        Err(error::GeneralError::QuotaExceeded(reason)) => {
+            log::info!("artifact {} is held by the quota: {}", art_id, reason);
+            delayed.park(&art_id.to_string(), QUOTA_RETRY_SEC, redis_conn)?;
+        },
+        Ok(allowed) if *allowed < runs => {
+            log::info!("artifact {} builds {} of {} runs within the quota, retry the rest later", art_id, allowed, runs);
+            delayed.park(&art_id.to_string(), QUOTA_RETRY_SEC, redis_conn)?;
+        },
+        _ => {}
+    }
+    result
+}
+
+/// Check in the accounts of the cleaned instance, and resume the artifacts pending on them.
+pub fn check_in_accounts(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32, inst_id: &str) -> error::Result<Vec<i32>> {
+    let resumed = AccountOps::check_in(conn, art_id, inst_id)?;
+    resume_artifacts(redis_conn, &resumed)?;
+    Ok(resumed)
+}
+
+/// Move the artifacts released from `PendingAccount` to the queue, whether they are parked or not.
+pub fn resume_artifacts(redis_conn: &mut dyn ConnectionLike, art_ids: &[i32]) -> error::Result<()> {
+    let queue = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
+    let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    for id in art_ids {
+        if !delayed.resume(&id.to_string(), &queue, redis_conn)? {
+            queue.enqueue(&id.to_string(), redis_conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reserve the next artifact from the queue for the worker and reconcile it under its lease, see
+/// `reconcile_leased`. It returns the ids of the instances started, or nothing if no artifact is
+/// queued within `timeout_sec` seconds. The artifact is acknowledged once it is reconciled, and
+/// given back to the queue to retry if it fails. On the failures of the redis server, it is left
+/// reserved to be delivered again after the visibility timeout.
+pub fn process(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, queue: &queue::Queue, worker: &str, timeout_sec: usize) -> error::Result<Vec<String>> {
+    let art_id = match queue.reserve(worker, timeout_sec, redis_conn)? {
+        Some(art_id) => art_id,
+        None => return Ok(Vec::new())
+    };
     log::info!("Dequeuing the artifact: {} ", art_id);
-    let mut artifact = ArtifactDao::one(&art_id, &mut conn)?;
-    let instances = InstanceDao::many(&art_id, &mut conn)?;
+    let result = art_id.parse()
+        .map_err(|err| error::error(&format!("Invalid artifact id {}: {}", art_id, err)))
+        .and_then(|id| reconcile_leased(conn, redis_conn, executor, worker, id));
+    match &result {
+        Ok(_) => queue.ack(worker, &art_id, redis_conn)?,
+        Err(error::GeneralError::RedisError(_)) => {},
+        Err(error::GeneralError::LeaseLost(_)) => {
+            park_busy(redis_conn, &art_id)?;
+            queue.ack(worker, &art_id, redis_conn)?;
+        },
+        Err(_) => {
+            queue.nack(worker, &art_id, redis_conn)?;
+        }
+    }
+    result
+}
+
+/// Take the lease on the artifact for the worker and reconcile it, so it is never reconciled by
+/// two schedulers at once. If another scheduler holds the lease, the artifact is parked shortly
+/// instead.
+pub fn reconcile_leased(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, worker: &str, art_id: i32) -> error::Result<Vec<String>> {
+    let lease = match Lease::acquire(&format!("artifact:{}", art_id), worker, lease::DEFAULT_LEASE_MS, redis_conn)? {
+        Some(lease) => lease,
+        None => {
+            log::info!("the artifact {} is reconciled by another scheduler, retry later", art_id);
+            park_busy(redis_conn, &art_id.to_string())?;
+            return Ok(Vec::new());
+        }
+    };
+    let result = reconcile(conn, redis_conn, executor, &lease, art_id);
+    if !lease.release(redis_conn)? && result.is_ok() {
+        log::warn!("the lease on the artifact {} expired before it is released", art_id);
+    }
+    result
+}
+
+/// Park the artifact reconciled by another scheduler, unless it is parked already for longer.
+fn park_busy(redis_conn: &mut dyn ConnectionLike, art_id: &str) -> error::Result<()> {
+    let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    delayed.park_if_absent(art_id, BUSY_RETRY_SEC, redis_conn)?;
+    Ok(())
+}
+
+/// Renew the lease on the artifact and record its fencing token, before each change made under
+/// the lease. It fails with `LeaseLost` if another scheduler took the artifact over.
+fn fence(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, lease: &Lease, art_id: i32) -> error::Result<()> {
+    if !lease.renew(redis_conn)? || !ArtifactDao::fence(conn, art_id, lease.token())? {
+        return Err(error::GeneralError::LeaseLost(format!("{} with the token {}", lease.resource(), lease.token())));
+    }
+    Ok(())
+}
+
+/// Bring the instances of the artifact to its target. The instances cleaned are removed first,
+/// the failed builds are cleaned, then the instances are built or cleaned, and the new instances
+/// and the status of the artifact are saved. An artifact in `Maintenance` is not built. It returns
+/// the ids of the instances started. The failures of the rollout are not returned, the artifact
+/// is parked to retry them instead.
+pub fn reconcile(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, lease: &Lease, art_id: i32) -> error::Result<Vec<String>> {
+    let model = match ArtifactOps::load_by_id(conn, art_id) {
+        Ok(model) => model,
+        Err(error::GeneralError::DBError(diesel::result::Error::NotFound)) => {
+            log::info!("the artifact {} is deleted, skip it", art_id);
+            return Ok(Vec::new());
+        },
+        Err(err) => return Err(err)
+    };
+    let mut artifact = Artifact::try_from(&model)?;
+    fence(conn, redis_conn, lease, art_id)?;
+    finish_cleaned(conn, redis_conn, art_id)?;
+    let instances = InstanceDao::many(&art_id.to_string(), redis_conn)?;
+    if instances.iter().any(Instance::is_ready) {
+        ArtifactDao::reset_failures(conn, art_id)?;
+    }
+    let failed = clean_failed_builds(conn, redis_conn, executor, lease, art_id, &mut artifact.clean, &instances)?;
+    if failed > 0 {
+        record_failures(conn, redis_conn, art_id, failed)?;
+    }
+    let maintenance = ArtifactStatus::from(ArtifactDao::load_stat(conn, art_id)?.0) == ArtifactStatus::Maintenance;
+    let instances = InstanceDao::many(&art_id.to_string(), redis_conn)?;
     let numbers = statistic_instances(&instances)?;
     let to_deploy = numbers_to_deploy(&artifact, &numbers);
     log::info!("{} environments are await to deploy ", to_deploy);
-    let rollout_result = rollout_artifact(&mut artifact, to_deploy);
-    let (rollout_ok, rollout_err) = multiplex_result(rollout_result);
-    //update_artifact(artifact, to_deploy, rollout_result)?;
-
-    if to_deploy > 0 {
-        ArtifactDao::update_build_status(&mut artifact, &rollout_err, &mut conn)?;
+    let result = if to_deploy > 0 && maintenance {
+        log::info!("the artifact {} is in maintenance, skip the builds", art_id);
+        Ok(Vec::new())
+    } else if to_deploy != 0 {
+        let usage = QuotaOps::run_usage(conn, redis_conn, model.team_id)?;
+        check_quota(conn, redis_conn, art_id, to_deploy, &usage).and_then(|runs| if runs > 0 {
+            build_instances(conn, redis_conn, executor, lease, art_id, &mut artifact.build, runs)
+        } else {
+            // Clean the ready instances over the target.
+            let ready: Vec<&Instance> = instances.iter().filter(|inst| inst.is_ready()).take(-to_deploy as usize).collect();
+            clean_instances(conn, redis_conn, executor, lease, art_id, &mut artifact.clean, &ready)
+        })
     } else {
-        ArtifactDao::update_clean_status(&mut artifact, &rollout_err, &mut conn)?;
+        Ok(Vec::new())
     };
+    update_status(conn, redis_conn, lease, art_id, maintenance, &result)?;
+    match result {
+        Ok(started) => Ok(started.into_iter().map(|inst| inst.id).collect()),
+        Err(err @ error::GeneralError::RedisError(_)) | Err(err @ error::GeneralError::LeaseLost(_)) => Err(err),
+        // The artifact is parked to retry, or in maintenance, see `update_status`.
+        Err(_) => Ok(Vec::new())
+    }
+}
 
-    let mut result = Vec::new();
-    if let Some(instances) = rollout_ok {
-        for inst in instances {
-            result.push(inst.id.clone());
-            InstanceDao::save(inst, &mut conn)?;
-        }
+/// Count the build failures of the artifact. It goes into `Maintenance` once they reach
+/// `MAX_BUILD_FAILURES`, otherwise it is parked for its backoff. It returns true if the artifact
+/// goes into `Maintenance`.
+fn record_failures(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32, count: i32) -> error::Result<bool> {
+    let failures = ArtifactDao::add_failures(conn, art_id, count)?;
+    let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    if failures >= MAX_BUILD_FAILURES {
+        log::warn!("the artifact {} failed {} times in a row, it is in maintenance until the owner acknowledges", art_id, failures);
+        ArtifactDao::update_stat(conn, art_id, &ArtifactStatus::Maintenance.to_string(), None)?;
+        delayed.remove(&art_id.to_string(), redis_conn)?;
+        delayed.clear_backoff(&art_id.to_string(), redis_conn)?;
+        Ok(true)
+    } else {
+        let delay_sec = delayed.park_with_backoff(&art_id.to_string(), FAILURE_RETRY_BASE_SEC, RETRY_MAX_SEC, redis_conn)?;
+        log::info!("the artifact {} failed {} times in a row, retry in {} seconds", art_id, failures, delay_sec);
+        Ok(false)
     }
-    if let Some(err) = rollout_err {
-        log::warn!("Failed to rollout the deploy: {} ", err);
+}
+
+/// Start the clean runs of the failed builds, which may have left resources behind. The accounts
+/// are checked in or quarantined once the clean run is done, see `finish_cleaned`. It returns the
+/// number of the failed builds.
+fn clean_failed_builds(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, lease: &Lease, art_id: i32, rollout: &mut Rollout, instances: &[Instance]) -> error::Result<i32> {
+    let failed: Vec<&Instance> = instances.iter()
+        .filter(|inst| !inst.dirt && matches!(inst.stat, InstanceStatus::Failed(_)))
+        .collect();
+    if failed.is_empty() {
+        return Ok(0);
     }
-*/
-    let result = Vec::new();
-    Ok(result)
+    let redactor = ArtifactOps::redactor(conn, art_id)?;
+    rollout.apply(executor, &redactor)?;
+    for inst in &failed {
+        log::info!("the build {} of the artifact {} failed: {}, clean it", inst.id, art_id, inst.stat.to_string());
+        fence(conn, redis_conn, lease, art_id)?;
+        let run_name = rollout.run(executor, &inst.id, &redactor)?;
+        InstanceDao::update(Instance {
+            run_name,
+            dirt: true,
+            stat: InstanceStatus::Running,
+            ..(*inst).clone()
+        }, redis_conn)?;
+    }
+    Ok(failed.len() as i32)
+}
+
+/// Remove the instances whose clean run is done. The accounts of the instance cleaned are checked
+/// in, and those of the instance failed to clean are quarantined, since they may still hold
+/// resources.
+fn finish_cleaned(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32) -> error::Result<()> {
+    for inst in InstanceDao::many(&art_id.to_string(), redis_conn)? {
+        if !inst.dirt || !inst.is_clean_run() {
+            continue;
+        }
+        match &inst.stat {
+            InstanceStatus::Succeeded => {
+                check_in_accounts(conn, redis_conn, art_id, &inst.id)?;
+            },
+            InstanceStatus::Failed(reason) => {
+                AccountOps::quarantine(conn, art_id, &inst.id, reason)?;
+            },
+            _ => continue
+        }
+        CredentialOps::revoke(conn, art_id, &inst.id)?;
+        InstanceDao::delete(&inst.id, &inst.art_id, redis_conn)?;
+        log::info!("the instance {} of the artifact {} is removed after {}", inst.id, art_id, inst.stat.to_string());
+    }
+    Ok(())
 }
 
-/*
-fn multiplex_result(r: error::Result<Vec<Instance>>) -> (Option<Vec<Instance>>, Option<error::GeneralError>) {
-    match r {
-        Ok(instances) => (Some(instances), None),
-        Err(err) => (None, Some(err))
+/// Save the status of the artifact after the rollout. The pending artifacts are left as they are
+/// marked by the check-out of the accounts, and the artifacts in `Maintenance` stay there. A
+/// failed rollout counts as a build failure, see `record_failures`.
+fn update_status(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, lease: &Lease, art_id: i32, maintenance: bool, result: &error::Result<Vec<Instance>>) -> error::Result<()> {
+    if let Err(error::GeneralError::LeaseLost(_)) = result {
+        return Ok(());
     }
+    fence(conn, redis_conn, lease, art_id)?;
+    let delayed = queue::DelayedQueue::new(queue::DEFAULT_DELAYED_QUEUE_NAME.to_owned());
+    let stat = match result {
+        Err(error::GeneralError::PendingAccount(_)) | Err(error::GeneralError::QuotaExceeded(_)) => return Ok(()),
+        Err(error::GeneralError::PendingArtRef) => {
+            delayed.park_with_backoff(&art_id.to_string(), PENDING_RETRY_BASE_SEC, RETRY_MAX_SEC, redis_conn)?;
+            ArtifactStatus::PendingArtRef
+        },
+        Err(err) => {
+            log::warn!("Failed to rollout the artifact {}: {} ", art_id, err);
+            if maintenance || record_failures(conn, redis_conn, art_id, 1)? {
+                return Ok(());
+            }
+            ArtifactStatus::Failed
+        },
+        Ok(_) if maintenance => return Ok(()),
+        Ok(_) => {
+            delayed.clear_backoff(&art_id.to_string(), redis_conn)?;
+            let instances = InstanceDao::many(&art_id.to_string(), redis_conn)?;
+            if instances.iter().any(|inst| inst.stat == InstanceStatus::Running) {
+                ArtifactStatus::Running
+            } else {
+                ArtifactStatus::Succeeded
+            }
+        }
+    };
+    ArtifactDao::update_stat(conn, art_id, &stat.to_string(), None)?;
+    Ok(())
 }
 
 //Return instance numbers that are in running, error
@@ -64,7 +459,8 @@ fn statistic_instances(instances: &[Instance]) -> error::Result<InstanceNumbers>
     let mut done_dirt = 0u32;
     for inst in instances {
         match inst.stat {
-            InstanceStatus::Running => running += 1,
+            // The instances being cleaned are neither ready nor on the way to be.
+            InstanceStatus::Running => if !inst.dirt {running += 1} else {done_dirt += 1},
             InstanceStatus::Failed(_) => fail += 1,
             InstanceStatus::Succeeded=> if !inst.dirt {done_clean += 1} else {done_dirt += 1},
             _ => {}
@@ -84,63 +480,117 @@ fn numbers_to_deploy(artifact: &Artifact, numbers: &InstanceNumbers) -> i32 {
     // The number to be deploy:
     //  buff = total - ready - in_proc - fail, need = target - ready - in_proc
     // to_deploy = min(buff, need)
-    let buff_number = artifact.total as i32 - numbers.done_dirt as i32 - numbers.done_clean as i32 - numbers.fail as i32 - numbers.running as i32;
-    let need = artifact.target as i32 - numbers.done_clean as i32 - numbers.running as i32;
+    let buff_number = artifact.total - numbers.done_dirt as i32 - numbers.done_clean as i32 - numbers.fail as i32 - numbers.running as i32;
+    let need = artifact.target - numbers.done_clean as i32 - numbers.running as i32;
     std::cmp::min(buff_number, need)
 }
 
-fn rollout_artifact(artifact: &mut Artifact, number: i32) -> error::Result<Vec<Instance>> {
-    if number > 0 {
-        artifact.build.run(number)
-    } else if number < 0 {
-        artifact.clean.run(number)
-    } else {
-        Ok(Vec::new())
+/// Build the number of instances. The instances are saved as soon as their runs start, so they are
+/// kept even if a later one fails.
+fn build_instances(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, lease: &Lease, art_id: i32, rollout: &mut Rollout, number: i32) -> error::Result<Vec<Instance>> {
+    let redactor = ArtifactOps::redactor(conn, art_id)?;
+    let secrets = ArtifactOps::apply_secrets(conn, executor, art_id)?;
+    rollout.apply(executor, &redactor)?;
+    let mut result = Vec::new();
+    for _ in 0..number {
+        fence(conn, redis_conn, lease, art_id)?;
+        let inst_id = format!("{}-{}", naming::word(None), naming::random_id());
+        let started = prepare_instance(conn, redis_conn, executor, art_id, &rollout.name, &inst_id, &secrets)
+            .and_then(|_| rollout.run(executor, &inst_id, &redactor));
+        let run_name = match started {
+            Ok(run_name) => run_name,
+            Err(err) => {
+                // Give back what the instance holds, the error is more useful than a failure here.
+                if let Err(release_err) = release_instance(conn, redis_conn, art_id, &inst_id) {
+                    log::warn!("failed to release the instance {} of the artifact {}: {}", inst_id, art_id, release_err);
+                }
+                return Err(err);
+            }
+        };
+        let inst = Instance {
+            id: inst_id,
+            art_id: art_id.to_string(),
+            run_name,
+            dirt: false,
+            stat: InstanceStatus::Running,
+            results: None
+        };
+        InstanceDao::save(inst.clone(), redis_conn)?;
+        result.push(inst);
+    }
+    Ok(result)
+}
+
+/// Check out the accounts, and apply them along with the credentials of the instance, which are
+/// mounted by the build run.
+fn prepare_instance(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, art_id: i32, art_name: &str, inst_id: &str, secrets: &[SecretValue]) -> error::Result<()> {
+    let units = check_out_accounts(conn, redis_conn, art_id, inst_id)?;
+    AccountOps::apply(executor, art_name, inst_id, &units)?;
+    let creds = CredentialOps::issue(conn, art_id, inst_id)?;
+    CredentialOps::apply(executor, art_name, inst_id, &creds)?;
+    SecretOps::record_usage(conn, art_id, inst_id, secrets)
+}
+
+fn release_instance(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, art_id: i32, inst_id: &str) -> error::Result<()> {
+    check_in_accounts(conn, redis_conn, art_id, inst_id)?;
+    CredentialOps::revoke(conn, art_id, inst_id)?;
+    Ok(())
+}
+
+/// Start the clean runs of the instances, they are saved as soon as their runs start.
+fn clean_instances(conn: &mut PgConnection, redis_conn: &mut dyn ConnectionLike, executor: &dyn Executable, lease: &Lease, art_id: i32, rollout: &mut Rollout, instances: &[&Instance]) -> error::Result<Vec<Instance>> {
+    let redactor = ArtifactOps::redactor(conn, art_id)?;
+    rollout.apply(executor, &redactor)?;
+    let mut result = Vec::new();
+    for inst in instances {
+        fence(conn, redis_conn, lease, art_id)?;
+        let run_name = rollout.run(executor, &inst.id, &redactor)?;
+        let cleaning = Instance {
+            run_name,
+            dirt: true,
+            stat: InstanceStatus::Running,
+            ..(*inst).clone()
+        };
+        InstanceDao::update(cleaning.clone(), redis_conn)?;
+        result.push(cleaning);
     }
+    Ok(result)
 }
-*/
 
 #[cfg(test)]
 mod tests {
-    /*
     use super::*;
-    use crate::artifact::{pipeline, ArtifactRequest, ArtifactStatus, dao::{self, ArtifactDao, InstanceDao}};
+    use crate::bo::{TeamOps, artifact::ArtifactRequest, pipeline};
+    use crate::bo::tests::sample_request;
+    use crate::executor::TektonExecutor;
 
     #[test]
     fn test_process() {
-        let request = r#"{"name":"opsman-process","total":1,"target":1,"refs":[{"name":"mock"}],"build":{"tasks":[{"name":"opsman-task1","spec":{"steps":[{"name":"step-collectdata","image":"ubuntu","script":"echo $(params.name)\necho with inputs: art_id: $(params.art_id)\tinst_id: $(params.inst_id) Done\nls -l /var\necho secret aws-route53\nls -l /var/aws-route53\ncat /var/aws-route53/user_id\ncat var/aws-route53/secret\necho secret pivnet\nls -l /var/pivnet\necho account gcp-environment\nls -l /var/gcp-environment","volumeMounts":[{"name":"aws-route53","mountPath":"/var/aws-route53"},{"name":"pivnet","mountPath":"/var/pivnet"},{"name":"gcp-environment","mountPath":"/var/gcp-environment"}]}],"params":[{"name":"name","type":"string","description":"The username"},{"name":"art_id","type":"string","description":"The artifact ID"},{"name":"inst_id","type":"string","description":"The instance ID"}],"volumes":[{"name":"aws-route53","secret":{"secretName":"sec-$(params.art_id)-aws-route53"}},{"name":"pivnet","secret":{"secretName":"sec-$(params.art_id)-pivnet"}},{"name":"gcp-environment","secret":{"secretName":"acnt-$(params.art_id)-$(params.inst_id)-gcp-environment"}}]},"paramValues":[{"name":"name","value":"John"},{"name":"art_id","value":"$(params.art_id)"},{"name":"inst_id","value":"$(params.inst_id)"}]}],"params":[{"name":"art_id","type":"string","description":"The artifact ID"},{"name":"inst_id","type":"string","description":"The instance ID"}],"secrets":[{"name":"aws-route53"},{"name":"pivnet"}],"accounts":[{"name":"gcp-environment"}]},"clean":{"tasks":[{"name":"task1","spec":{"steps":[{"name":"collect-data","image":"ubuntu","script":"echo $(params.name)\necho with art_id:"}],"params":[{"name":"name","type":"string","description":"The username"}]},"paramValues":[{"name":"name","value":"John"}]}],"params":[{"name":"art_id","type":"string","description":"The artifact ID"},{"name":"inst_id","type":"string","description":"The instance ID"}],"secrets":[{"name":"aws-route53"},{"name":"pivnet"}],"accounts":[{"name":"gcp-environment"}]}}"#;
-        let mut artifact_request: ArtifactRequest = serde_json::from_str(request).expect("failed to deserialize the rollout request");
-        artifact_request.validate().expect("Failed to validate the request");
-        artifact_request.format().expect("Failed to format the artifact request");
-        let artifact = Artifact::try_from(artifact_request).expect("failed to deserialize the request to artifact");
-        let mut conn = dao::connection(DEFAULT_REDIS_URL).expect(&format!("Failed to establish connection to redis server at: {}", DEFAULT_REDIS_URL));
-        let instances = InstanceDao::many(&artifact.id, &mut conn).expect(&format!("Failed to query instances of artifact {}", artifact.id));
-        for inst in instances {
-            let _result = pipeline::delete_run(&inst.run_name, "train");
-            InstanceDao::delete(&inst.id, &artifact.id, &mut conn).expect(&format!("Failed to delete instance {}", inst.id));
-        }
-        ArtifactDao::delete("opsman-process", &mut conn).expect("Failed to delete artifact:opsman-lib from DB");
-        let art_id = artifact.id.clone();
-        ArtifactDao::save(artifact, &mut conn).expect("Failed to save artifact");
-        let queue = queue::Queue::new(queue::DEFAULT_QUEUE_NAME.to_owned());
-        let mut conn = dao::connection(DEFAULT_REDIS_URL).expect(&format!("Failed to establish connection to redis server at: {}", DEFAULT_REDIS_URL));
-        queue.reset(&mut conn).unwrap();
-        queue.enqueue(&art_id, &mut conn).unwrap();
-
-        let result = process(&queue);
-        if let Err(err) = result {
-            panic!("Failed to process the artifact, with error: {}", err);
-        }
-        let artifact = ArtifactDao::one(&art_id, &mut conn).unwrap();
-        assert_eq!(artifact.build.stats, ArtifactStatus::Running);
-        assert!(chrono::Local::now() - artifact.build.last_sched < chrono::Duration::seconds(5));
-        let instances = result.unwrap();
-        assert!(instances.len() > 0);
-
-        println!("############## instance art name: {} id: {}", art_id, instances[0]);
-        let instance = InstanceDao::one(&instances[0], &art_id, &mut conn).unwrap();
-        assert_eq!(instance.stat, InstanceStatus::Running);
+        crate::bo::tests::Environment::init(true, |conn| {
+            let (_, token) = TeamOps::create(conn, "Team P".to_owned(), None)?;
+            let request = ArtifactRequest { total: 1, target: 1, ..sample_request("opsman-process") };
+            let art_id = ArtifactOps::create(conn, &token, request)?;
 
+            let mut redis_conn = queue::connection()?;
+            let queue = queue::Queue::new("unit-test-process".to_owned());
+            queue.reset(&mut redis_conn)?;
+            queue.enqueue(&art_id.to_string(), &mut redis_conn)?;
+            let started = process(conn, &mut redis_conn, &TektonExecutor::default(), &queue, "unit-test-worker", 5)?;
+            assert_eq!(started.len(), 1);
+            let instance = InstanceDao::one(&started[0], &art_id.to_string(), &mut redis_conn)?;
+            assert_eq!(instance.stat, InstanceStatus::Running);
+            assert_eq!(ArtifactDao::load_stat(conn, art_id)?.0, ArtifactStatus::Running.to_string());
+            // Nothing more to build while the instance is running.
+            assert!(reconcile_leased(conn, &mut redis_conn, &TektonExecutor::default(), "unit-test-worker", art_id)?.is_empty());
+            // The artifact leased by another scheduler is left to it.
+            let lease = Lease::acquire(&format!("artifact:{}", art_id), "unit-test-other", 60_000, &mut redis_conn)?.unwrap();
+            assert!(reconcile_leased(conn, &mut redis_conn, &TektonExecutor::default(), "unit-test-worker", art_id)?.is_empty());
+            lease.release(&mut redis_conn)?;
+
+            let _result = pipeline::delete_run(&instance.run_name, "train");
+            InstanceDao::delete(&instance.id, &instance.art_id, &mut redis_conn)?;
+            Ok(())
+        }).unwrap();
     }
 
     #[test]
@@ -222,12 +672,141 @@ mod tests {
             instance(true, InstanceStatus::Succeeded),
             instance(true, InstanceStatus::Succeeded),
             instance(true, InstanceStatus::Succeeded),
+            instance(true, InstanceStatus::Running),
         ];
         let stats = statistic_instances(&instances).unwrap();
         assert_eq!(stats.running, 1);
         assert_eq!(stats.fail, 2);
         assert_eq!(stats.done_clean, 3);
-        assert_eq!(stats.done_dirt, 4);
+        assert_eq!(stats.done_dirt, 5);
+    }
+
+    #[test]
+    fn test_run_status_to_instance_status() {
+        assert_eq!(InstanceStatus::from(RunStatus::Pending), InstanceStatus::Running);
+        assert_eq!(InstanceStatus::from(RunStatus::Running), InstanceStatus::Running);
+        assert_eq!(InstanceStatus::from(RunStatus::Succeeded), InstanceStatus::Succeeded);
+        assert_eq!(InstanceStatus::from(RunStatus::Failed("Tasks Completed: 1 (Failed: 1)".to_owned())), InstanceStatus::Failed("Tasks Completed: 1 (Failed: 1)".to_owned()));
+        assert_eq!(InstanceStatus::from(RunStatus::Cancelled), InstanceStatus::Failed("Cancelled".to_owned()));
+        // The failed status survives the round trip through redis.
+        let failed = InstanceStatus::Failed("Cancelled".to_owned());
+        assert_eq!(InstanceStatus::from(failed.to_string()), failed);
+    }
+
+    /// The executor reporting the statuses and the results set by the test, it fails to read the
+    /// results of the runs in `broken`.
+    #[derive(Default)]
+    struct StubExecutor {
+        runs: HashMap<String, (RunStatus, HashMap<String, String>)>,
+        broken: Vec<String>
+    }
+
+    impl Executable for StubExecutor {
+        fn apply(&self, _pipeline: &str, _manifest: &str, _redactor: &Redactor) -> error::Result<()> {
+            Ok(())
+        }
+
+        fn apply_secrets(&self, _secrets: &[Secret], _redactor: &Redactor) -> error::Result<()> {
+            Ok(())
+        }
+
+        fn start(&self, pipeline: &str, _params: &[(String, String)], _redactor: &Redactor) -> error::Result<String> {
+            Ok(format!("{}-run-stub", pipeline))
+        }
+
+        fn status(&self, run_name: &str) -> error::Result<RunStatus> {
+            self.runs.get(run_name).map(|(stat, _)| stat.clone()).ok_or_else(|| error::error("run not found"))
+        }
+
+        fn results(&self, run_name: &str, redactor: &Redactor) -> error::Result<HashMap<String, String>> {
+            if self.broken.iter().any(|name| name == run_name) {
+                return Err(error::error("results not readable"));
+            }
+            self.runs.get(run_name).map(|(_, results)| redactor.redact_map(results)).ok_or_else(|| error::error("run not found"))
+        }
+
+        fn logs(&self, _run_name: &str, _redactor: &Redactor) -> error::Result<String> {
+            Ok(String::new())
+        }
+
+        fn cancel(&self, _run_name: &str) -> error::Result<()> {
+            Ok(())
+        }
+
+        fn delete(&self, _run_name: &str) -> error::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sync_instances() {
+        crate::bo::tests::Environment::init(true, |conn| {
+            let (_, token) = TeamOps::create(conn, "Team S".to_owned(), None)?;
+            let art_id = ArtifactOps::create(conn, &token, sample_request("opsman-sync"))?;
+
+            let mut redis_conn = queue::connection()?;
+            let queue = queue::Queue::new("unit-test-sync".to_owned());
+            queue.reset(&mut redis_conn)?;
+            let building = Instance {
+                id: "warm-sync".to_owned(),
+                art_id: art_id.to_string(),
+                run_name: "build-opsman-sync-run-1".to_owned(),
+                dirt: false,
+                stat: InstanceStatus::Running,
+                results: None
+            };
+            let cleaning = Instance {
+                id: "cold-sync".to_owned(),
+                run_name: "clean-opsman-sync-run-2".to_owned(),
+                dirt: true,
+                ..building.clone()
+            };
+            let broken = Instance {
+                id: "warm-sync-broken".to_owned(),
+                run_name: "build-opsman-sync-run-3".to_owned(),
+                ..building.clone()
+            };
+            InstanceDao::save(building.clone(), &mut redis_conn)?;
+            InstanceDao::save(cleaning.clone(), &mut redis_conn)?;
+            InstanceDao::save(broken.clone(), &mut redis_conn)?;
+
+            let mut executor = StubExecutor::default();
+            executor.runs.insert(building.run_name.clone(), (RunStatus::Running, HashMap::new()));
+            executor.runs.insert(cleaning.run_name.clone(), (RunStatus::Running, HashMap::new()));
+            executor.runs.insert(broken.run_name.clone(), (RunStatus::Running, HashMap::new()));
+            executor.broken.push(broken.run_name.clone());
+            assert_eq!(sync_leased(conn, &mut redis_conn, &executor, &queue, "unit-test-sync", art_id)?, Some(Vec::new()));
+            assert!(queue.pending(&mut redis_conn)?.is_empty());
+
+            let results = HashMap::from([("url".to_owned(), "https://warm-sync.example.com".to_owned())]);
+            executor.runs.insert(building.run_name.clone(), (RunStatus::Succeeded, results.clone()));
+            executor.runs.insert(cleaning.run_name.clone(), (RunStatus::Cancelled, HashMap::new()));
+            executor.runs.insert(broken.run_name.clone(), (RunStatus::Succeeded, HashMap::new()));
+            let changed = sync_leased(conn, &mut redis_conn, &executor, &queue, "unit-test-sync", art_id)?.unwrap();
+            assert_eq!(changed.len(), 3);
+            let built = InstanceDao::one(&building.id, &building.art_id, &mut redis_conn)?;
+            assert_eq!(built.stat, InstanceStatus::Succeeded);
+            assert_eq!(built.results, Some(results));
+            // The results failed to read are left unset.
+            let built = InstanceDao::one(&broken.id, &broken.art_id, &mut redis_conn)?;
+            assert_eq!(built.stat, InstanceStatus::Succeeded);
+            assert_eq!(built.results, None);
+            let cleaned = InstanceDao::one(&cleaning.id, &cleaning.art_id, &mut redis_conn)?;
+            assert_eq!(cleaned.stat, InstanceStatus::Failed("Cancelled".to_owned()));
+            assert_eq!(queue.pending(&mut redis_conn)?.len(), 1);
+            let numbers = poll(conn, &mut redis_conn, &executor, &queue, "unit-test-sync", art_id)?;
+            assert_eq!(numbers, Some(InstanceNumbers { running: 0, fail: 1, done_clean: 2, done_dirt: 0 }));
+
+            // The artifact leased by a scheduler is synced next time.
+            let lease = Lease::acquire(&format!("artifact:{}", art_id), "unit-test-other", 60_000, &mut redis_conn)?.unwrap();
+            assert_eq!(sync_leased(conn, &mut redis_conn, &executor, &queue, "unit-test-sync", art_id)?, None);
+            assert_eq!(poll(conn, &mut redis_conn, &executor, &queue, "unit-test-sync", art_id)?, None);
+            lease.release(&mut redis_conn)?;
+
+            InstanceDao::delete(&building.id, &building.art_id, &mut redis_conn)?;
+            InstanceDao::delete(&cleaning.id, &cleaning.art_id, &mut redis_conn)?;
+            InstanceDao::delete(&broken.id, &broken.art_id, &mut redis_conn)?;
+            Ok(())
+        }).unwrap();
     }
-    */
 }